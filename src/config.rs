@@ -0,0 +1,164 @@
+// Per-directory `.lineman.toml` override files, read with just enough TOML for `key = true` lines
+// Each file's options start from the command line and are overridden by every config from the
+// root down to the file's directory, so the nearest config wins
+
+use lineman::CleanOptions;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const CONFIG_FILE_NAME: &str = ".lineman.toml";
+
+#[derive(Debug, Default, PartialEq)]
+pub struct DirectoryConfig {
+    normalize_eof_newlines: Option<bool>,
+    preserve_quoted_trailing: Option<bool>,
+    preserve_python_strings: Option<bool>,
+    keep_last_line_trailing: Option<bool>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ConfigError(String);
+
+// Resolved options are kept per directory, so each config is read once however many files it covers
+pub struct ConfigCache {
+    root_paths: Vec<PathBuf>,
+    root_options: CleanOptions,
+    resolved_options: HashMap<PathBuf, CleanOptions>,
+}
+
+impl DirectoryConfig {
+    pub fn parse(config_string: &str) -> Result<DirectoryConfig, ConfigError> {
+        let mut config = DirectoryConfig::default();
+
+        for (line_index, line) in config_string.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+
+            if line.is_empty() {
+                continue;
+            }
+
+            let error =
+                |message: &str| ConfigError(format!("{} on line {}", message, line_index + 1));
+
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| error("Expected `key = value`"))?;
+
+            let value = match value.trim() {
+                "true" => true,
+                "false" => false,
+                _ => return Err(error("Expected `true` or `false`")),
+            };
+
+            let setting = match key.trim() {
+                "normalize_eof_newlines" => &mut config.normalize_eof_newlines,
+                "preserve_quoted_trailing" => &mut config.preserve_quoted_trailing,
+                "preserve_python_strings" => &mut config.preserve_python_strings,
+                "keep_last_line_trailing" => &mut config.keep_last_line_trailing,
+                _ => return Err(error("Unknown setting")),
+            };
+
+            *setting = Some(value);
+        }
+
+        Ok(config)
+    }
+
+    fn apply(&self, options: &mut CleanOptions) {
+        options.normalize_eof_newlines = self
+            .normalize_eof_newlines
+            .unwrap_or(options.normalize_eof_newlines);
+        options.preserve_quoted_trailing = self
+            .preserve_quoted_trailing
+            .unwrap_or(options.preserve_quoted_trailing);
+        options.preserve_python_strings = self
+            .preserve_python_strings
+            .unwrap_or(options.preserve_python_strings);
+        options.keep_last_line_trailing = self
+            .keep_last_line_trailing
+            .unwrap_or(options.keep_last_line_trailing);
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "{}", self.0)
+    }
+}
+
+impl ConfigCache {
+    pub fn new(root_paths: &[PathBuf], root_options: CleanOptions) -> ConfigCache {
+        ConfigCache {
+            root_paths: root_paths.to_vec(),
+            root_options,
+            resolved_options: HashMap::new(),
+        }
+    }
+
+    pub fn options_for(&mut self, directory: &Path) -> Result<CleanOptions, ConfigError> {
+        if let Some(options) = self.resolved_options.get(directory) {
+            return Ok(options.clone());
+        }
+
+        if !self
+            .root_paths
+            .iter()
+            .any(|root_path| directory.starts_with(root_path))
+        {
+            return Ok(self.root_options.clone());
+        }
+
+        let is_root = self
+            .root_paths
+            .iter()
+            .any(|root_path| directory == root_path);
+
+        let mut options = match directory.parent() {
+            Some(parent) if !is_root => self.options_for(parent)?,
+            _ => self.root_options.clone(),
+        };
+
+        let config_path = directory.join(CONFIG_FILE_NAME);
+
+        if config_path.is_file() {
+            let config_string = fs::read_to_string(&config_path).map_err(|error| {
+                ConfigError(format!(
+                    "{} could not be read: {}",
+                    config_path.display(),
+                    error
+                ))
+            })?;
+
+            DirectoryConfig::parse(&config_string)
+                .map_err(|error| ConfigError(format!("{}: {}", config_path.display(), error)))?
+                .apply(&mut options);
+        }
+
+        self.resolved_options
+            .insert(directory.to_path_buf(), options.clone());
+
+        Ok(options)
+    }
+}
+
+#[test]
+fn parse_reads_booleans_and_ignores_comments() {
+    let config = DirectoryConfig::parse(
+        "# Generated files only\nnormalize_eof_newlines = false\n\nkeep_last_line_trailing=true # sentinel\n",
+    )
+    .unwrap();
+
+    assert_eq!(
+        config,
+        DirectoryConfig {
+            normalize_eof_newlines: Some(false),
+            keep_last_line_trailing: Some(true),
+            ..DirectoryConfig::default()
+        }
+    );
+    assert!(DirectoryConfig::parse("normalize_eof_newlines = 1").is_err());
+    assert!(DirectoryConfig::parse("unknown_setting = true").is_err());
+    assert!(DirectoryConfig::parse("normalize_eof_newlines").is_err());
+}
@@ -0,0 +1,449 @@
+// A small line-based diff, used to show what cleaning would change in a file
+// The edit script is computed with the linear-space variant of Myers' algorithm, so memory stays
+// proportional to the file size even when every line of a large file is dirty
+
+use std::ops::Range;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Edit {
+    Equal(usize, usize),
+    Delete(usize),
+    Insert(usize),
+}
+
+// A run of old lines that was replaced by a run of new lines, either of which can be empty
+#[derive(Debug, PartialEq)]
+pub struct Change {
+    pub old_lines: Range<usize>,
+    pub new_lines: Range<usize>,
+}
+
+pub fn unified_diff<A: AsRef<str>, B: AsRef<str>>(
+    old_lines: &[A],
+    new_lines: &[B],
+    path: &str,
+    context: usize,
+) -> String {
+    let old_lines: Vec<&str> = old_lines.iter().map(|line| line.as_ref()).collect();
+    let new_lines: Vec<&str> = new_lines.iter().map(|line| line.as_ref()).collect();
+
+    let mut edits = Vec::new();
+    diff_range(&old_lines, &new_lines, 0, 0, &mut edits);
+
+    let mut output = String::new();
+
+    for hunk in group_into_hunks(&edits, context) {
+        if output.is_empty() {
+            output.push_str(&format!("--- {}\n+++ {}\n", path, path));
+        }
+
+        write_hunk(&mut output, &edits, hunk, &old_lines, &new_lines);
+    }
+
+    output
+}
+
+// The number of old lines that were modified or removed to produce the new ones
+pub fn changed_line_count<A: AsRef<str>, B: AsRef<str>>(old_lines: &[A], new_lines: &[B]) -> usize {
+    let old_lines: Vec<&str> = old_lines.iter().map(|line| line.as_ref()).collect();
+    let new_lines: Vec<&str> = new_lines.iter().map(|line| line.as_ref()).collect();
+
+    let mut edits = Vec::new();
+    diff_range(&old_lines, &new_lines, 0, 0, &mut edits);
+
+    edits
+        .iter()
+        .filter(|edit| matches!(edit, Edit::Delete(_)))
+        .count()
+}
+
+// Consecutive deletions and insertions are grouped, so a modified line is one change rather than a
+// deletion and an insertion
+pub fn changes<A: AsRef<str>, B: AsRef<str>>(old_lines: &[A], new_lines: &[B]) -> Vec<Change> {
+    let old_lines: Vec<&str> = old_lines.iter().map(|line| line.as_ref()).collect();
+    let new_lines: Vec<&str> = new_lines.iter().map(|line| line.as_ref()).collect();
+
+    let mut edits = Vec::new();
+    diff_range(&old_lines, &new_lines, 0, 0, &mut edits);
+
+    let mut changes: Vec<Change> = Vec::new();
+    let (mut old_index, mut new_index) = (0, 0);
+    let mut follows_change = false;
+
+    for edit in edits {
+        if !matches!(edit, Edit::Equal(..)) && !follows_change {
+            changes.push(Change {
+                old_lines: old_index..old_index,
+                new_lines: new_index..new_index,
+            });
+        }
+
+        follows_change = !matches!(edit, Edit::Equal(..));
+
+        match edit {
+            Edit::Equal(..) => {
+                old_index += 1;
+                new_index += 1;
+            }
+            Edit::Delete(_) => old_index += 1,
+            Edit::Insert(_) => new_index += 1,
+        }
+
+        if let Some(change) = changes.last_mut().filter(|_| follows_change) {
+            change.old_lines.end = old_index;
+            change.new_lines.end = new_index;
+        }
+    }
+
+    changes
+}
+
+fn diff_range(
+    old_lines: &[&str],
+    new_lines: &[&str],
+    old_offset: usize,
+    new_offset: usize,
+    edits: &mut Vec<Edit>,
+) {
+    let prefix_length = old_lines
+        .iter()
+        .zip(new_lines)
+        .take_while(|(old_line, new_line)| old_line == new_line)
+        .count();
+
+    let old_lines = &old_lines[prefix_length..];
+    let new_lines = &new_lines[prefix_length..];
+
+    let suffix_length = old_lines
+        .iter()
+        .rev()
+        .zip(new_lines.iter().rev())
+        .take_while(|(old_line, new_line)| old_line == new_line)
+        .count();
+
+    let old_lines = &old_lines[..old_lines.len() - suffix_length];
+    let new_lines = &new_lines[..new_lines.len() - suffix_length];
+
+    for index in 0..prefix_length {
+        edits.push(Edit::Equal(old_offset + index, new_offset + index));
+    }
+
+    let old_offset = old_offset + prefix_length;
+    let new_offset = new_offset + prefix_length;
+
+    if old_lines.is_empty() {
+        edits.extend((0..new_lines.len()).map(|index| Edit::Insert(new_offset + index)));
+    } else if new_lines.is_empty() {
+        edits.extend((0..old_lines.len()).map(|index| Edit::Delete(old_offset + index)));
+    } else {
+        let (x_start, y_start, x_end, y_end) = find_middle_snake(old_lines, new_lines);
+
+        diff_range(
+            &old_lines[..x_start],
+            &new_lines[..y_start],
+            old_offset,
+            new_offset,
+            edits,
+        );
+
+        for index in 0..(x_end - x_start) {
+            edits.push(Edit::Equal(
+                old_offset + x_start + index,
+                new_offset + y_start + index,
+            ));
+        }
+
+        diff_range(
+            &old_lines[x_end..],
+            &new_lines[y_end..],
+            old_offset + x_end,
+            new_offset + y_end,
+            edits,
+        );
+    }
+
+    let old_offset = old_offset + old_lines.len();
+    let new_offset = new_offset + new_lines.len();
+
+    for index in 0..suffix_length {
+        edits.push(Edit::Equal(old_offset + index, new_offset + index));
+    }
+}
+
+// Returns the start and end points of the snake that sits in the middle of a shortest edit path
+// The backward search runs over the reversed sequences, so its diagonals are mirrored around `delta`
+fn find_middle_snake(old_lines: &[&str], new_lines: &[&str]) -> (usize, usize, usize, usize) {
+    let n = old_lines.len() as isize;
+    let m = new_lines.len() as isize;
+    let delta = n - m;
+    let delta_is_odd = delta.rem_euclid(2) == 1;
+    let max_depth = (n + m + 1) / 2;
+    let offset = max_depth + 1;
+    let mut forward = vec![0isize; (2 * offset + 1) as usize];
+    let mut backward = vec![0isize; (2 * offset + 1) as usize];
+
+    let old_at = |x: isize| old_lines[x as usize];
+    let new_at = |y: isize| new_lines[y as usize];
+
+    for depth in 0..=max_depth {
+        for k in (-depth..=depth).step_by(2) {
+            let index = (k + offset) as usize;
+            let mut x = if k == -depth || (k != depth && forward[index - 1] < forward[index + 1]) {
+                forward[index + 1]
+            } else {
+                forward[index - 1] + 1
+            };
+            let mut y = x - k;
+            let (x_start, y_start) = (x, y);
+
+            while x < n && y < m && old_at(x) == new_at(y) {
+                x += 1;
+                y += 1;
+            }
+
+            forward[index] = x;
+
+            let mirrored_k = delta - k;
+
+            if delta_is_odd
+                && mirrored_k >= -(depth - 1)
+                && mirrored_k < depth
+                && x + backward[(mirrored_k + offset) as usize] >= n
+            {
+                return (x_start as usize, y_start as usize, x as usize, y as usize);
+            }
+        }
+
+        for k in (-depth..=depth).step_by(2) {
+            let index = (k + offset) as usize;
+            let mut x = if k == -depth || (k != depth && backward[index - 1] < backward[index + 1])
+            {
+                backward[index + 1]
+            } else {
+                backward[index - 1] + 1
+            };
+            let mut y = x - k;
+            let (x_start, y_start) = (x, y);
+
+            while x < n && y < m && old_at(n - x - 1) == new_at(m - y - 1) {
+                x += 1;
+                y += 1;
+            }
+
+            backward[index] = x;
+
+            let mirrored_k = delta - k;
+
+            if !delta_is_odd
+                && mirrored_k >= -depth
+                && mirrored_k <= depth
+                && x + forward[(mirrored_k + offset) as usize] >= n
+            {
+                return (
+                    (n - x) as usize,
+                    (m - y) as usize,
+                    (n - x_start) as usize,
+                    (m - y_start) as usize,
+                );
+            }
+        }
+    }
+
+    unreachable!("a shortest edit path always has a middle snake")
+}
+
+fn group_into_hunks(edits: &[Edit], context: usize) -> Vec<(usize, usize)> {
+    let change_indices: Vec<usize> = edits
+        .iter()
+        .enumerate()
+        .filter(|(_, edit)| !matches!(edit, Edit::Equal(..)))
+        .map(|(index, _)| index)
+        .collect();
+
+    let mut hunks = Vec::new();
+    let mut change_iter = change_indices.iter().peekable();
+
+    while let Some(&first_change) = change_iter.next() {
+        let mut last_change = first_change;
+
+        while let Some(&&next_change) = change_iter.peek() {
+            if next_change - last_change - 1 > 2 * context {
+                break;
+            }
+
+            last_change = next_change;
+            change_iter.next();
+        }
+
+        let start = first_change.saturating_sub(context);
+        let end = (last_change + context + 1).min(edits.len());
+        hunks.push((start, end));
+    }
+
+    hunks
+}
+
+fn write_hunk(
+    output: &mut String,
+    edits: &[Edit],
+    (start, end): (usize, usize),
+    old_lines: &[&str],
+    new_lines: &[&str],
+) {
+    let is_old_line = |edit: &&Edit| !matches!(edit, Edit::Insert(_));
+    let is_new_line = |edit: &&Edit| !matches!(edit, Edit::Delete(_));
+
+    let old_range = hunk_range(
+        edits[..start].iter().filter(is_old_line).count(),
+        edits[start..end].iter().filter(is_old_line).count(),
+    );
+    let new_range = hunk_range(
+        edits[..start].iter().filter(is_new_line).count(),
+        edits[start..end].iter().filter(is_new_line).count(),
+    );
+
+    output.push_str(&format!("@@ -{} +{} @@\n", old_range, new_range));
+
+    for edit in &edits[start..end] {
+        let (marker, line) = match *edit {
+            Edit::Equal(old_index, _) => (' ', old_lines[old_index]),
+            Edit::Delete(old_index) => ('-', old_lines[old_index]),
+            Edit::Insert(new_index) => ('+', new_lines[new_index]),
+        };
+
+        output.push(marker);
+        output.push_str(line);
+
+        if !line.ends_with('\n') {
+            output.push_str("\n\\ No newline at end of file\n");
+        }
+    }
+}
+
+// An empty side of a hunk is numbered by the line it follows, as `diff -u` does
+fn hunk_range(lines_before: usize, count: usize) -> String {
+    match count {
+        0 => format!("{},0", lines_before),
+        1 => format!("{}", lines_before + 1),
+        _ => format!("{},{}", lines_before + 1, count),
+    }
+}
+
+#[test]
+fn unified_diff_with_context_zero_and_three() {
+    let old_lines = [
+        "a\n", "b\n", "c\n", "d   \n", "e\n", "f\n", "g\n", "h\n", "i\n", "j\t\n", "k\n",
+    ];
+    let new_lines = [
+        "a\n", "b\n", "c\n", "d\n", "e\n", "f\n", "g\n", "h\n", "i\n", "j\n", "k\n",
+    ];
+
+    let expected_context_zero = concat!(
+        "--- file.txt\n",
+        "+++ file.txt\n",
+        "@@ -4 +4 @@\n",
+        "-d   \n",
+        "+d\n",
+        "@@ -10 +10 @@\n",
+        "-j\t\n",
+        "+j\n",
+    );
+
+    let expected_context_three = concat!(
+        "--- file.txt\n",
+        "+++ file.txt\n",
+        "@@ -1,11 +1,11 @@\n",
+        " a\n",
+        " b\n",
+        " c\n",
+        "-d   \n",
+        "+d\n",
+        " e\n",
+        " f\n",
+        " g\n",
+        " h\n",
+        " i\n",
+        "-j\t\n",
+        "+j\n",
+        " k\n",
+    );
+
+    assert_eq!(
+        unified_diff(&old_lines, &new_lines, "file.txt", 0),
+        expected_context_zero
+    );
+    assert_eq!(
+        unified_diff(&old_lines, &new_lines, "file.txt", 3),
+        expected_context_three
+    );
+}
+
+#[test]
+fn unified_diff_with_missing_and_removed_eof_newlines() {
+    let old_lines = ["a\n", "b\n", "c\n", "d"];
+    let new_lines = ["a\n", "b\n", "c\n", "d\n"];
+
+    let expected_output = concat!(
+        "--- file.txt\n",
+        "+++ file.txt\n",
+        "@@ -3,2 +3,2 @@\n",
+        " c\n",
+        "-d\n",
+        "\\ No newline at end of file\n",
+        "+d\n",
+    );
+
+    assert_eq!(
+        unified_diff(&old_lines, &new_lines, "file.txt", 1),
+        expected_output
+    );
+
+    let old_lines = ["a\n", "b\n", "\n", "\n"];
+    let new_lines = ["a\n", "b\n"];
+
+    let expected_output = concat!(
+        "--- file.txt\n",
+        "+++ file.txt\n",
+        "@@ -2,3 +2 @@\n",
+        " b\n",
+        "-\n",
+        "-\n",
+    );
+
+    assert_eq!(
+        unified_diff(&old_lines, &new_lines, "file.txt", 1),
+        expected_output
+    );
+}
+
+#[test]
+fn unified_diff_of_identical_lines_is_empty() {
+    let lines = ["a\n", "b\n"];
+
+    assert_eq!(unified_diff(&lines, &lines, "file.txt", 3), "");
+}
+
+#[test]
+fn changes_group_modified_and_removed_lines() {
+    let old_lines = ["a  \n", "b\n", "x\n", "y\n", "c\n", "d"];
+    let new_lines = ["a\n", "b\n", "c\n", "d\n"];
+
+    assert_eq!(
+        changes(&old_lines, &new_lines),
+        vec![
+            Change {
+                old_lines: 0..1,
+                new_lines: 0..1,
+            },
+            Change {
+                old_lines: 2..4,
+                new_lines: 2..2,
+            },
+            Change {
+                old_lines: 5..6,
+                new_lines: 3..4,
+            },
+        ]
+    );
+    assert!(changes(&new_lines, &new_lines).is_empty());
+}
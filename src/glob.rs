@@ -0,0 +1,474 @@
+// Shell-style glob patterns, matched against paths relative to the root being processed
+// `*` and `?` never cross a `/`, `**` does, and `[...]` matches a single character from a set
+// A pattern without a `/` is matched against the file name alone, so `*.rs` finds files at any depth
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::{Component, Path};
+use std::str::FromStr;
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Literal(char),
+    AnyCharacter,
+    AnySequence,
+    AnyPath,
+    AnyDirectories,
+    Class {
+        negated: bool,
+        ranges: Vec<(char, char)>,
+    },
+}
+
+#[derive(Clone)]
+pub struct Glob {
+    pattern: String,
+    tokens: Vec<Token>,
+    match_file_name_only: bool,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct GlobError(String);
+
+// Patterns that are a literal name or path, or a `*` followed by a literal suffix such as `*.rs`,
+// are looked up in tables, so only the rest have to be matched one by one
+#[derive(Debug, Default)]
+pub struct GlobSet {
+    literal_file_names: HashSet<String>,
+    literal_paths: HashSet<String>,
+    // Keyed by the text after the suffix's last `.`, which is what a file name is looked up by
+    suffixes_by_extension: HashMap<String, Vec<String>>,
+    other_globs: Vec<Glob>,
+}
+
+impl Glob {
+    pub fn new(pattern: &str) -> Result<Glob, GlobError> {
+        let characters: Vec<char> = pattern.trim_start_matches("./").chars().collect();
+        let mut tokens = Vec::new();
+        let mut index = 0;
+
+        while index < characters.len() {
+            match characters[index] {
+                '*' if characters.get(index + 1) == Some(&'*') => {
+                    if characters.get(index + 2) == Some(&'/') {
+                        tokens.push(Token::AnyDirectories);
+                        index += 3;
+                    } else {
+                        tokens.push(Token::AnyPath);
+                        index += 2;
+                    }
+                }
+                '*' => {
+                    tokens.push(Token::AnySequence);
+                    index += 1;
+                }
+                '?' => {
+                    tokens.push(Token::AnyCharacter);
+                    index += 1;
+                }
+                '[' => {
+                    let (token, next_index) = parse_class(&characters, index, pattern)?;
+                    tokens.push(token);
+                    index = next_index;
+                }
+                '\\' if index + 1 < characters.len() => {
+                    tokens.push(Token::Literal(characters[index + 1]));
+                    index += 2;
+                }
+                character => {
+                    tokens.push(Token::Literal(character));
+                    index += 1;
+                }
+            }
+        }
+
+        Ok(Glob {
+            pattern: pattern.to_string(),
+            match_file_name_only: !characters.contains(&'/'),
+            tokens,
+        })
+    }
+
+    // Matched against the whole relative path even without a `/`, as `.gitignore` does with a
+    // pattern that starts with one
+    pub fn anchored(pattern: &str) -> Result<Glob, GlobError> {
+        let mut glob = Glob::new(pattern)?;
+        glob.match_file_name_only = false;
+
+        Ok(glob)
+    }
+
+    pub fn is_match(&self, relative_path: &Path) -> bool {
+        let path_string = if self.match_file_name_only {
+            relative_path
+                .file_name()
+                .map(|file_name| file_name.to_string_lossy().to_string())
+                .unwrap_or_default()
+        } else {
+            path_to_glob_string(relative_path)
+        };
+
+        let characters: Vec<char> = path_string.chars().collect();
+
+        matches_from(&self.tokens, &characters)
+    }
+}
+
+impl GlobSet {
+    pub fn new(globs: &[Glob]) -> GlobSet {
+        let mut glob_set = GlobSet::default();
+
+        for glob in globs {
+            let literal_suffix = match glob.tokens.split_first() {
+                Some((Token::AnySequence, suffix_tokens)) if glob.match_file_name_only => {
+                    literal_text(suffix_tokens)
+                }
+                _ => None,
+            };
+
+            if let Some(literal) = literal_text(&glob.tokens) {
+                if glob.match_file_name_only {
+                    glob_set.literal_file_names.insert(literal);
+                } else {
+                    glob_set.literal_paths.insert(literal);
+                }
+            } else if let Some((_, extension)) = literal_suffix
+                .as_ref()
+                .and_then(|literal_suffix| literal_suffix.rsplit_once('.'))
+            {
+                glob_set
+                    .suffixes_by_extension
+                    .entry(extension.to_string())
+                    .or_default()
+                    .extend(literal_suffix.clone());
+            } else {
+                glob_set.other_globs.push(glob.clone());
+            }
+        }
+
+        glob_set
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.literal_file_names.is_empty()
+            && self.literal_paths.is_empty()
+            && self.suffixes_by_extension.is_empty()
+            && self.other_globs.is_empty()
+    }
+
+    pub fn is_match(&self, relative_path: &Path) -> bool {
+        let file_name = relative_path
+            .file_name()
+            .map(|file_name| file_name.to_string_lossy())
+            .unwrap_or_default();
+
+        let matches_suffix = || {
+            file_name
+                .rsplit_once('.')
+                .and_then(|(_, extension)| self.suffixes_by_extension.get(extension))
+                .is_some_and(|suffixes| suffixes.iter().any(|suffix| file_name.ends_with(suffix)))
+        };
+
+        self.literal_file_names.contains(file_name.as_ref())
+            || matches_suffix()
+            || (!self.literal_paths.is_empty()
+                && self
+                    .literal_paths
+                    .contains(&path_to_glob_string(relative_path)))
+            || self
+                .other_globs
+                .iter()
+                .any(|glob| glob.is_match(relative_path))
+    }
+}
+
+impl fmt::Debug for Glob {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "{:?}", self.pattern)
+    }
+}
+
+impl fmt::Display for GlobError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "{}", self.0)
+    }
+}
+
+impl FromStr for Glob {
+    type Err = GlobError;
+
+    fn from_str(pattern: &str) -> Result<Glob, GlobError> {
+        Glob::new(pattern)
+    }
+}
+
+// Joins the normal components with `/` so patterns behave the same on every platform
+pub fn path_to_glob_string(path: &Path) -> String {
+    path.components()
+        .filter_map(|component| match component {
+            Component::Normal(part) => Some(part.to_string_lossy().to_string()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn literal_text(tokens: &[Token]) -> Option<String> {
+    tokens
+        .iter()
+        .map(|token| match token {
+            Token::Literal(character) => Some(*character),
+            _ => None,
+        })
+        .collect()
+}
+
+fn parse_class(
+    characters: &[char],
+    start: usize,
+    pattern: &str,
+) -> Result<(Token, usize), GlobError> {
+    let mut index = start + 1;
+    let negated = matches!(characters.get(index), Some('!') | Some('^'));
+
+    if negated {
+        index += 1;
+    }
+
+    let mut ranges = Vec::new();
+    let class_start = index;
+
+    loop {
+        match characters.get(index) {
+            None => {
+                return Err(GlobError(format!(
+                    "Unclosed character class in glob: {}",
+                    pattern
+                )))
+            }
+            Some(']') if index > class_start => {
+                return Ok((Token::Class { negated, ranges }, index + 1))
+            }
+            Some(&low) => {
+                if characters.get(index + 1) == Some(&'-')
+                    && characters.get(index + 2).is_some_and(|high| *high != ']')
+                {
+                    ranges.push((low, characters[index + 2]));
+                    index += 3;
+                } else {
+                    ranges.push((low, low));
+                    index += 1;
+                }
+            }
+        }
+    }
+}
+
+fn matches_from(tokens: &[Token], characters: &[char]) -> bool {
+    let mut memo = vec![None; (tokens.len() + 1) * (characters.len() + 1)];
+
+    matches_at(tokens, characters, 0, 0, &mut memo)
+}
+
+// Each token and character position is only matched once, as retrying them for every way the
+// wildcards before could be split takes exponential time on patterns like `*a*a*a*b`
+fn matches_at(
+    tokens: &[Token],
+    characters: &[char],
+    token_index: usize,
+    character_index: usize,
+    memo: &mut [Option<bool>],
+) -> bool {
+    let memo_index = token_index * (characters.len() + 1) + character_index;
+
+    if let Some(is_match) = memo[memo_index] {
+        return is_match;
+    }
+
+    let rest = &characters[character_index..];
+    let matches_after = |length: usize, memo: &mut [Option<bool>]| {
+        matches_at(
+            tokens,
+            characters,
+            token_index + 1,
+            character_index + length,
+            memo,
+        )
+    };
+
+    let is_match = match tokens.get(token_index) {
+        None => rest.is_empty(),
+        Some(Token::Literal(literal)) => rest.first() == Some(literal) && matches_after(1, memo),
+        Some(Token::AnyCharacter) => {
+            rest.first().is_some_and(|character| *character != '/') && matches_after(1, memo)
+        }
+        Some(Token::Class { negated, ranges }) => {
+            rest.first().is_some_and(|character| {
+                let in_class = ranges
+                    .iter()
+                    .any(|(low, high)| low <= character && character <= high);
+
+                *character != '/' && in_class != *negated
+            }) && matches_after(1, memo)
+        }
+        Some(Token::AnySequence) => (0..=rest.len())
+            .take_while(|&length| length == 0 || rest[length - 1] != '/')
+            .any(|length| matches_after(length, memo)),
+        Some(Token::AnyPath) => (0..=rest.len()).any(|length| matches_after(length, memo)),
+        Some(Token::AnyDirectories) => {
+            matches_after(0, memo)
+                || (1..=rest.len())
+                    .filter(|&length| rest[length - 1] == '/')
+                    .any(|length| matches_after(length, memo))
+        }
+    };
+
+    memo[memo_index] = Some(is_match);
+    is_match
+}
+
+#[test]
+fn glob_matches_file_names_at_any_depth() {
+    let glob = Glob::new("*.rs").unwrap();
+
+    assert!(glob.is_match(Path::new("main.rs")));
+    assert!(glob.is_match(Path::new("src/nested/lib.rs")));
+    assert!(!glob.is_match(Path::new("src/main.py")));
+}
+
+#[test]
+fn glob_with_separator_matches_relative_path() {
+    let glob = Glob::new("src/*.rs").unwrap();
+
+    assert!(glob.is_match(Path::new("src/main.rs")));
+    assert!(!glob.is_match(Path::new("src/nested/lib.rs")));
+    assert!(!glob.is_match(Path::new("main.rs")));
+
+    let glob = Glob::new("src/**/*.rs").unwrap();
+
+    assert!(glob.is_match(Path::new("src/main.rs")));
+    assert!(glob.is_match(Path::new("src/nested/deeper/lib.rs")));
+    assert!(!glob.is_match(Path::new("tests/main.rs")));
+}
+
+#[test]
+fn glob_character_classes_and_wildcards() {
+    let glob = Glob::new("file[0-9]?.[!p]*").unwrap();
+
+    assert!(glob.is_match(Path::new("file1a.txt")));
+    assert!(!glob.is_match(Path::new("fileXa.txt")));
+    assert!(!glob.is_match(Path::new("file1a.py")));
+    assert!(Glob::new("file[0-9").is_err());
+}
+
+#[test]
+fn glob_with_many_wildcards_fails_quickly_on_long_names() {
+    let glob = Glob::new("*a*a*a*a*a*a*a*a*b").unwrap();
+    let name = "a".repeat(200);
+
+    assert!(!glob.is_match(Path::new(&name)));
+    assert!(glob.is_match(Path::new(&format!("{}b", name))));
+    assert!(Glob::new("src/**/a*a*b")
+        .unwrap()
+        .is_match(Path::new("src/x/y/aaab")));
+}
+
+#[test]
+fn glob_set_matches_what_its_globs_match() {
+    let patterns = [
+        "*.rs",
+        "*.tar.gz",
+        "Makefile",
+        "src/main.py",
+        "docs/**/*.md",
+        "*.",
+        "test_?.txt",
+    ];
+    let globs: Vec<Glob> = patterns
+        .iter()
+        .map(|pattern| Glob::new(pattern).unwrap())
+        .collect();
+    let glob_set = GlobSet::new(&globs);
+
+    for path in [
+        "main.rs",
+        "src/nested/lib.rs",
+        "archive.tar.gz",
+        "archive.gz",
+        ".rs",
+        "build/Makefile",
+        "src/main.py",
+        "nested/src/main.py",
+        "docs/guide/intro.md",
+        "intro.md",
+        "trailing.",
+        "test_1.txt",
+        "test_10.txt",
+    ] {
+        let path = Path::new(path);
+
+        assert_eq!(
+            glob_set.is_match(path),
+            globs.iter().any(|glob| glob.is_match(path)),
+            "{}",
+            path.display()
+        );
+    }
+
+    assert!(GlobSet::new(&[]).is_empty());
+    assert!(!glob_set.is_empty());
+}
+
+// A benchmark rather than a test, run with `cargo test --release glob_set -- --ignored --nocapture`
+#[test]
+#[ignore]
+fn glob_set_outpaces_compiling_globs_for_each_file() {
+    use std::path::PathBuf;
+    use std::time::Instant;
+
+    let patterns: Vec<String> = (0..200)
+        .map(|index| format!("*.ext{}", index))
+        .chain(["src/**/generated/*.rs".to_string(), "Makefile".to_string()])
+        .collect();
+    let paths: Vec<PathBuf> = (0..20_000)
+        .map(|index| {
+            PathBuf::from(format!(
+                "src/module{}/file{}.ext{}",
+                index % 50,
+                index,
+                index % 400
+            ))
+        })
+        .collect();
+
+    let start = Instant::now();
+    let naive_match_count = paths
+        .iter()
+        .filter(|path| {
+            patterns
+                .iter()
+                .any(|pattern| Glob::new(pattern).unwrap().is_match(path))
+        })
+        .count();
+    let naive_duration = start.elapsed();
+
+    let start = Instant::now();
+    let globs: Vec<Glob> = patterns
+        .iter()
+        .map(|pattern| Glob::new(pattern).unwrap())
+        .collect();
+    let glob_set = GlobSet::new(&globs);
+    let cached_match_count = paths.iter().filter(|path| glob_set.is_match(path)).count();
+    let cached_duration = start.elapsed();
+
+    println!(
+        "{} paths against {} globs: {:?} compiling per file, {:?} with a glob set",
+        paths.len(),
+        patterns.len(),
+        naive_duration,
+        cached_duration
+    );
+
+    assert_eq!(naive_match_count, cached_match_count);
+    assert!(cached_duration < naive_duration);
+}
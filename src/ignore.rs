@@ -0,0 +1,182 @@
+// `.gitignore` and `.ignore` files, read from the root down as the tree is walked, along with the
+// global git excludes and the repository's `.git/info/exclude`
+// Later rules override earlier ones, a directory's rules override its parents', and `.ignore`
+// overrides `.gitignore`, so the last rule that matches a path decides whether it's ignored
+
+use crate::glob::Glob;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const IGNORE_FILE_NAMES: [&str; 2] = [".gitignore", ".ignore"];
+
+#[derive(Debug)]
+struct IgnoreRule {
+    glob: Glob,
+    negated: bool,
+    directory_only: bool,
+}
+
+// Rules are kept per directory, so each ignore file is read once however many entries it covers
+pub struct IgnoreCache {
+    root_path: PathBuf,
+    root_rules: Vec<IgnoreRule>,
+    directory_rules: HashMap<PathBuf, Vec<IgnoreRule>>,
+}
+
+impl IgnoreRule {
+    fn parse(line: &str) -> Option<IgnoreRule> {
+        let line = line.trim_end();
+
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+
+        let (negated, pattern) = match line.strip_prefix('!') {
+            Some(pattern) => (true, pattern),
+            None => (false, line),
+        };
+
+        // A leading `\` escapes a `#` or `!` that is part of the name
+        let pattern = pattern.strip_prefix('\\').unwrap_or(pattern);
+        let directory_only = pattern.ends_with('/');
+        let pattern = pattern.trim_end_matches('/');
+
+        // A `/` anywhere but the end ties the pattern to the ignore file's directory
+        let glob = match pattern.strip_prefix('/') {
+            Some(anchored_pattern) => Glob::anchored(anchored_pattern),
+            None => Glob::new(pattern),
+        }
+        .ok()?;
+
+        Some(IgnoreRule {
+            glob,
+            negated,
+            directory_only,
+        })
+    }
+}
+
+impl IgnoreCache {
+    pub fn new(root_path: &Path) -> IgnoreCache {
+        let root_rules = global_excludes_path()
+            .into_iter()
+            .chain([root_path.join(".git/info/exclude")])
+            .flat_map(|rules_path| read_rules(&rules_path))
+            .collect();
+
+        IgnoreCache {
+            root_path: root_path.to_path_buf(),
+            root_rules,
+            directory_rules: HashMap::new(),
+        }
+    }
+
+    // Git never tracks what's inside `.git`, so it is always ignored
+    pub fn is_ignored(&mut self, path: &Path, is_directory: bool) -> bool {
+        let Ok(relative_path) = path.strip_prefix(&self.root_path) else {
+            return false;
+        };
+
+        if relative_path.as_os_str().is_empty() {
+            return false;
+        }
+
+        if is_directory
+            && path
+                .file_name()
+                .is_some_and(|file_name| file_name == ".git")
+        {
+            return true;
+        }
+
+        let mut ignored = last_match(&self.root_rules, relative_path, is_directory);
+
+        let mut directories: Vec<&Path> = path
+            .ancestors()
+            .skip(1)
+            .take_while(|directory| directory.starts_with(&self.root_path))
+            .collect();
+        directories.reverse();
+
+        for directory in directories {
+            let rules = self
+                .directory_rules
+                .entry(directory.to_path_buf())
+                .or_insert_with(|| {
+                    IGNORE_FILE_NAMES
+                        .iter()
+                        .flat_map(|file_name| read_rules(&directory.join(file_name)))
+                        .collect()
+                });
+
+            let relative_path = path.strip_prefix(directory).unwrap_or(path);
+            ignored = last_match(rules, relative_path, is_directory).or(ignored);
+        }
+
+        ignored.unwrap_or(false)
+    }
+}
+
+// An ignore file that is missing or unreadable has no rules
+fn read_rules(rules_path: &Path) -> Vec<IgnoreRule> {
+    fs::read_to_string(rules_path)
+        .map(|rules_string| rules_string.lines().filter_map(IgnoreRule::parse).collect())
+        .unwrap_or_default()
+}
+
+// Whether the last rule that matches ignores the path, or `None` when no rule matches it
+fn last_match(rules: &[IgnoreRule], relative_path: &Path, is_directory: bool) -> Option<bool> {
+    rules
+        .iter()
+        .rev()
+        .find(|rule| (is_directory || !rule.directory_only) && rule.glob.is_match(relative_path))
+        .map(|rule| !rule.negated)
+}
+
+// `core.excludesFile` when it's set, and git's default location otherwise
+fn global_excludes_path() -> Option<PathBuf> {
+    let configured_path = Command::new("git")
+        .args(["config", "--path", "--get", "core.excludesFile"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .filter(|configured_path| !configured_path.is_empty());
+
+    if let Some(configured_path) = configured_path {
+        return Some(PathBuf::from(configured_path));
+    }
+
+    env::var_os("XDG_CONFIG_HOME")
+        .filter(|config_home| !config_home.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+        .map(|config_home| config_home.join("git/ignore"))
+}
+
+#[test]
+fn rules_follow_gitignore_semantics() {
+    let rules: Vec<IgnoreRule> =
+        "# Build output\ntarget/\n*.log\n!keep.log\n/local.txt\ndocs/*.md\n"
+            .lines()
+            .filter_map(IgnoreRule::parse)
+            .collect();
+
+    let is_ignored = |relative_path: &str, is_directory: bool| {
+        last_match(&rules, Path::new(relative_path), is_directory).unwrap_or(false)
+    };
+
+    assert!(is_ignored("target", true));
+    assert!(is_ignored("nested/target", true));
+    assert!(!is_ignored("target", false));
+    assert!(is_ignored("nested/debug.log", false));
+    assert!(!is_ignored("nested/keep.log", false));
+    assert!(is_ignored("local.txt", false));
+    assert!(!is_ignored("nested/local.txt", false));
+    assert!(is_ignored("docs/readme.md", false));
+    assert!(!is_ignored("docs/nested/readme.md", false));
+    assert!(!is_ignored("src/main.rs", false));
+}
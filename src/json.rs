@@ -0,0 +1,381 @@
+// Just enough JSON to write lineman's machine-readable reports and read small JSON inputs back
+
+use std::fmt;
+
+// Arrays and objects are parsed recursively, so deeper input is rejected before it can overflow the
+// stack
+const MAX_DEPTH: usize = 128;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct JsonError(String);
+
+impl Value {
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(members) => members
+                .iter()
+                .find(|(member_key, _)| member_key == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+}
+
+// Whole numbers are written without a fraction, as every count lineman reports is one
+impl fmt::Display for Value {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Null => write!(formatter, "null"),
+            Value::Bool(boolean) => write!(formatter, "{}", boolean),
+            Value::Number(number) if number.fract() == 0.0 && number.abs() < 1e15 => {
+                write!(formatter, "{}", *number as i64)
+            }
+            Value::Number(number) => write!(formatter, "{}", number),
+            Value::String(string) => write!(formatter, "{}", quote(string)),
+            Value::Array(values) => {
+                write!(formatter, "[")?;
+
+                for (index, value) in values.iter().enumerate() {
+                    if index > 0 {
+                        write!(formatter, ",")?;
+                    }
+
+                    write!(formatter, "{}", value)?;
+                }
+
+                write!(formatter, "]")
+            }
+            Value::Object(members) => {
+                write!(formatter, "{{")?;
+
+                for (index, (key, value)) in members.iter().enumerate() {
+                    if index > 0 {
+                        write!(formatter, ",")?;
+                    }
+
+                    write!(formatter, "{}:{}", quote(key), value)?;
+                }
+
+                write!(formatter, "}}")
+            }
+        }
+    }
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "{}", self.0)
+    }
+}
+
+pub fn quote(string: &str) -> String {
+    let mut quoted = String::with_capacity(string.len() + 2);
+    quoted.push('"');
+
+    for character in string.chars() {
+        match character {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            character if (character as u32) < 0x20 => {
+                quoted.push_str(&format!("\\u{:04x}", character as u32))
+            }
+            character => quoted.push(character),
+        }
+    }
+
+    quoted.push('"');
+    quoted
+}
+
+pub fn parse(input: &str) -> Result<Value, JsonError> {
+    let mut parser = Parser {
+        characters: input.chars().collect(),
+        index: 0,
+        depth: 0,
+    };
+
+    let value = parser.parse_value()?;
+    parser.skip_whitespace();
+
+    if parser.index < parser.characters.len() {
+        return Err(parser.error("Unexpected trailing characters"));
+    }
+
+    Ok(value)
+}
+
+struct Parser {
+    characters: Vec<char>,
+    index: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn error(&self, message: &str) -> JsonError {
+        JsonError(format!("{} at character {}", message, self.index))
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.characters.get(self.index).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self
+            .peek()
+            .is_some_and(|character| character.is_whitespace())
+        {
+            self.index += 1;
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), JsonError> {
+        if self.peek() == Some(expected) {
+            self.index += 1;
+            Ok(())
+        } else {
+            Err(self.error(&format!("Expected '{}'", expected)))
+        }
+    }
+
+    fn parse_value(&mut self) -> Result<Value, JsonError> {
+        self.skip_whitespace();
+
+        match self.peek() {
+            Some('{' | '[') if self.depth == MAX_DEPTH => Err(self.error("Nesting is too deep")),
+            Some('{') => self.nested(Parser::parse_object),
+            Some('[') => self.nested(Parser::parse_array),
+            Some('"') => self.parse_string().map(Value::String),
+            Some('t') => self.parse_literal("true", Value::Bool(true)),
+            Some('f') => self.parse_literal("false", Value::Bool(false)),
+            Some('n') => self.parse_literal("null", Value::Null),
+            Some(character) if character == '-' || character.is_ascii_digit() => {
+                self.parse_number()
+            }
+            _ => Err(self.error("Expected a value")),
+        }
+    }
+
+    fn nested(
+        &mut self,
+        parse: fn(&mut Parser) -> Result<Value, JsonError>,
+    ) -> Result<Value, JsonError> {
+        self.depth += 1;
+        let value = parse(self);
+        self.depth -= 1;
+        value
+    }
+
+    fn parse_literal(&mut self, literal: &str, value: Value) -> Result<Value, JsonError> {
+        let literal_characters: Vec<char> = literal.chars().collect();
+
+        if self.characters[self.index..].starts_with(&literal_characters) {
+            self.index += literal_characters.len();
+            Ok(value)
+        } else {
+            Err(self.error("Invalid literal"))
+        }
+    }
+
+    fn parse_number(&mut self) -> Result<Value, JsonError> {
+        let start = self.index;
+
+        while self.peek().is_some_and(|character| {
+            character.is_ascii_digit() || matches!(character, '-' | '+' | '.' | 'e' | 'E')
+        }) {
+            self.index += 1;
+        }
+
+        let number_string: String = self.characters[start..self.index].iter().collect();
+
+        number_string
+            .parse()
+            .map(Value::Number)
+            .map_err(|_| self.error("Invalid number"))
+    }
+
+    fn parse_string(&mut self) -> Result<String, JsonError> {
+        self.expect('"')?;
+        let mut string = String::new();
+
+        loop {
+            match self.peek() {
+                None => return Err(self.error("Unterminated string")),
+                Some('"') => {
+                    self.index += 1;
+                    return Ok(string);
+                }
+                Some('\\') => {
+                    self.index += 1;
+                    let escaped = self
+                        .peek()
+                        .ok_or_else(|| self.error("Unterminated string"))?;
+                    self.index += 1;
+
+                    match escaped {
+                        '"' | '\\' | '/' => string.push(escaped),
+                        'b' => string.push('\u{8}'),
+                        'f' => string.push('\u{c}'),
+                        'n' => string.push('\n'),
+                        'r' => string.push('\r'),
+                        't' => string.push('\t'),
+                        'u' => string.push(self.parse_unicode_escape()?),
+                        _ => return Err(self.error("Invalid escape")),
+                    }
+                }
+                Some(character) => {
+                    string.push(character);
+                    self.index += 1;
+                }
+            }
+        }
+    }
+
+    // Surrogate pairs arrive as two consecutive `\u` escapes and are combined into one character
+    fn parse_unicode_escape(&mut self) -> Result<char, JsonError> {
+        let high = self.parse_hex_digits()?;
+
+        let code_point = if (0xd800..0xdc00).contains(&high) {
+            if !self.characters[self.index..].starts_with(&['\\', 'u']) {
+                return Err(self.error("Unpaired surrogate"));
+            }
+
+            self.index += 2;
+            let low = self.parse_hex_digits()?;
+
+            if !(0xdc00..0xe000).contains(&low) {
+                return Err(self.error("Invalid surrogate pair"));
+            }
+
+            0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00)
+        } else {
+            high
+        };
+
+        char::from_u32(code_point).ok_or_else(|| self.error("Invalid unicode escape"))
+    }
+
+    fn parse_hex_digits(&mut self) -> Result<u32, JsonError> {
+        let hex_string: String = self
+            .characters
+            .get(self.index..self.index + 4)
+            .ok_or_else(|| self.error("Invalid unicode escape"))?
+            .iter()
+            .collect();
+
+        self.index += 4;
+
+        u32::from_str_radix(&hex_string, 16).map_err(|_| self.error("Invalid unicode escape"))
+    }
+
+    fn parse_array(&mut self) -> Result<Value, JsonError> {
+        self.expect('[')?;
+        let mut values = Vec::new();
+        self.skip_whitespace();
+
+        if self.peek() == Some(']') {
+            self.index += 1;
+            return Ok(Value::Array(values));
+        }
+
+        loop {
+            values.push(self.parse_value()?);
+            self.skip_whitespace();
+
+            match self.peek() {
+                Some(',') => self.index += 1,
+                Some(']') => {
+                    self.index += 1;
+                    return Ok(Value::Array(values));
+                }
+                _ => return Err(self.error("Expected ',' or ']'")),
+            }
+        }
+    }
+
+    fn parse_object(&mut self) -> Result<Value, JsonError> {
+        self.expect('{')?;
+        let mut members = Vec::new();
+        self.skip_whitespace();
+
+        if self.peek() == Some('}') {
+            self.index += 1;
+            return Ok(Value::Object(members));
+        }
+
+        loop {
+            self.skip_whitespace();
+            let key = self.parse_string()?;
+            self.skip_whitespace();
+            self.expect(':')?;
+            members.push((key, self.parse_value()?));
+            self.skip_whitespace();
+
+            match self.peek() {
+                Some(',') => self.index += 1,
+                Some('}') => {
+                    self.index += 1;
+                    return Ok(Value::Object(members));
+                }
+                _ => return Err(self.error("Expected ',' or '}'")),
+            }
+        }
+    }
+}
+
+#[test]
+fn quoted_strings_parse_back_to_the_original() {
+    let original = "path/with \"quotes\", a \\ backslash,\ta tab and a \u{1} control";
+
+    assert_eq!(
+        parse(&quote(original)),
+        Ok(Value::String(original.to_string()))
+    );
+}
+
+#[test]
+fn values_are_written_back_compactly() {
+    let input = r#"{"paths":["a.rs","b\"é.rs"],"count":2,"ratio":0.5,"ok":true,"none":null}"#;
+
+    assert_eq!(parse(input).unwrap().to_string(), input);
+}
+
+#[test]
+fn parse_nested_values() {
+    let value =
+        parse(r#" {"paths": ["a.rs", "bé.rs"], "count": 2, "ok": true, "none": null} "#).unwrap();
+
+    assert_eq!(
+        value.get("paths"),
+        Some(&Value::Array(vec![
+            Value::String("a.rs".to_string()),
+            Value::String("bé.rs".to_string()),
+        ]))
+    );
+    assert_eq!(value.get("count"), Some(&Value::Number(2.0)));
+    assert_eq!(value.get("ok"), Some(&Value::Bool(true)));
+    assert_eq!(value.get("none"), Some(&Value::Null));
+    assert!(parse("[1, 2").is_err());
+    assert!(parse("{} trailing").is_err());
+}
+
+#[test]
+fn deeply_nested_input_is_an_error_instead_of_a_stack_overflow() {
+    let nested = |depth: usize| format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+
+    assert!(parse(&nested(MAX_DEPTH)).is_ok());
+    assert!(parse(&nested(MAX_DEPTH + 1)).is_err());
+    assert!(parse(&"[{\"a\":".repeat(200_000)).is_err());
+}
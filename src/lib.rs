@@ -0,0 +1,2584 @@
+// Trims trailing whitespace and normalizes the ends of files. The `lineman` binary is a thin
+// command line interface over `clean_str`, `clean_file` and the cleaning below
+
+mod diff;
+
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, prelude::*, BufReader, BufWriter};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// How text is cleaned. The default trims trailing whitespace from every line and ends the text
+/// with exactly one newline
+#[derive(Clone, Debug)]
+pub struct CleanOptions {
+    /// Ends the text with a single newline, removing the blank lines before it
+    pub normalize_eof_newlines: bool,
+    /// Keeps the trailing whitespace of lines that end inside a quoted string
+    pub preserve_quoted_trailing: bool,
+    /// Keeps the trailing whitespace of lines inside Python triple-quoted strings
+    pub preserve_python_strings: bool,
+    /// Keeps the trailing whitespace of the last line
+    pub keep_last_line_trailing: bool,
+    /// Keeps the trailing whitespace of comments with these prefixes that are aligned with others
+    pub aligned_comment_prefixes: Vec<String>,
+    /// Trims trailing whitespace, without which only the end of the text is normalized
+    pub trim_trailing_whitespace: bool,
+    /// Leaves a last line without a newline alone when its trailing whitespace was trimmed
+    pub minimal_eof: bool,
+    /// Only trims the trailing whitespace of comments with these prefixes, leaving every other line
+    /// and the end of the text as they are
+    pub comments_only_prefixes: Option<Vec<String>>,
+    /// Keeps a single trailing blank line, when there is one
+    pub keep_one_trailing_blank_line: bool,
+    /// Ends every line with this line ending
+    pub line_ending: Option<LineEnding>,
+    /// Collapses longer runs of blank lines down to this many
+    pub max_consecutive_blank_lines: Option<usize>,
+    /// The order the transforms run in, which decides what counts as a blank line
+    pub transform_order: TransformOrder,
+    /// Only adds a final newline to text with at least this many lines
+    pub final_newline_min_lines: usize,
+    /// Only trims trailing whitespace from lines with more than this many trailing characters
+    pub min_trailing: usize,
+    /// Converts the leading indentation of each line
+    pub reindentation: Option<Reindentation>,
+    /// Removes a leading byte order mark from files instead of keeping it
+    pub strip_bom: bool,
+}
+
+impl Default for CleanOptions {
+    fn default() -> Self {
+        CleanOptions {
+            normalize_eof_newlines: true,
+            preserve_quoted_trailing: false,
+            preserve_python_strings: false,
+            keep_last_line_trailing: false,
+            aligned_comment_prefixes: Vec::new(),
+            trim_trailing_whitespace: true,
+            minimal_eof: false,
+            comments_only_prefixes: None,
+            keep_one_trailing_blank_line: false,
+            line_ending: None,
+            max_consecutive_blank_lines: None,
+            transform_order: TransformOrder::default(),
+            final_newline_min_lines: 0,
+            min_trailing: 0,
+            reindentation: None,
+            strip_bom: false,
+        }
+    }
+}
+
+/// Why a file couldn't be cleaned
+#[derive(Debug)]
+pub enum LinemanFileError {
+    FileNotOpened,
+    FileNotCleaned,
+    CleaningNotVerified,
+    FileOverMemoryBudget,
+    WriteNotVerified,
+    SelfCheckFailed,
+}
+
+impl fmt::Display for LinemanFileError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self {
+            LinemanFileError::FileNotOpened => "The file could not be read as UTF-8 text",
+            LinemanFileError::FileNotCleaned => "The cleaned file could not be written",
+            LinemanFileError::CleaningNotVerified => "Cleaning would change more than whitespace",
+            LinemanFileError::FileOverMemoryBudget => "The file doesn't fit in the memory budget",
+            LinemanFileError::WriteNotVerified => "The written file doesn't match what was cleaned",
+            LinemanFileError::SelfCheckFailed => "Cleaning the cleaned file would change it again",
+        };
+
+        write!(formatter, "{}", message)
+    }
+}
+
+impl std::error::Error for LinemanFileError {}
+
+/// What cleaning changed in a file
+#[derive(Clone, Debug, PartialEq)]
+pub struct FileStats {
+    pub lines_changed: usize,
+    pub bytes_saved: i64,
+    pub applied_transforms: AppliedTransforms,
+    pub streamed: bool,
+    /// The blank lines at the end of the file before cleaning
+    pub trailing_blank_lines: usize,
+    /// The blank lines at the end of the file after cleaning
+    pub expected_trailing_blank_lines: usize,
+    /// Only collected when `CleanFileFlags::collects_issues` is set
+    pub issues: Vec<WhitespaceIssue>,
+    /// The trailing bytes removed from each line, only formatted when
+    /// `CleanFileFlags::show_removed` is set
+    pub removed_trailing: String,
+}
+
+/// A single whitespace change. Positions are 1-based lines and byte columns, and the end is
+/// exclusive, as reviewdog expects
+#[derive(Clone, Debug, PartialEq)]
+pub struct WhitespaceIssue {
+    pub message: &'static str,
+    pub start: (usize, usize),
+    pub end: (usize, usize),
+    pub replacement: String,
+}
+
+/// Which of the cleaning transforms changed a file
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct AppliedTransforms {
+    pub trailing_whitespace: bool,
+    pub final_newline: bool,
+    pub trailing_blank_lines: bool,
+    pub line_endings: bool,
+    pub blank_line_runs: bool,
+    pub indentation: bool,
+    pub byte_order_mark: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LineEnding {
+    Lf,
+    Crlf,
+}
+
+// Tab widths are in columns, with each tab moving to the next multiple of the width
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Reindentation {
+    TabsToSpaces(usize),
+    SpacesToTabs(usize),
+}
+
+// The transforms whose results depend on the order they run in
+// Reindentation, line endings and the byte order mark come out the same in any order, so they
+// aren't listed
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Transform {
+    TrailingWhitespace,
+    BlankLineRuns,
+    EndOfFile,
+}
+
+// Listed in the default order
+const TRANSFORM_NAMES: [(Transform, &str); 3] = [
+    (Transform::TrailingWhitespace, "trailing-whitespace"),
+    (Transform::BlankLineRuns, "blank-line-runs"),
+    (Transform::EndOfFile, "end-of-file"),
+];
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TransformOrder(Vec<Transform>);
+
+/// How a single file is cleaned beyond its clean options
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CleanFileFlags {
+    /// Fails instead of writing when cleaning would change more than whitespace
+    pub verify: bool,
+    /// Reads the written file back and fails when it doesn't match what was cleaned
+    pub verify_writes: bool,
+    /// Formats the trailing bytes removed from each line
+    pub show_removed: bool,
+    /// Fails when cleaning the cleaned file would change it again
+    pub self_check: bool,
+    /// Rewrites the file in place, keeping its inode, instead of replacing it
+    pub in_place: bool,
+    /// Collects each whitespace change as a `WhitespaceIssue`
+    pub collects_issues: bool,
+    /// Syncs the written file to disk before replacing the original
+    pub syncs: bool,
+}
+
+/// Whether a cleaned file is written, printed as a unified diff with this many lines of context,
+/// or left alone
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WriteMode {
+    Write,
+    Diff(usize),
+    DryRun,
+}
+
+impl FromStr for LineEnding {
+    type Err = String;
+
+    fn from_str(line_ending: &str) -> Result<Self, Self::Err> {
+        match line_ending {
+            "lf" => Ok(LineEnding::Lf),
+            "crlf" => Ok(LineEnding::Crlf),
+            _ => Err(format!("Unknown line ending: {}", line_ending)),
+        }
+    }
+}
+
+impl LineEnding {
+    fn terminator(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::Crlf => "\r\n",
+        }
+    }
+}
+
+impl Default for TransformOrder {
+    fn default() -> Self {
+        TransformOrder(
+            TRANSFORM_NAMES
+                .iter()
+                .map(|(transform, _)| *transform)
+                .collect(),
+        )
+    }
+}
+
+impl FromStr for TransformOrder {
+    type Err = String;
+
+    fn from_str(transform_list: &str) -> Result<Self, Self::Err> {
+        let mut transforms = Vec::new();
+
+        for transform_name in transform_list.split(',').map(str::trim) {
+            let Some((transform, _)) = TRANSFORM_NAMES
+                .iter()
+                .find(|(_, name)| *name == transform_name)
+            else {
+                return Err(format!(
+                    "Unknown transform: {}, expected one of {}",
+                    transform_name,
+                    TRANSFORM_NAMES
+                        .iter()
+                        .map(|(_, name)| *name)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ));
+            };
+
+            if transforms.contains(transform) {
+                return Err(format!("Transform listed twice: {}", transform_name));
+            }
+
+            transforms.push(*transform);
+        }
+
+        for (transform, _) in TRANSFORM_NAMES {
+            if !transforms.contains(&transform) {
+                transforms.push(transform);
+            }
+        }
+
+        Ok(TransformOrder(transforms))
+    }
+}
+
+impl TransformOrder {
+    fn runs_before(&self, first: Transform, second: Transform) -> bool {
+        let position = |transform| self.0.iter().position(|listed| *listed == transform);
+
+        position(first) < position(second)
+    }
+}
+
+impl AppliedTransforms {
+    /// Whether any transform changed the file
+    pub fn any(&self) -> bool {
+        self.trailing_whitespace
+            || self.final_newline
+            || self.trailing_blank_lines
+            || self.line_endings
+            || self.blank_line_runs
+            || self.indentation
+            || self.byte_order_mark
+    }
+
+    fn include(&mut self, other: AppliedTransforms) {
+        self.trailing_whitespace |= other.trailing_whitespace;
+        self.final_newline |= other.final_newline;
+        self.trailing_blank_lines |= other.trailing_blank_lines;
+        self.line_endings |= other.line_endings;
+        self.blank_line_runs |= other.blank_line_runs;
+        self.indentation |= other.indentation;
+        self.byte_order_mark |= other.byte_order_mark;
+    }
+
+    /// The names of the transforms that changed the file, as `--explain-changes` lists them
+    pub fn names(&self) -> Vec<&'static str> {
+        [
+            (self.trailing_whitespace, "trailing-whitespace"),
+            (self.final_newline, "final-newline"),
+            (self.trailing_blank_lines, "trailing-blank-lines"),
+            (self.line_endings, "line-endings"),
+            (self.blank_line_runs, "blank-line-runs"),
+            (self.indentation, "indentation"),
+            (self.byte_order_mark, "byte-order-mark"),
+        ]
+        .iter()
+        .filter(|(applied, _)| *applied)
+        .map(|(_, name)| *name)
+        .collect()
+    }
+}
+
+/// The size of the buffers that files too big to clean in memory are read through
+pub const STREAM_BUFFER_SIZE: usize = 64 * 1024;
+const BYTE_ORDER_MARK: &str = "\u{feff}";
+
+// Numbers the temporary files that cleaned files are written into before replacing the originals
+static TEMPORARY_FILE_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Cleans `input` with `options`, returning the cleaned text and whether cleaning changed it
+pub fn clean_str(input: &str, options: &CleanOptions) -> (String, bool) {
+    let lines: Vec<&str> = input.split_inclusive('\n').collect();
+    let (clean_lines, applied_transforms) = clean_lines(&lines, options);
+
+    (clean_lines.concat(), applied_transforms.any())
+}
+
+/// Cleans the file in place, returning whether cleaning changed it. The file is only replaced once
+/// its cleaned content is fully written, so a failure leaves the original untouched
+pub fn clean_file(path: &Path, options: &CleanOptions) -> Result<bool, LinemanFileError> {
+    let flags = CleanFileFlags {
+        syncs: true,
+        ..CleanFileFlags::default()
+    };
+
+    clean_file_with_flags(path, options, WriteMode::Write, flags)
+        .map(|file_stats| file_stats.is_some())
+}
+
+/// Cleans the file with `flags`, returning what cleaning changed, if anything
+pub fn clean_file_with_flags(
+    path: &Path,
+    clean_options: &CleanOptions,
+    write_mode: WriteMode,
+    flags: CleanFileFlags,
+) -> Result<Option<FileStats>, LinemanFileError> {
+    clean_file_with(
+        path,
+        clean_options,
+        write_mode,
+        flags,
+        clean_lines,
+        |path: &Path, clean_lines: &[String]| {
+            if flags.in_place {
+                write_clean_lines_in_place(path, clean_lines, flags.syncs)
+            } else {
+                write_clean_lines(path, clean_lines, flags.syncs)
+            }
+        },
+    )
+}
+
+// Takes the line cleaner and the writer as parameters so tests can check `--verify`,
+// `--verify-writes` and `--self-check` against faulty ones
+fn clean_file_with(
+    path: &Path,
+    clean_options: &CleanOptions,
+    write_mode: WriteMode,
+    flags: CleanFileFlags,
+    cleaner: impl Fn(&[&str], &CleanOptions) -> (Vec<String>, AppliedTransforms),
+    write_lines: impl Fn(&Path, &[String]) -> io::Result<()>,
+) -> Result<Option<FileStats>, LinemanFileError> {
+    let file_string = fs::read_to_string(path).map_err(|_| LinemanFileError::FileNotOpened)?;
+
+    // Split as the cleaner will split them, so the lines line up with the cleaned ones in diffs
+    let lines: Vec<&str> = if clean_options.line_ending.is_some() {
+        file_string
+            .split_inclusive('\n')
+            .flat_map(split_at_lone_carriage_returns)
+            .collect()
+    } else {
+        file_string.split_inclusive('\n').collect()
+    };
+
+    let (clean_lines, applied_transforms) = cleaner(&lines, clean_options);
+    let file_was_cleaned = applied_transforms.any();
+
+    if flags.self_check && file_was_cleaned {
+        let clean_line_slices: Vec<&str> = clean_lines.iter().map(String::as_str).collect();
+
+        if cleaner(&clean_line_slices, clean_options).1.any() {
+            return Err(LinemanFileError::SelfCheckFailed);
+        }
+    }
+
+    let clean_string = clean_lines.concat();
+    let without_byte_order_mark = |string: &str| {
+        string
+            .strip_prefix(BYTE_ORDER_MARK)
+            .unwrap_or(string)
+            .to_string()
+    };
+
+    // A stripped byte order mark isn't whitespace, but is still only a change to the encoding
+    if flags.verify
+        && file_was_cleaned
+        && !only_whitespace_changed(
+            &without_byte_order_mark(&file_string),
+            &without_byte_order_mark(&clean_string),
+        )
+    {
+        return Err(LinemanFileError::CleaningNotVerified);
+    }
+
+    if let WriteMode::Diff(diff_context) = write_mode {
+        if file_was_cleaned {
+            let path_string = path.display().to_string();
+            print!(
+                "{}",
+                diff::unified_diff(&lines, &clean_lines, &path_string, diff_context)
+            );
+        }
+    } else if file_was_cleaned && write_mode == WriteMode::Write {
+        write_lines(path, &clean_lines).map_err(|_| LinemanFileError::FileNotCleaned)?;
+
+        if flags.verify_writes
+            && fs::read(path).map_or(true, |written_bytes| {
+                written_bytes != clean_string.as_bytes()
+            })
+        {
+            return Err(LinemanFileError::WriteNotVerified);
+        }
+    }
+
+    Ok(file_was_cleaned.then(|| FileStats {
+        lines_changed: diff::changed_line_count(&lines, &clean_lines),
+        bytes_saved: file_string.len() as i64 - clean_string.len() as i64,
+        applied_transforms,
+        streamed: false,
+        trailing_blank_lines: trailing_blank_line_count(&lines),
+        expected_trailing_blank_lines: trailing_blank_line_count(&clean_lines),
+        issues: if flags.collects_issues {
+            whitespace_issues(&lines, &clean_lines)
+        } else {
+            Vec::new()
+        },
+        removed_trailing: if flags.show_removed {
+            format_removed_trailing(&lines, &clean_lines)
+        } else {
+            String::new()
+        },
+    }))
+}
+
+// The lines are written to a temporary file next to the original, which only replaces it once every
+// write has succeeded, so a crash or a full disk leaves either the original or the cleaned file
+fn write_clean_lines(path: &Path, clean_lines: &[String], syncs: bool) -> io::Result<()> {
+    // A symlink is replaced by its target's new contents, not by a regular file
+    let path = fs::canonicalize(path)?;
+    let temporary_path = temporary_path_beside(&path);
+
+    let result = File::create(&temporary_path).and_then(|mut temporary_file| {
+        for clean_line in clean_lines {
+            temporary_file.write_all(clean_line.as_bytes())?;
+        }
+
+        if syncs {
+            temporary_file.sync_all()?;
+        }
+
+        replace_with_temporary_file(&path, &temporary_path)
+    });
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temporary_path);
+    }
+
+    result
+}
+
+// A failure partway through leaves the file partially written, so this is only used for files
+// whose inode has to be kept
+fn write_clean_lines_in_place(path: &Path, clean_lines: &[String], syncs: bool) -> io::Result<()> {
+    let mut file = File::create(path)?;
+
+    for clean_line in clean_lines {
+        file.write_all(clean_line.as_bytes())?;
+    }
+
+    if syncs {
+        file.sync_all()?;
+    }
+
+    Ok(())
+}
+
+// Kept in the same directory, so it's on the same filesystem and the rename over the original is
+// atomic
+fn temporary_path_beside(path: &Path) -> PathBuf {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+
+    path.with_file_name(format!(
+        ".{}.lineman-{}-{}.tmp",
+        file_name,
+        process::id(),
+        TEMPORARY_FILE_COUNT.fetch_add(1, Ordering::SeqCst)
+    ))
+}
+
+// The new file would otherwise get the default permissions rather than the original's mode bits
+fn replace_with_temporary_file(path: &Path, temporary_path: &Path) -> io::Result<()> {
+    fs::set_permissions(temporary_path, fs::metadata(path)?.permissions())?;
+    fs::rename(temporary_path, path)
+}
+
+// A blank line that was removed had all of its whitespace removed with it
+fn format_removed_trailing<A: AsRef<str>, B: AsRef<str>>(lines: &[A], clean_lines: &[B]) -> String {
+    let mut removed_trailing = String::new();
+    let mut paired_lines = Vec::new();
+
+    for change in diff::changes(lines, clean_lines) {
+        let old_lines = &lines[change.old_lines.clone()];
+        let new_lines = &clean_lines[change.new_lines.clone()];
+
+        for (offset, clean_line) in paired_clean_lines(old_lines, new_lines)
+            .into_iter()
+            .enumerate()
+        {
+            paired_lines.push((change.old_lines.start + offset, clean_line));
+        }
+    }
+
+    for (line_index, clean_line) in paired_lines {
+        let line = lines[line_index].as_ref();
+
+        if clean_line.is_none() && !line.trim().is_empty() {
+            continue;
+        }
+
+        // Only the whitespace at the ends is compared, as the indentation may have been rewritten
+        let line_trailing = trailing_whitespace(line);
+        let clean_line_trailing = clean_line.map_or("", trailing_whitespace);
+        let removed = line_trailing
+            .strip_suffix(clean_line_trailing)
+            .or_else(|| line_trailing.strip_prefix(clean_line_trailing))
+            .unwrap_or(line_trailing);
+
+        if removed.is_empty() {
+            continue;
+        }
+
+        let escaped_removed: String = removed
+            .bytes()
+            .map(|byte| match byte {
+                b'\t' => "\\t".to_string(),
+                b'\r' => "\\r".to_string(),
+                _ => format!("\\x{:02x}", byte),
+            })
+            .collect();
+
+        removed_trailing.push_str(&format!(
+            "    line {}: removed \"{}\"\n",
+            line_index + 1,
+            escaped_removed
+        ));
+    }
+
+    removed_trailing
+}
+
+fn trailing_whitespace(line: &str) -> &str {
+    let line_content = line.trim_end_matches('\n');
+
+    &line_content[line_content.trim_end().len()..]
+}
+
+fn content_without_whitespace(line: &str) -> &str {
+    line.trim_start_matches(BYTE_ORDER_MARK).trim()
+}
+
+// Each line of a change is paired with the cleaned line that has the same content, or with none when
+// it's a blank line that was removed, falling back to pairing them in order
+fn paired_clean_lines<'a, A: AsRef<str>, B: AsRef<str>>(
+    old_lines: &[A],
+    new_lines: &'a [B],
+) -> Vec<Option<&'a str>> {
+    let mut paired_lines = Vec::new();
+    let mut remaining_new_lines = new_lines.iter().map(|line| line.as_ref()).peekable();
+
+    for line in old_lines.iter().map(|line| line.as_ref()) {
+        match remaining_new_lines.next_if(|clean_line| {
+            content_without_whitespace(clean_line) == content_without_whitespace(line)
+        }) {
+            Some(clean_line) => paired_lines.push(Some(clean_line)),
+            None if line.trim().is_empty() => paired_lines.push(None),
+            None => {
+                return (0..old_lines.len())
+                    .map(|index| new_lines.get(index).map(|line| line.as_ref()))
+                    .collect();
+            }
+        }
+    }
+
+    paired_lines
+}
+
+// Each change is reported with the text that would replace it, so reviewdog can suggest the fix
+// Within a change, each line is paired with the cleaned line that has the same content, and the
+// blank lines left over were removed. Anything else is reported as one replacement of the change
+fn whitespace_issues(lines: &[&str], clean_lines: &[String]) -> Vec<WhitespaceIssue> {
+    let mut issues = Vec::new();
+
+    for change in diff::changes(lines, clean_lines) {
+        let new_lines = &clean_lines[change.new_lines.clone()];
+
+        match paired_line_issues(lines, change.old_lines.clone(), new_lines) {
+            Some(change_issues) => issues.extend(change_issues),
+            None => issues.push(WhitespaceIssue {
+                message: "Whitespace differs",
+                start: (change.old_lines.start + 1, 1),
+                end: position_after_line(lines, change.old_lines.end),
+                replacement: new_lines.concat(),
+            }),
+        }
+    }
+
+    issues
+}
+
+fn paired_line_issues(
+    lines: &[&str],
+    old_lines: Range<usize>,
+    new_lines: &[String],
+) -> Option<Vec<WhitespaceIssue>> {
+    let mut issues = Vec::new();
+    let mut new_lines = new_lines.iter().peekable();
+    let mut removed_start = None;
+
+    for line_index in old_lines.clone() {
+        let line = lines[line_index];
+
+        match new_lines.next_if(|clean_line| clean_line.trim() == line.trim()) {
+            Some(clean_line) => {
+                if let Some(removed_start) = removed_start.take() {
+                    issues.push(removed_lines_issue(lines, removed_start..line_index));
+                }
+
+                if line != clean_line {
+                    issues.push(line_issue(lines, line_index, line, clean_line));
+                }
+            }
+            None if line.trim().is_empty() => {
+                removed_start.get_or_insert(line_index);
+            }
+            None => return None,
+        }
+    }
+
+    if new_lines.next().is_some() {
+        return None;
+    }
+
+    if let Some(removed_start) = removed_start {
+        issues.push(removed_lines_issue(lines, removed_start..old_lines.end));
+    }
+
+    Some(issues)
+}
+
+fn removed_lines_issue(lines: &[&str], removed_lines: Range<usize>) -> WhitespaceIssue {
+    WhitespaceIssue {
+        message: "Extra blank lines",
+        start: (removed_lines.start + 1, 1),
+        end: position_after_line(lines, removed_lines.end),
+        replacement: String::new(),
+    }
+}
+
+fn line_issue(lines: &[&str], line_index: usize, line: &str, clean_line: &str) -> WhitespaceIssue {
+    let line_content = line.trim_end_matches(['\r', '\n']);
+    let clean_line_content = clean_line.trim_end_matches(['\r', '\n']);
+    let keeps_terminator = line[line_content.len()..] == clean_line[clean_line_content.len()..];
+    let line_number = line_index + 1;
+
+    if keeps_terminator && line_content.starts_with(clean_line_content) {
+        return WhitespaceIssue {
+            message: "Trailing whitespace",
+            start: (line_number, clean_line_content.len() + 1),
+            end: (line_number, line_content.len() + 1),
+            replacement: String::new(),
+        };
+    }
+
+    if line_content == clean_line_content && line.len() == line_content.len() {
+        return WhitespaceIssue {
+            message: "Missing final newline",
+            start: (line_number, line_content.len() + 1),
+            end: (line_number, line_content.len() + 1),
+            replacement: clean_line[clean_line_content.len()..].to_string(),
+        };
+    }
+
+    let message = if line_content == clean_line_content {
+        "Line ending differs"
+    } else if line_content.trim_start() == clean_line_content.trim_start() {
+        "Indentation differs"
+    } else {
+        "Whitespace differs"
+    };
+
+    WhitespaceIssue {
+        message,
+        start: (line_number, 1),
+        end: position_after_line(lines, line_index + 1),
+        replacement: clean_line.to_string(),
+    }
+}
+
+// The position just past the line before `line_index`, which is the start of the next line unless
+// it's the last one and has no newline
+fn position_after_line(lines: &[&str], line_index: usize) -> (usize, usize) {
+    match line_index
+        .checked_sub(1)
+        .map(|previous_index| lines[previous_index])
+    {
+        Some(line) if !line.ends_with('\n') => (line_index, line.len() + 1),
+        _ => (line_index + 1, 1),
+    }
+}
+
+/// Cleans a file too big to hold in memory line by line into a temporary file, which then replaces
+/// the original as `clean_file_with_flags` does, or is copied over it when its inode has to be kept
+pub fn clean_file_streaming(
+    path: &Path,
+    clean_options: &CleanOptions,
+    write_mode: WriteMode,
+    memory_budget: usize,
+    flags: CleanFileFlags,
+) -> Result<Option<FileStats>, LinemanFileError> {
+    let file = File::open(path).map_err(|_| LinemanFileError::FileNotOpened)?;
+    let reader = BufReader::with_capacity(STREAM_BUFFER_SIZE.min(memory_budget.max(1)), file);
+
+    if write_mode != WriteMode::Write {
+        return stream_clean_lines(reader, &mut io::sink(), clean_options, memory_budget);
+    }
+
+    let path = &fs::canonicalize(path).map_err(|_| LinemanFileError::FileNotOpened)?;
+    let temporary_path = temporary_path_beside(path);
+
+    let result = File::create(&temporary_path)
+        .map_err(|_| LinemanFileError::FileNotCleaned)
+        .and_then(|temporary_file| {
+            let mut writer = BufWriter::new(temporary_file);
+            let file_stats = stream_clean_lines(reader, &mut writer, clean_options, memory_budget)?;
+
+            writer
+                .into_inner()
+                .map_err(|error| error.into_error())
+                .and_then(|temporary_file| {
+                    if flags.syncs {
+                        temporary_file.sync_all()
+                    } else {
+                        Ok(())
+                    }
+                })
+                .map_err(|_| LinemanFileError::FileNotCleaned)?;
+
+            if file_stats.is_none() {
+                return Ok(None);
+            }
+
+            if flags.self_check {
+                let temporary_file =
+                    File::open(&temporary_path).map_err(|_| LinemanFileError::FileNotCleaned)?;
+                let reader = BufReader::with_capacity(
+                    STREAM_BUFFER_SIZE.min(memory_budget.max(1)),
+                    temporary_file,
+                );
+
+                if stream_clean_lines(reader, &mut io::sink(), clean_options, memory_budget)?
+                    .is_some()
+                {
+                    return Err(LinemanFileError::SelfCheckFailed);
+                }
+            }
+
+            // `fs::copy` would also copy the temporary file's permissions, so the bytes are copied
+            // into the truncated original instead
+            if flags.in_place {
+                File::open(&temporary_path)
+                    .and_then(|mut temporary_file| {
+                        io::copy(&mut temporary_file, &mut File::create(path)?)
+                    })
+                    .map_err(|_| LinemanFileError::FileNotCleaned)?;
+
+                if flags.verify_writes && !files_match(&temporary_path, path).unwrap_or(false) {
+                    return Err(LinemanFileError::WriteNotVerified);
+                }
+            } else {
+                // The renamed file is the one that was just written, so there's nothing to verify
+                replace_with_temporary_file(path, &temporary_path)
+                    .map_err(|_| LinemanFileError::FileNotCleaned)?;
+            }
+
+            Ok(file_stats)
+        });
+
+    let _ = fs::remove_file(&temporary_path);
+
+    result
+}
+
+// Compared a buffer at a time, so neither file has to fit in memory
+fn files_match(first_path: &Path, second_path: &Path) -> io::Result<bool> {
+    let mut first_reader = BufReader::with_capacity(STREAM_BUFFER_SIZE, File::open(first_path)?);
+    let mut second_reader = BufReader::with_capacity(STREAM_BUFFER_SIZE, File::open(second_path)?);
+
+    loop {
+        let first_buffer = first_reader.fill_buf()?;
+        let second_buffer = second_reader.fill_buf()?;
+
+        if first_buffer.is_empty() || second_buffer.is_empty() {
+            return Ok(first_buffer.is_empty() && second_buffer.is_empty());
+        }
+
+        let length = first_buffer.len().min(second_buffer.len());
+
+        if first_buffer[..length] != second_buffer[..length] {
+            return Ok(false);
+        }
+
+        first_reader.consume(length);
+        second_reader.consume(length);
+    }
+}
+
+fn stream_clean_lines(
+    mut reader: impl BufRead,
+    writer: &mut dyn Write,
+    clean_options: &CleanOptions,
+    memory_budget: usize,
+) -> Result<Option<FileStats>, LinemanFileError> {
+    let mut line_cleaner = LineCleaner::new(clean_options);
+    let mut line_buffer = Vec::new();
+    let mut original_byte_count = 0;
+    let mut cleaned_byte_count = 0;
+    let mut trailing_blank_lines = 0;
+    let mut expected_trailing_blank_lines = 0;
+    let mut write_failed = false;
+
+    // The byte order mark is split off before the first line, as in `clean_lines`
+    let has_byte_order_mark = reader
+        .fill_buf()
+        .map_err(|_| LinemanFileError::FileNotOpened)?
+        .starts_with(BYTE_ORDER_MARK.as_bytes());
+
+    if has_byte_order_mark {
+        reader.consume(BYTE_ORDER_MARK.len());
+        original_byte_count += BYTE_ORDER_MARK.len();
+
+        if !clean_options.strip_bom {
+            cleaned_byte_count += BYTE_ORDER_MARK.len();
+            write_failed |= writer.write_all(BYTE_ORDER_MARK.as_bytes()).is_err();
+        }
+    }
+
+    let mut emit = |cleaned_line: String| {
+        cleaned_byte_count += cleaned_line.len();
+        expected_trailing_blank_lines = if cleaned_line.trim_end().is_empty() {
+            expected_trailing_blank_lines + 1
+        } else {
+            0
+        };
+        write_failed |= writer.write_all(cleaned_line.as_bytes()).is_err();
+    };
+
+    loop {
+        line_buffer.clear();
+
+        // Reading one byte past the budget is enough to tell that a line doesn't fit in it
+        let read_byte_count = reader
+            .by_ref()
+            .take(memory_budget as u64 + 1)
+            .read_until(b'\n', &mut line_buffer)
+            .map_err(|_| LinemanFileError::FileNotOpened)?;
+
+        if read_byte_count == 0 {
+            break;
+        }
+
+        if line_buffer.len() + line_cleaner.pending_bytes() > memory_budget {
+            return Err(LinemanFileError::FileOverMemoryBudget);
+        }
+
+        original_byte_count += read_byte_count;
+
+        let line =
+            std::str::from_utf8(&line_buffer).map_err(|_| LinemanFileError::FileNotOpened)?;
+
+        trailing_blank_lines = if line.trim_end().is_empty() {
+            trailing_blank_lines + 1
+        } else {
+            0
+        };
+
+        line_cleaner.push(line, &mut emit);
+    }
+
+    let mut applied_transforms = line_cleaner.finish(&mut emit);
+    applied_transforms.byte_order_mark = has_byte_order_mark && clean_options.strip_bom;
+
+    if write_failed {
+        return Err(LinemanFileError::FileNotCleaned);
+    }
+
+    Ok(applied_transforms.any().then(|| FileStats {
+        lines_changed: line_cleaner.lines_changed,
+        bytes_saved: original_byte_count as i64 - cleaned_byte_count as i64,
+        applied_transforms,
+        streamed: true,
+        trailing_blank_lines,
+        expected_trailing_blank_lines,
+        // Files over the memory budget are skipped by the formats that report each issue, and by
+        // `--show-removed`
+        issues: Vec::new(),
+        removed_trailing: String::new(),
+    }))
+}
+
+fn trailing_blank_line_count<L: AsRef<str>>(lines: &[L]) -> usize {
+    lines
+        .iter()
+        .rev()
+        .take_while(|line| line.as_ref().trim_end().is_empty())
+        .count()
+}
+
+// Every run of whitespace is collapsed, so two strings compare equal when they differ only in
+// whitespace
+fn only_whitespace_changed(original: &str, cleaned: &str) -> bool {
+    original.split_whitespace().eq(cleaned.split_whitespace())
+}
+
+/// Cleans lines split with `split_inclusive('\n')`, returning the cleaned lines and which transforms
+/// changed them
+// The byte order mark is split off the first line before cleaning, so transforms that look at the
+// start of a line never see it, and is put back on afterwards unless it's being stripped
+pub fn clean_lines(lines: &[&str], options: &CleanOptions) -> (Vec<String>, AppliedTransforms) {
+    let mut content_lines = lines.to_vec();
+    let has_byte_order_mark = lines
+        .first()
+        .is_some_and(|first_line| first_line.starts_with(BYTE_ORDER_MARK));
+
+    if has_byte_order_mark {
+        content_lines[0] = &content_lines[0][BYTE_ORDER_MARK.len()..];
+
+        if content_lines[0].is_empty() {
+            content_lines.remove(0);
+        }
+    }
+
+    let mut cleaned_lines = Vec::new();
+    let mut line_cleaner = LineCleaner::new(options);
+
+    for line in content_lines {
+        line_cleaner.push(line, &mut |cleaned_line| cleaned_lines.push(cleaned_line));
+    }
+
+    let mut applied_transforms =
+        line_cleaner.finish(&mut |cleaned_line| cleaned_lines.push(cleaned_line));
+    applied_transforms.byte_order_mark = has_byte_order_mark && options.strip_bom;
+
+    if has_byte_order_mark && !options.strip_bom {
+        match cleaned_lines.first_mut() {
+            Some(first_line) => first_line.insert_str(0, BYTE_ORDER_MARK),
+            None => cleaned_lines.push(BYTE_ORDER_MARK.to_string()),
+        }
+    }
+
+    // Removing the lines before a `\u{feff}` leaves it where the next run takes it for a byte order
+    // mark, so it's cleaned as one straight away
+    if !has_byte_order_mark
+        && cleaned_lines
+            .first()
+            .is_some_and(|first_line| first_line.starts_with(BYTE_ORDER_MARK))
+    {
+        let exposed_lines = cleaned_lines;
+        let (cleaned_lines, exposed_applied_transforms) = clean_lines(
+            &exposed_lines.iter().map(String::as_str).collect::<Vec<_>>(),
+            options,
+        );
+
+        applied_transforms.include(exposed_applied_transforms);
+        return (cleaned_lines, applied_transforms);
+    }
+
+    (cleaned_lines, applied_transforms)
+}
+
+// Cleans one line at a time, so a large file can be streamed through it
+// The last content line and the blank lines after it are held back until it's known whether more
+// content follows, since they are cleaned differently at the end of the file
+struct LineCleaner<'a> {
+    options: &'a CleanOptions,
+    open_quote: Option<char>,
+    open_triple_quote: Option<char>,
+    pending_content_line: Option<PendingContentLine>,
+    pending_blank_lines: Vec<PendingBlankLine>,
+    applied_transforms: AppliedTransforms,
+    lines_changed: usize,
+    line_count: usize,
+}
+
+struct PendingContentLine {
+    line: String,
+    keeps_trailing: bool,
+    keeps_indentation: bool,
+}
+
+// A whitespace-only line only counts as blank to the transforms that run after trimming, while an
+// empty one is blank to all of them
+struct PendingBlankLine {
+    cleaned_line: String,
+    changed: bool,
+    empty: bool,
+}
+
+impl<'a> LineCleaner<'a> {
+    fn new(options: &'a CleanOptions) -> Self {
+        LineCleaner {
+            options,
+            open_quote: None,
+            open_triple_quote: None,
+            pending_content_line: None,
+            pending_blank_lines: Vec::new(),
+            applied_transforms: AppliedTransforms::default(),
+            lines_changed: 0,
+            line_count: 0,
+        }
+    }
+
+    // Lines that aren't comments are left byte-identical with `comments_only_prefixes`, so the end of
+    // the text is left alone too
+    fn normalizes_eof_newlines(&self) -> bool {
+        self.options.normalize_eof_newlines && self.options.comments_only_prefixes.is_none()
+    }
+
+    // A lone `\r` also ends a line when line endings are normalized, as in classic Mac OS files
+    fn push(&mut self, line: &str, emit: &mut dyn FnMut(String)) {
+        if self.options.line_ending.is_none() {
+            return self.push_line(line, emit);
+        }
+
+        for line in split_at_lone_carriage_returns(line) {
+            self.push_line(line, emit);
+        }
+    }
+
+    fn push_line(&mut self, line: &str, emit: &mut dyn FnMut(String)) {
+        let options = self.options;
+        self.line_count += 1;
+
+        // A line that starts inside a string has no indentation of its own to convert
+        let keeps_indentation = self.open_quote.is_some() || self.open_triple_quote.is_some();
+
+        // Both trackers carry state between lines, so each has to see every line once it's in use
+        let reindents = options.reindentation.is_some();
+        let ends_inside_quotes = (options.preserve_quoted_trailing || reindents)
+            && line_ends_inside_quotes(line, &mut self.open_quote);
+        let ends_inside_python_string = (options.preserve_python_strings || reindents)
+            && line_ends_inside_python_string(line, &mut self.open_triple_quote);
+        let inside_quotes = options.preserve_quoted_trailing && ends_inside_quotes;
+        let inside_python_string = options.preserve_python_strings && ends_inside_python_string;
+
+        let keeps_trailing = !options.trim_trailing_whitespace
+            || inside_quotes
+            || inside_python_string
+            || is_aligned_comment(line, &options.aligned_comment_prefixes)
+            || options
+                .comments_only_prefixes
+                .as_ref()
+                .is_some_and(|comment_prefixes| comment_body(line, comment_prefixes).is_none());
+
+        if line.trim_end().is_empty() {
+            let keeps_trailing = keeps_trailing || self.keeps_whitespace_only_lines();
+            let cleaned_line = self.clean_line(line, keeps_trailing, true);
+
+            self.pending_blank_lines.push(PendingBlankLine {
+                changed: cleaned_line != line,
+                empty: line.trim_end_matches(['\r', '\n']).is_empty(),
+                cleaned_line,
+            });
+            return;
+        }
+
+        if let Some(content_line) = self.pending_content_line.take() {
+            self.emit_content_line(content_line, emit);
+        }
+
+        self.emit_blank_lines(emit);
+
+        self.pending_content_line = Some(PendingContentLine {
+            line: line.to_string(),
+            keeps_trailing,
+            keeps_indentation,
+        });
+    }
+
+    fn finish(&mut self, emit: &mut dyn FnMut(String)) -> AppliedTransforms {
+        if let Some(mut content_line) = self.pending_content_line.take() {
+            content_line.keeps_trailing |= self.options.keep_last_line_trailing;
+            self.emit_content_line(content_line, emit);
+        }
+
+        if self.normalizes_eof_newlines() {
+            let counts_as_blank = self.counts_as_blank(Transform::EndOfFile);
+            let trailing_start = self
+                .pending_blank_lines
+                .iter()
+                .rposition(|blank_line| !counts_as_blank(blank_line))
+                .map_or(0, |index| index + 1);
+            let mut trailing_blank_lines = self.pending_blank_lines.split_off(trailing_start);
+
+            if self.options.keep_one_trailing_blank_line && !trailing_blank_lines.is_empty() {
+                self.pending_blank_lines
+                    .push(trailing_blank_lines.remove(0));
+            }
+
+            if !trailing_blank_lines.is_empty() {
+                self.applied_transforms.trailing_blank_lines = true;
+                self.lines_changed += trailing_blank_lines.len();
+            }
+        }
+
+        self.emit_blank_lines(emit);
+        self.applied_transforms
+    }
+
+    fn emit_content_line(
+        &mut self,
+        content_line: PendingContentLine,
+        emit: &mut dyn FnMut(String),
+    ) {
+        let cleaned_line = self.clean_line(
+            &content_line.line,
+            content_line.keeps_trailing,
+            content_line.keeps_indentation,
+        );
+
+        if cleaned_line != content_line.line {
+            self.lines_changed += 1;
+        }
+
+        emit(cleaned_line);
+    }
+
+    fn emit_blank_lines(&mut self, emit: &mut dyn FnMut(String)) {
+        if let Some(max_consecutive_blank_lines) = self.options.max_consecutive_blank_lines {
+            let counts_as_blank = self.counts_as_blank(Transform::BlankLineRuns);
+            let pending_line_count = self.pending_blank_lines.len();
+            let mut run_length = 0;
+
+            self.pending_blank_lines.retain(|blank_line| {
+                if counts_as_blank(blank_line) {
+                    run_length += 1;
+                    run_length <= max_consecutive_blank_lines
+                } else {
+                    run_length = 0;
+                    true
+                }
+            });
+
+            let removed_line_count = pending_line_count - self.pending_blank_lines.len();
+
+            if removed_line_count > 0 {
+                self.applied_transforms.blank_line_runs = true;
+                self.lines_changed += removed_line_count;
+            }
+        }
+
+        for blank_line in self.pending_blank_lines.drain(..) {
+            self.lines_changed += usize::from(blank_line.changed);
+            emit(blank_line.cleaned_line);
+        }
+    }
+
+    // A whitespace-only line that a transform running before trimming kept as content is left as it
+    // is, since trimming it would leave a blank line for that transform to remove on the next run
+    fn keeps_whitespace_only_lines(&self) -> bool {
+        let runs_before_trimming = |transform| {
+            !self
+                .options
+                .transform_order
+                .runs_before(Transform::TrailingWhitespace, transform)
+        };
+
+        (self.options.max_consecutive_blank_lines.is_some()
+            && runs_before_trimming(Transform::BlankLineRuns))
+            || (self.normalizes_eof_newlines() && runs_before_trimming(Transform::EndOfFile))
+    }
+
+    fn counts_as_blank(&self, transform: Transform) -> impl Fn(&PendingBlankLine) -> bool {
+        let runs_after_trimming = self
+            .options
+            .transform_order
+            .runs_before(Transform::TrailingWhitespace, transform);
+
+        move |blank_line: &PendingBlankLine| blank_line.empty || runs_after_trimming
+    }
+
+    // The bytes held back so far, which have to fit in memory however the file is read
+    fn pending_bytes(&self) -> usize {
+        self.pending_content_line
+            .as_ref()
+            .map_or(0, |content_line| content_line.line.len())
+            + self
+                .pending_blank_lines
+                .iter()
+                .map(|blank_line| blank_line.cleaned_line.len())
+                .sum::<usize>()
+    }
+
+    // Without a line ending to normalize to, a `\r` before the newline is part of the content, and
+    // is trimmed along with the rest of the trailing whitespace
+    // Only the last line can lack a newline, so the line count is the file's by the time it's seen
+    fn clean_line(&mut self, line: &str, keeps_trailing: bool, keeps_indentation: bool) -> String {
+        let normalizes_line_endings = self.options.line_ending.is_some();
+        let line_has_newline =
+            line.ends_with('\n') || (normalizes_line_endings && line.ends_with('\r'));
+        let mut line_content = line.strip_suffix('\n').unwrap_or(line);
+
+        if line_has_newline && normalizes_line_endings {
+            line_content = line_content.strip_suffix('\r').unwrap_or(line_content);
+        }
+
+        let line_terminator = &line[line_content.len()..];
+        let reindented_line = match self.options.reindentation {
+            _ if keeps_indentation => None,
+            Some(Reindentation::TabsToSpaces(tab_width)) => {
+                expand_leading_tabs(line_content, tab_width)
+            }
+            Some(Reindentation::SpacesToTabs(tab_width)) => {
+                collapse_leading_spaces(line_content, tab_width)
+            }
+            None => None,
+        };
+
+        self.applied_transforms.indentation |= reindented_line.is_some();
+
+        let line_content = reindented_line.as_deref().unwrap_or(line_content);
+        let trimmed_line = line_content.trim_end();
+        let trailing_character_count = line_content[trimmed_line.len()..].chars().count();
+        let trimmed_line =
+            if keeps_trailing || trailing_character_count <= self.options.min_trailing {
+                line_content
+            } else {
+                trimmed_line
+            };
+
+        let terminator = self
+            .options
+            .line_ending
+            .map_or("\n", |line_ending| line_ending.terminator());
+        let trailing_whitespace_trimmed = trimmed_line != line_content;
+        let adds_final_newline = self.normalizes_eof_newlines()
+            && !line_has_newline
+            && self.line_count >= self.options.final_newline_min_lines
+            && !(self.options.minimal_eof && trailing_whitespace_trimmed);
+
+        self.applied_transforms.trailing_whitespace |= trailing_whitespace_trimmed;
+        self.applied_transforms.final_newline |= adds_final_newline;
+        self.applied_transforms.line_endings |= line_has_newline && line_terminator != terminator;
+
+        if adds_final_newline || line_has_newline {
+            format!("{}{}", trimmed_line, terminator)
+        } else {
+            trimmed_line.to_string()
+        }
+    }
+}
+
+fn split_at_lone_carriage_returns(line: &str) -> Vec<&str> {
+    let mut lines = Vec::new();
+    let mut line_start = 0;
+
+    for (carriage_return_index, _) in line.match_indices('\r') {
+        if !line[carriage_return_index + 1..].starts_with('\n') {
+            lines.push(&line[line_start..=carriage_return_index]);
+            line_start = carriage_return_index + 1;
+        }
+    }
+
+    if line_start < line.len() {
+        lines.push(&line[line_start..]);
+    }
+
+    lines
+}
+
+// Each tab moves to the next multiple of the tab width, so spaces mixed in before it keep the
+// indentation's width. Returns `None` when the indentation has no tabs
+fn expand_leading_tabs(line: &str, tab_width: usize) -> Option<String> {
+    let content_start = line.len() - line.trim_start_matches([' ', '\t']).len();
+    let indentation = &line[..content_start];
+
+    if !indentation.contains('\t') {
+        return None;
+    }
+
+    let mut expanded_line = String::with_capacity(line.len() + indentation.len() * tab_width);
+
+    for character in indentation.chars() {
+        if character == '\t' {
+            let column = expanded_line.len();
+            let next_tab_stop = (column / tab_width.max(1) + 1) * tab_width.max(1);
+            expanded_line.push_str(&" ".repeat(next_tab_stop - column));
+        } else {
+            expanded_line.push(character);
+        }
+    }
+
+    expanded_line.push_str(&line[content_start..]);
+
+    Some(expanded_line)
+}
+
+// The indentation keeps its width, with whole tab widths written as tabs and the rest as spaces
+// Returns `None` when the indentation is already written that way
+fn collapse_leading_spaces(line: &str, tab_width: usize) -> Option<String> {
+    let tab_width = tab_width.max(1);
+    let content_start = line.len() - line.trim_start_matches([' ', '\t']).len();
+    let indentation = &line[..content_start];
+
+    let indentation_width = indentation.chars().fold(0, |column, character| {
+        if character == '\t' {
+            (column / tab_width + 1) * tab_width
+        } else {
+            column + 1
+        }
+    });
+
+    let collapsed_indentation = format!(
+        "{}{}",
+        "\t".repeat(indentation_width / tab_width),
+        " ".repeat(indentation_width % tab_width)
+    );
+
+    if collapsed_indentation == indentation {
+        return None;
+    }
+
+    Some(collapsed_indentation + &line[content_start..])
+}
+
+// A line is a comment when, after its indentation, it starts with one of the prefixes
+fn comment_body<'a>(line: &'a str, comment_prefixes: &[String]) -> Option<&'a str> {
+    let line = line.trim();
+
+    comment_prefixes
+        .iter()
+        .find_map(|comment_prefix| line.strip_prefix(comment_prefix.as_str()))
+}
+
+// A border is a symbol repeated at the end of the comment, or the same symbol opening and closing it
+fn is_aligned_comment(line: &str, comment_prefixes: &[String]) -> bool {
+    let Some(comment) = comment_body(line, comment_prefixes) else {
+        return false;
+    };
+
+    let comment_characters: Vec<char> = comment.trim().chars().collect();
+    let is_border_character = |character: &char| !character.is_alphanumeric();
+
+    match comment_characters.as_slice() {
+        [first, .., second_last, last] if is_border_character(last) => {
+            second_last == last || first == last
+        }
+        _ => false,
+    }
+}
+
+// Tracks quoting the way dotenv files do, where a quoted value may span several lines
+// Returns whether the line ends inside an open quote, as its trailing whitespace is then part of
+// the value
+fn line_ends_inside_quotes(line: &str, open_quote: &mut Option<char>) -> bool {
+    let content = line.trim_end_matches(['\r', '\n']);
+
+    let quoted_content = match open_quote {
+        Some(_) => content,
+        None => {
+            if content.trim_start().starts_with('#') {
+                return false;
+            }
+
+            let Some((_, value)) = content.split_once('=') else {
+                return false;
+            };
+
+            let value = value.trim_start();
+
+            match value.chars().next() {
+                Some(quote @ ('"' | '\'')) => {
+                    *open_quote = Some(quote);
+                    &value[1..]
+                }
+                _ => return false,
+            }
+        }
+    };
+
+    let mut characters = quoted_content.chars();
+
+    while let Some(character) = characters.next() {
+        if character == '\\' && *open_quote == Some('"') {
+            characters.next();
+        } else if Some(character) == *open_quote {
+            *open_quote = None;
+            return false;
+        }
+    }
+
+    open_quote.is_some()
+}
+
+// Returns whether the line ends inside a triple-quoted string that continues onto the next line
+// Single-quoted strings and comments are followed too, so quotes inside them are not mistaken for
+// the start of a triple-quoted string
+fn line_ends_inside_python_string(line: &str, open_triple_quote: &mut Option<char>) -> bool {
+    let characters: Vec<char> = line.trim_end_matches(['\r', '\n']).chars().collect();
+    let starts_triple_quote =
+        |index: usize, quote: char| characters[index..].starts_with(&[quote, quote, quote]);
+    let mut open_single_quote = None;
+    let mut index = 0;
+
+    while index < characters.len() {
+        let character = characters[index];
+
+        match (*open_triple_quote, open_single_quote) {
+            (Some(_), _) | (_, Some(_)) if character == '\\' => index += 2,
+            (Some(quote), _) => {
+                if character == quote && starts_triple_quote(index, quote) {
+                    *open_triple_quote = None;
+                    index += 3;
+                } else {
+                    index += 1;
+                }
+            }
+            (None, Some(quote)) => {
+                if character == quote {
+                    open_single_quote = None;
+                }
+
+                index += 1;
+            }
+            (None, None) => match character {
+                '#' => break,
+                '"' | '\'' if starts_triple_quote(index, character) => {
+                    *open_triple_quote = Some(character);
+                    index += 3;
+                }
+                '"' | '\'' => {
+                    open_single_quote = Some(character);
+                    index += 1;
+                }
+                _ => index += 1,
+            },
+        }
+    }
+
+    open_triple_quote.is_some()
+}
+
+#[cfg(test)]
+fn create_test_directory(test_name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let directory =
+        std::env::temp_dir().join(format!("lineman-{}-{}", test_name, std::process::id()));
+
+    let _ = fs::remove_dir_all(&directory);
+
+    for (relative_path, content) in files {
+        let path = directory.join(relative_path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    directory
+}
+
+#[test]
+fn clean_lines_with_trailing_spaces() {
+    let input_lines = [
+        "def main():   \n",
+        "    print(\"Hello World\")    \n",
+        "    \n",
+        "if __name__ == \"__main__\":    \n",
+        "    main()    \n",
+    ];
+
+    let expected_output_lines = [
+        "def main():\n",
+        "    print(\"Hello World\")\n",
+        "\n",
+        "if __name__ == \"__main__\":\n",
+        "    main()\n",
+    ];
+
+    let (output_lines, applied_transforms) = clean_lines(&input_lines, &CleanOptions::default());
+
+    assert_eq!(expected_output_lines.to_vec(), output_lines);
+    assert!(applied_transforms.any());
+}
+
+#[test]
+fn clean_lines_with_trailing_tabs() {
+    let input_lines = [
+        "def main():\t\n",
+        "    print(\"Hello World\")\t\n",
+        "\t\n",
+        "if __name__ == \"__main__\":\t\n",
+        "    main()\t\n",
+    ];
+
+    let expected_output_lines = [
+        "def main():\n",
+        "    print(\"Hello World\")\n",
+        "\n",
+        "if __name__ == \"__main__\":\n",
+        "    main()\n",
+    ];
+
+    let (output_lines, applied_transforms) = clean_lines(&input_lines, &CleanOptions::default());
+
+    assert_eq!(expected_output_lines.to_vec(), output_lines);
+    assert!(applied_transforms.any());
+}
+
+// A stand-in for a fuzz target: pseudo-random lines mixing multi-byte characters and many kinds of
+// whitespace are cleaned with varied options
+// The output is a `String`, so it is always valid UTF-8, which leaves panics and idempotency to check
+#[test]
+fn add_newline_to_end_of_file() {
+    let input_lines = [
+        "def main():\n",
+        "    print(\"Hello World\")\n",
+        "\n",
+        "if __name__ == \"__main__\":\n",
+        "    main()",
+    ];
+
+    let expected_output_lines = [
+        "def main():\n",
+        "    print(\"Hello World\")\n",
+        "\n",
+        "if __name__ == \"__main__\":\n",
+        "    main()\n",
+    ];
+
+    let (output_lines, applied_transforms) = clean_lines(&input_lines, &CleanOptions::default());
+
+    assert_eq!(expected_output_lines.to_vec(), output_lines);
+    assert!(applied_transforms.any());
+}
+
+#[test]
+fn do_not_add_newline_to_end_of_file() {
+    let input_lines = [
+        "def main():\n",
+        "    print(\"Hello World\")\n",
+        "\n",
+        "if __name__ == \"__main__\":\n",
+        "    main()",
+    ];
+
+    let expected_output_lines = [
+        "def main():\n",
+        "    print(\"Hello World\")\n",
+        "\n",
+        "if __name__ == \"__main__\":\n",
+        "    main()",
+    ];
+
+    let (output_lines, applied_transforms) = clean_lines(
+        &input_lines,
+        &CleanOptions {
+            normalize_eof_newlines: false,
+            ..CleanOptions::default()
+        },
+    );
+
+    assert_eq!(expected_output_lines.to_vec(), output_lines);
+    assert!(!applied_transforms.any());
+}
+
+#[test]
+fn remove_excessive_newlines_from_end_of_file() {
+    let input_lines = [
+        "def main():\n",
+        "    print(\"Hello World\")\n",
+        "\n",
+        "if __name__ == \"__main__\":\n",
+        "    main()\n",
+        "\n",
+        "\n",
+        "\n",
+    ];
+
+    let expected_output_lines = [
+        "def main():\n",
+        "    print(\"Hello World\")\n",
+        "\n",
+        "if __name__ == \"__main__\":\n",
+        "    main()\n",
+    ];
+
+    let (output_lines, applied_transforms) = clean_lines(&input_lines, &CleanOptions::default());
+
+    assert_eq!(expected_output_lines.to_vec(), output_lines);
+    assert!(applied_transforms.any());
+}
+
+#[test]
+fn do_not_remove_excessive_newlines_from_end_of_file() {
+    let input_lines = [
+        "def main():\n",
+        "    print(\"Hello World\")\n",
+        "\n",
+        "if __name__ == \"__main__\":\n",
+        "    main()\n",
+        "\n",
+        "\n",
+        "\n",
+    ];
+
+    let expected_output_lines = [
+        "def main():\n",
+        "    print(\"Hello World\")\n",
+        "\n",
+        "if __name__ == \"__main__\":\n",
+        "    main()\n",
+        "\n",
+        "\n",
+        "\n",
+    ];
+
+    let (output_lines, applied_transforms) = clean_lines(
+        &input_lines,
+        &CleanOptions {
+            normalize_eof_newlines: false,
+            ..CleanOptions::default()
+        },
+    );
+
+    assert_eq!(expected_output_lines.to_vec(), output_lines);
+    assert!(!applied_transforms.any());
+}
+
+#[test]
+fn final_newline_min_lines_leaves_shorter_files_without_one() {
+    let clean_options = CleanOptions {
+        final_newline_min_lines: 2,
+        ..CleanOptions::default()
+    };
+
+    let (output_lines, applied_transforms) = clean_lines(&["key = value   "], &clean_options);
+
+    assert_eq!(output_lines, vec!["key = value"]);
+    assert!(!applied_transforms.final_newline);
+
+    let input_lines = ["a\n", "b\n", "c\n", "d\n", "e"];
+    let (output_lines, applied_transforms) = clean_lines(&input_lines, &clean_options);
+
+    assert_eq!(output_lines, vec!["a\n", "b\n", "c\n", "d\n", "e\n"]);
+    assert!(applied_transforms.final_newline);
+}
+
+#[test]
+fn min_trailing_only_trims_lines_over_the_threshold() {
+    let clean_options = CleanOptions {
+        min_trailing: 2,
+        ..CleanOptions::default()
+    };
+
+    let input_lines = [
+        "at threshold  \n",
+        "over threshold \t \n",
+        "  \n",
+        "last",
+        "\n",
+    ];
+    let (output_lines, applied_transforms) = clean_lines(&input_lines, &clean_options);
+
+    assert_eq!(
+        output_lines,
+        vec!["at threshold  \n", "over threshold\n", "  \n", "last\n"]
+    );
+    assert!(applied_transforms.trailing_whitespace);
+
+    let (output_lines, applied_transforms) =
+        clean_lines(&["at threshold  \n", "\t\n"], &clean_options);
+
+    assert_eq!(output_lines, vec!["at threshold  \n"]);
+    assert!(!applied_transforms.trailing_whitespace);
+}
+
+#[test]
+fn tabs_to_spaces_only_expands_leading_tabs() {
+    let input_lines = [
+        "def main():\n",
+        "\tif ready:\n",
+        " \t\treturn\t1\n",
+        "    text = \"\"\"\n",
+        "\tinside a string\n",
+        "\"\"\"\n",
+    ];
+
+    let expected_output_lines = [
+        "def main():\n",
+        "    if ready:\n",
+        "        return\t1\n",
+        "    text = \"\"\"\n",
+        "\tinside a string\n",
+        "\"\"\"\n",
+    ];
+
+    let (output_lines, applied_transforms) = clean_lines(
+        &input_lines,
+        &CleanOptions {
+            reindentation: Some(Reindentation::TabsToSpaces(4)),
+            ..CleanOptions::default()
+        },
+    );
+
+    assert_eq!(output_lines, expected_output_lines);
+    assert_eq!(applied_transforms.names(), vec!["indentation"]);
+}
+
+#[test]
+fn reindentation_leaves_string_literals_alone() {
+    let options = CleanOptions {
+        reindentation: Some(Reindentation::SpacesToTabs(4)),
+        ..CleanOptions::default()
+    };
+
+    assert_eq!(
+        clean_str("x = \"\"\"\n    keep\n\"\"\"\n", &options),
+        ("x = \"\"\"\n    keep\n\"\"\"\n".to_string(), false)
+    );
+    assert_eq!(
+        clean_str("MESSAGE=\"first\n    second\"\n    next\n", &options),
+        ("MESSAGE=\"first\n    second\"\n\tnext\n".to_string(), true)
+    );
+}
+
+#[test]
+fn spaces_to_tabs_keeps_the_width_of_mixed_indentation() {
+    let input_lines = [
+        "fn main() {\n",
+        "    let a = 1;\n",
+        "  \tlet b = 2;\n",
+        "\t      let c =    3;\n",
+        "\tlet d = 4;\n",
+        "}\n",
+    ];
+
+    let expected_output_lines = [
+        "fn main() {\n",
+        "\tlet a = 1;\n",
+        "\tlet b = 2;\n",
+        "\t\t  let c =    3;\n",
+        "\tlet d = 4;\n",
+        "}\n",
+    ];
+
+    let (output_lines, applied_transforms) = clean_lines(
+        &input_lines,
+        &CleanOptions {
+            reindentation: Some(Reindentation::SpacesToTabs(4)),
+            ..CleanOptions::default()
+        },
+    );
+
+    assert_eq!(output_lines, expected_output_lines);
+    assert_eq!(applied_transforms.names(), vec!["indentation"]);
+
+    let (output_lines, applied_transforms) = clean_lines(
+        &expected_output_lines,
+        &CleanOptions {
+            reindentation: Some(Reindentation::SpacesToTabs(4)),
+            ..CleanOptions::default()
+        },
+    );
+
+    assert_eq!(output_lines, expected_output_lines);
+    assert!(applied_transforms.names().is_empty());
+}
+
+#[test]
+fn line_ending_converges_mixed_endings() {
+    let input_lines = ["first\r\n", "second\n", "third\rfourth\r\n", "fifth"];
+
+    let (output_lines, applied_transforms) = clean_lines(
+        &input_lines,
+        &CleanOptions {
+            line_ending: Some(LineEnding::Lf),
+            ..CleanOptions::default()
+        },
+    );
+
+    assert_eq!(
+        output_lines,
+        vec!["first\n", "second\n", "third\n", "fourth\n", "fifth\n"]
+    );
+    assert!(applied_transforms.line_endings);
+
+    let crlf_options = CleanOptions {
+        line_ending: Some(LineEnding::Crlf),
+        ..CleanOptions::default()
+    };
+    let (output_lines, _) = clean_lines(&input_lines, &crlf_options);
+
+    assert_eq!(
+        output_lines,
+        vec![
+            "first\r\n",
+            "second\r\n",
+            "third\r\n",
+            "fourth\r\n",
+            "fifth\r\n"
+        ]
+    );
+
+    let crlf_lines: Vec<&str> = output_lines.iter().map(String::as_str).collect();
+    let (_, applied_transforms) = clean_lines(&crlf_lines, &crlf_options);
+
+    assert!(!applied_transforms.any());
+
+    let (output_lines, _) = clean_lines(&["old mac\r", "\r"], &crlf_options);
+
+    assert_eq!(output_lines, vec!["old mac\r\n"]);
+}
+
+#[test]
+fn keep_trailing_whitespace_on_last_line() {
+    let input_lines = ["first line   \n", "second line\t\n", "sentinel   "];
+
+    let expected_output_lines = ["first line\n", "second line\n", "sentinel   \n"];
+
+    let (output_lines, applied_transforms) = clean_lines(
+        &input_lines,
+        &CleanOptions {
+            keep_last_line_trailing: true,
+            ..CleanOptions::default()
+        },
+    );
+
+    assert_eq!(expected_output_lines.to_vec(), output_lines);
+    assert!(applied_transforms.any());
+
+    let input_lines = ["first line   \n", "sentinel   \n", "\n", "\n"];
+    let expected_output_lines = ["first line\n", "sentinel   \n"];
+
+    let (output_lines, _) = clean_lines(
+        &input_lines,
+        &CleanOptions {
+            keep_last_line_trailing: true,
+            ..CleanOptions::default()
+        },
+    );
+
+    assert_eq!(expected_output_lines.to_vec(), output_lines);
+}
+
+#[test]
+fn preserve_trailing_whitespace_on_bordered_comments() {
+    let input_lines = [
+        "# ===== Section =====   \n",
+        "// | boxed |  \n",
+        "# A normal comment   \n",
+        "let value = 1; // ==   \n",
+    ];
+
+    let expected_output_lines = [
+        "# ===== Section =====   \n",
+        "// | boxed |  \n",
+        "# A normal comment\n",
+        "let value = 1; // ==\n",
+    ];
+
+    let (output_lines, applied_transforms) = clean_lines(
+        &input_lines,
+        &CleanOptions {
+            aligned_comment_prefixes: vec!["#".to_string(), "//".to_string()],
+            ..CleanOptions::default()
+        },
+    );
+
+    assert_eq!(expected_output_lines.to_vec(), output_lines);
+    assert!(applied_transforms.any());
+}
+
+#[test]
+fn show_removed_escapes_the_trailing_bytes_of_each_line() {
+    let input_lines = ["value  \t\n", "clean\n", "crlf\r\n", " \n", "last \u{a0}"];
+    let (output_lines, _) = clean_lines(&input_lines, &CleanOptions::default());
+
+    assert_eq!(
+        format_removed_trailing(&input_lines, &output_lines),
+        concat!(
+            "    line 1: removed \"\\x20\\x20\\t\"\n",
+            "    line 3: removed \"\\r\"\n",
+            "    line 4: removed \"\\x20\"\n",
+            "    line 5: removed \"\\x20\\xc2\\xa0\"\n",
+        )
+    );
+}
+
+#[test]
+fn show_removed_pairs_lines_around_a_collapsed_blank_run() {
+    let input_lines = ["a  \n", "\n", " \n", "\n", "\n", "b   \n", "c \n"];
+    let (output_lines, _) = clean_lines(
+        &input_lines,
+        &CleanOptions {
+            max_consecutive_blank_lines: Some(1),
+            ..CleanOptions::default()
+        },
+    );
+
+    assert_eq!(output_lines, ["a\n", "\n", "b\n", "c\n"]);
+    assert_eq!(
+        format_removed_trailing(&input_lines, &output_lines),
+        concat!(
+            "    line 1: removed \"\\x20\\x20\"\n",
+            "    line 3: removed \"\\x20\"\n",
+            "    line 6: removed \"\\x20\\x20\\x20\"\n",
+            "    line 7: removed \"\\x20\"\n",
+        )
+    );
+}
+
+#[test]
+fn show_removed_includes_lines_that_were_also_reindented() {
+    let input_lines = ["\u{feff}\tvalue  \n", "\t\tnext\t\n", "done\n"];
+    let (output_lines, _) = clean_lines(
+        &input_lines,
+        &CleanOptions {
+            reindentation: Some(Reindentation::TabsToSpaces(4)),
+            strip_bom: true,
+            ..CleanOptions::default()
+        },
+    );
+
+    assert_eq!(output_lines, ["    value\n", "        next\n", "done\n"]);
+    assert_eq!(
+        format_removed_trailing(&input_lines, &output_lines),
+        concat!(
+            "    line 1: removed \"\\x20\\x20\"\n",
+            "    line 2: removed \"\\t\"\n",
+        )
+    );
+}
+
+#[test]
+fn comments_only_trims_just_comment_lines() {
+    let input_lines = [
+        "// A comment   \n",
+        "    // An indented comment\t\n",
+        "let value = 1;   \n",
+        "let other = 2; // trailing comment   \n",
+        "    \n",
+        "}",
+    ];
+
+    let expected_output_lines = [
+        "// A comment\n",
+        "    // An indented comment\n",
+        "let value = 1;   \n",
+        "let other = 2; // trailing comment   \n",
+        "    \n",
+        "}",
+    ];
+
+    let (output_lines, applied_transforms) = clean_lines(
+        &input_lines,
+        &CleanOptions {
+            comments_only_prefixes: Some(vec!["//".to_string()]),
+            ..CleanOptions::default()
+        },
+    );
+
+    assert_eq!(expected_output_lines.to_vec(), output_lines);
+    assert!(applied_transforms.trailing_whitespace);
+    assert!(!applied_transforms.final_newline);
+
+    let input_lines = ["let value = 1;   \n", "\n", "  \n"];
+    let (output_lines, applied_transforms) = clean_lines(
+        &input_lines,
+        &CleanOptions {
+            comments_only_prefixes: Some(vec!["//".to_string()]),
+            ..CleanOptions::default()
+        },
+    );
+
+    assert_eq!(input_lines.to_vec(), output_lines);
+    assert!(!applied_transforms.any());
+}
+
+#[test]
+fn transform_order_changes_which_lines_count_as_blank() {
+    let input_lines = ["a\n", "\n", "  \n", "\n", "b\n", "  \n"];
+
+    let clean_in_order = |transform_order: &str| {
+        clean_lines(
+            &input_lines,
+            &CleanOptions {
+                max_consecutive_blank_lines: Some(1),
+                transform_order: transform_order.parse().unwrap(),
+                ..CleanOptions::default()
+            },
+        )
+        .0
+    };
+
+    assert_eq!(
+        clean_in_order("trailing-whitespace,blank-line-runs,end-of-file"),
+        ["a\n", "\n", "b\n"]
+    );
+    assert_eq!(
+        clean_in_order("blank-line-runs,end-of-file"),
+        ["a\n", "\n", "  \n", "\n", "b\n", "  \n"]
+    );
+    assert_eq!(
+        clean_in_order("end-of-file,trailing-whitespace"),
+        ["a\n", "\n", "b\n", "  \n"]
+    );
+    assert!("end-of-file,unknown".parse::<TransformOrder>().is_err());
+    assert!("end-of-file,end-of-file".parse::<TransformOrder>().is_err());
+}
+
+#[test]
+fn eof_only_keeps_trailing_whitespace_on_lines() {
+    let input_lines = ["first line   \n", "\t\n", "last line\t", "\n", "  \n"];
+
+    let expected_output_lines = ["first line   \n", "\t\n", "last line\t\n"];
+
+    let (output_lines, applied_transforms) = clean_lines(
+        &input_lines,
+        &CleanOptions {
+            trim_trailing_whitespace: false,
+            ..CleanOptions::default()
+        },
+    );
+
+    assert_eq!(expected_output_lines.to_vec(), output_lines);
+    assert!(applied_transforms.any());
+
+    let input_lines = ["first line   \n", "last line\t"];
+    let expected_output_lines = ["first line   \n", "last line\t\n"];
+
+    let (output_lines, _) = clean_lines(
+        &input_lines,
+        &CleanOptions {
+            trim_trailing_whitespace: false,
+            ..CleanOptions::default()
+        },
+    );
+
+    assert_eq!(expected_output_lines.to_vec(), output_lines);
+}
+
+#[test]
+fn minimal_eof_only_trims_a_last_line_with_trailing_whitespace() {
+    let minimal_eof_options = CleanOptions {
+        minimal_eof: true,
+        ..CleanOptions::default()
+    };
+
+    let (output_lines, applied_transforms) = clean_lines(&["x   "], &minimal_eof_options);
+
+    assert_eq!(vec!["x"], output_lines);
+    assert_eq!(
+        applied_transforms,
+        AppliedTransforms {
+            trailing_whitespace: true,
+            ..AppliedTransforms::default()
+        }
+    );
+
+    let (output_lines, _) = clean_lines(&["first   \n", "x"], &minimal_eof_options);
+
+    assert_eq!(vec!["first\n", "x\n"], output_lines);
+
+    let (output_lines, _) = clean_lines(&["x   \n", "\n"], &minimal_eof_options);
+
+    assert_eq!(vec!["x\n"], output_lines);
+}
+
+#[test]
+fn preserve_trailing_whitespace_inside_quotes() {
+    let input_lines = [
+        "QUOTED=\"val \"\n",
+        "UNQUOTED=val   \n",
+        "MULTILINE=\"first   \n",
+        "second\"   \n",
+        "# A comment with a stray \"   \n",
+    ];
+
+    let expected_output_lines = [
+        "QUOTED=\"val \"\n",
+        "UNQUOTED=val\n",
+        "MULTILINE=\"first   \n",
+        "second\"\n",
+        "# A comment with a stray \"\n",
+    ];
+
+    let (output_lines, applied_transforms) = clean_lines(
+        &input_lines,
+        &CleanOptions {
+            preserve_quoted_trailing: true,
+            ..CleanOptions::default()
+        },
+    );
+
+    assert_eq!(expected_output_lines.to_vec(), output_lines);
+    assert!(applied_transforms.any());
+}
+
+#[test]
+fn preserve_trailing_whitespace_inside_python_strings() {
+    let input_lines = [
+        "def greet():   \n",
+        "    \"\"\"Greets the user.   \n",
+        "\n",
+        "    >>> greet()   \n",
+        "    'hello   '\n",
+        "    \"\"\"   \n",
+        "    text = '\"\"\"'   \n",
+        "    other = '''escaped \\''' quote   \n",
+        "    '''   \n",
+        "    return 'hello'   # \"\"\"   \n",
+    ];
+
+    let expected_output_lines = [
+        "def greet():\n",
+        "    \"\"\"Greets the user.   \n",
+        "\n",
+        "    >>> greet()   \n",
+        "    'hello   '\n",
+        "    \"\"\"\n",
+        "    text = '\"\"\"'\n",
+        "    other = '''escaped \\''' quote   \n",
+        "    '''\n",
+        "    return 'hello'   # \"\"\"\n",
+    ];
+
+    let (output_lines, applied_transforms) = clean_lines(
+        &input_lines,
+        &CleanOptions {
+            preserve_python_strings: true,
+            ..CleanOptions::default()
+        },
+    );
+
+    assert_eq!(expected_output_lines.to_vec(), output_lines);
+    assert!(applied_transforms.any());
+}
+
+#[test]
+fn clean_str_sets_the_byte_order_mark_aside() {
+    let options = CleanOptions {
+        reindentation: Some(Reindentation::TabsToSpaces(4)),
+        ..CleanOptions::default()
+    };
+
+    assert_eq!(
+        clean_str("\u{feff}\tx   \n", &options),
+        ("\u{feff}    x\n".to_string(), true)
+    );
+    assert_eq!(
+        clean_str("\u{feff}", &options),
+        ("\u{feff}".to_string(), false)
+    );
+
+    let options = CleanOptions {
+        strip_bom: true,
+        ..options
+    };
+
+    assert_eq!(
+        clean_str("\u{feff}\tx\n", &options),
+        ("    x\n".to_string(), true)
+    );
+    assert_eq!(clean_str("\u{feff}", &options), (String::new(), true));
+
+    let options = CleanOptions {
+        max_consecutive_blank_lines: Some(0),
+        strip_bom: false,
+        ..options
+    };
+
+    assert_eq!(
+        clean_str("\n\u{feff}\n", &options),
+        ("\u{feff}".to_string(), true)
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn writes_replace_the_file_and_keep_its_permissions() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let directory = create_test_directory(
+        "atomic-writes",
+        &[("script.sh", "echo hi   \n"), ("target.txt", "target   \n")],
+    );
+    let script_path = directory.join("script.sh");
+    let link_path = directory.join("link.txt");
+
+    fs::set_permissions(&script_path, fs::Permissions::from_mode(0o750)).unwrap();
+    std::os::unix::fs::symlink(directory.join("target.txt"), &link_path).unwrap();
+
+    write_clean_lines(&script_path, &["echo hi\n".to_string()], true).unwrap();
+    write_clean_lines(&link_path, &["target\n".to_string()], true).unwrap();
+
+    assert_eq!(fs::read_to_string(&script_path).unwrap(), "echo hi\n");
+    assert_eq!(
+        fs::metadata(&script_path).unwrap().permissions().mode() & 0o777,
+        0o750
+    );
+    assert!(fs::symlink_metadata(&link_path)
+        .unwrap()
+        .file_type()
+        .is_symlink());
+    assert_eq!(
+        fs::read_to_string(directory.join("target.txt")).unwrap(),
+        "target\n"
+    );
+
+    // Renaming a file over a directory fails after the temporary file has been written
+    let subdirectory_path = directory.join("subdirectory");
+    fs::create_dir(&subdirectory_path).unwrap();
+
+    assert!(write_clean_lines(&subdirectory_path, &["lost\n".to_string()], true).is_err());
+    assert!(subdirectory_path.is_dir());
+
+    let mut file_names: Vec<String> = fs::read_dir(&directory)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+        .collect();
+    file_names.sort();
+
+    assert_eq!(
+        file_names,
+        vec!["link.txt", "script.sh", "subdirectory", "target.txt"]
+    );
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn streaming_matches_in_memory_cleaning() {
+    let inputs = [
+        "trailing   \n\tindented\t\n\n  \nlast   ",
+        "KEY=\"value   \nstill quoted   \"   \n\n\n",
+        "# ==== Banner ====   \nsentinel   ",
+        "",
+        "   ",
+    ];
+    let options = [
+        CleanOptions::default(),
+        CleanOptions {
+            preserve_quoted_trailing: true,
+            keep_last_line_trailing: true,
+            ..CleanOptions::default()
+        },
+        CleanOptions {
+            normalize_eof_newlines: false,
+            aligned_comment_prefixes: vec!["#".to_string()],
+            ..CleanOptions::default()
+        },
+        CleanOptions {
+            minimal_eof: true,
+            ..CleanOptions::default()
+        },
+    ];
+
+    for input in inputs {
+        for clean_options in &options {
+            let lines: Vec<&str> = input.split_inclusive('\n').collect();
+            let (cleaned_lines, applied_transforms) = clean_lines(&lines, clean_options);
+
+            let mut streamed_output = Vec::new();
+            let file_stats =
+                stream_clean_lines(input.as_bytes(), &mut streamed_output, clean_options, 1024)
+                    .unwrap();
+
+            assert_eq!(
+                String::from_utf8(streamed_output).unwrap(),
+                cleaned_lines.concat()
+            );
+            assert_eq!(
+                file_stats.map(|file_stats| file_stats.lines_changed),
+                applied_transforms
+                    .any()
+                    .then(|| diff::changed_line_count(&lines, &cleaned_lines))
+            );
+        }
+    }
+}
+
+#[test]
+fn whitespace_issues_cover_removed_and_reindented_lines() {
+    let lines = ["\tif ready {\n", "\n", "\n", "\n", "}\n", "\n", "\n"];
+    let clean_lines: Vec<String> = ["    if ready {\n", "\n", "}\n"]
+        .iter()
+        .map(|line| line.to_string())
+        .collect();
+
+    assert_eq!(
+        whitespace_issues(&lines, &clean_lines),
+        vec![
+            WhitespaceIssue {
+                message: "Indentation differs",
+                start: (1, 1),
+                end: (2, 1),
+                replacement: "    if ready {\n".to_string(),
+            },
+            WhitespaceIssue {
+                message: "Extra blank lines",
+                start: (2, 1),
+                end: (4, 1),
+                replacement: String::new(),
+            },
+            WhitespaceIssue {
+                message: "Extra blank lines",
+                start: (6, 1),
+                end: (8, 1),
+                replacement: String::new(),
+            },
+        ]
+    );
+}
+
+#[test]
+fn clean_str_and_clean_file_report_whether_anything_changed() {
+    let options = CleanOptions {
+        normalize_eof_newlines: false,
+        ..CleanOptions::default()
+    };
+
+    assert_eq!(
+        clean_str("kept  \nlast", &options),
+        ("kept\nlast".to_string(), true)
+    );
+    assert_eq!(
+        clean_str("kept\n", &CleanOptions::default()),
+        ("kept\n".to_string(), false)
+    );
+
+    let directory = create_test_directory("library", &[("main.rs", "fn main() {}   \n\n")]);
+    let path = directory.join("main.rs");
+
+    assert!(clean_file(&path, &CleanOptions::default()).unwrap());
+    assert!(!clean_file(&path, &CleanOptions::default()).unwrap());
+    assert_eq!(fs::read_to_string(&path).unwrap(), "fn main() {}\n");
+
+    let error = clean_file(&directory.join("missing.rs"), &CleanOptions::default()).unwrap_err();
+
+    assert!(matches!(error, LinemanFileError::FileNotOpened));
+    assert_eq!(
+        error.to_string(),
+        "The file could not be read as UTF-8 text"
+    );
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn verify_leaves_files_with_non_whitespace_changes_untouched() {
+    let directory = create_test_directory("verify", &[("file.txt", "let value = 1;   \n")]);
+    let path = directory.join("file.txt");
+
+    let deletes_a_character = |lines: &[&str], _: &CleanOptions| {
+        let cleaned_lines: Vec<String> = lines
+            .iter()
+            .map(|line| line.trim_end().replacen(';', "", 1) + "\n")
+            .collect();
+
+        let applied_transforms = AppliedTransforms {
+            trailing_whitespace: true,
+            ..AppliedTransforms::default()
+        };
+
+        (cleaned_lines, applied_transforms)
+    };
+
+    assert!(matches!(
+        clean_file_with(
+            &path,
+            &CleanOptions::default(),
+            WriteMode::Write,
+            CleanFileFlags {
+                verify: true,
+                ..CleanFileFlags::default()
+            },
+            deletes_a_character,
+            |path: &Path, clean_lines: &[String]| write_clean_lines(path, clean_lines, true)
+        ),
+        Err(LinemanFileError::CleaningNotVerified)
+    ));
+    assert_eq!(fs::read_to_string(&path).unwrap(), "let value = 1;   \n");
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn verify_writes_detects_a_corrupted_write() {
+    let directory = create_test_directory("verify_writes", &[("file.txt", "let value = 1;   \n")]);
+    let path = directory.join("file.txt");
+
+    let drops_the_last_byte = |path: &Path, clean_lines: &[String]| {
+        let clean_string = clean_lines.concat();
+        fs::write(path, &clean_string[..clean_string.len() - 1])
+    };
+
+    let verify_writes = CleanFileFlags {
+        verify_writes: true,
+        ..CleanFileFlags::default()
+    };
+
+    assert!(matches!(
+        clean_file_with(
+            &path,
+            &CleanOptions::default(),
+            WriteMode::Write,
+            verify_writes,
+            clean_lines,
+            drops_the_last_byte
+        ),
+        Err(LinemanFileError::WriteNotVerified)
+    ));
+    assert!(matches!(
+        clean_file_with(
+            &path,
+            &CleanOptions::default(),
+            WriteMode::Write,
+            verify_writes,
+            clean_lines,
+            |path: &Path, clean_lines: &[String]| write_clean_lines(path, clean_lines, true)
+        ),
+        Ok(Some(_))
+    ));
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn self_check_fails_a_transform_that_is_not_idempotent() {
+    let directory = create_test_directory("self_check", &[("file.txt", "let value = 1;   \n")]);
+    let path = directory.join("file.txt");
+
+    let removes_one_space = |lines: &[&str], _: &CleanOptions| {
+        let cleaned_lines: Vec<String> = lines
+            .iter()
+            .map(|line| match line.strip_suffix(" \n") {
+                Some(line_content) => format!("{}\n", line_content),
+                None => line.to_string(),
+            })
+            .collect();
+
+        let applied_transforms = AppliedTransforms {
+            trailing_whitespace: cleaned_lines != lines,
+            ..AppliedTransforms::default()
+        };
+
+        (cleaned_lines, applied_transforms)
+    };
+
+    assert!(matches!(
+        clean_file_with(
+            &path,
+            &CleanOptions::default(),
+            WriteMode::Write,
+            CleanFileFlags {
+                self_check: true,
+                ..CleanFileFlags::default()
+            },
+            removes_one_space,
+            |path: &Path, clean_lines: &[String]| write_clean_lines(path, clean_lines, true)
+        ),
+        Err(LinemanFileError::SelfCheckFailed)
+    ));
+    assert_eq!(fs::read_to_string(&path).unwrap(), "let value = 1;   \n");
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn trimming_keeps_multi_byte_characters_intact() {
+    let input_lines = [
+        "café   \n",
+        "日本語\t \n",
+        "naïve e\u{301}  \n",
+        "emoji 🦀\u{3000}\n",
+        "ends in é",
+    ];
+
+    let expected_output_lines = [
+        "café\n",
+        "日本語\n",
+        "naïve e\u{301}\n",
+        "emoji 🦀\n",
+        "ends in é\n",
+    ];
+
+    let (output_lines, applied_transforms) = clean_lines(&input_lines, &CleanOptions::default());
+
+    assert_eq!(expected_output_lines.to_vec(), output_lines);
+    assert!(applied_transforms.any());
+}
+
+#[test]
+fn clean_lines_handles_arbitrary_utf8() {
+    let alphabet = [
+        'a', 'é', '日', '🦀', '\u{301}', ' ', '\t', '\r', '\n', '\u{a0}', '\u{3000}', '\u{feff}',
+        '"', '#', '/',
+    ];
+    let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+    let mut next_random = || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+
+    for _ in 0..2000 {
+        let length = (next_random() % 40) as usize;
+        let input: String = (0..length)
+            .map(|_| alphabet[(next_random() % alphabet.len() as u64) as usize])
+            .collect();
+        let input_lines: Vec<&str> = input.split_inclusive('\n').collect();
+
+        let options = CleanOptions {
+            preserve_quoted_trailing: next_random() % 2 == 0,
+            normalize_eof_newlines: next_random() % 2 == 0,
+            line_ending: [None, Some(LineEnding::Lf), Some(LineEnding::Crlf)]
+                [(next_random() % 3) as usize],
+            max_consecutive_blank_lines: Some((next_random() % 3) as usize),
+            transform_order: [
+                "trailing-whitespace,blank-line-runs,end-of-file",
+                "trailing-whitespace,end-of-file,blank-line-runs",
+                "blank-line-runs,trailing-whitespace,end-of-file",
+                "blank-line-runs,end-of-file,trailing-whitespace",
+                "end-of-file,trailing-whitespace,blank-line-runs",
+                "end-of-file,blank-line-runs,trailing-whitespace",
+            ][(next_random() % 6) as usize]
+                .parse()
+                .unwrap(),
+            ..CleanOptions::default()
+        };
+
+        let (output_lines, _) = clean_lines(&input_lines, &options);
+        let output = output_lines.concat();
+
+        assert_eq!(
+            clean_lines(&output.split_inclusive('\n').collect::<Vec<_>>(), &options)
+                .0
+                .concat(),
+            output,
+            "cleaning {:?} twice changed it again",
+            input
+        );
+    }
+}
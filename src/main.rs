@@ -1,319 +1,6029 @@
+mod config;
+mod glob;
+mod ignore;
+mod json;
+mod profiles;
+mod sha256;
+
+use config::ConfigCache;
+use glob::{Glob, GlobSet};
+use ignore::IgnoreCache;
+use lineman::{
+    clean_file_streaming, clean_file_with_flags, clean_str, CleanFileFlags, CleanOptions,
+    FileStats, LineEnding, LinemanFileError, Reindentation, TransformOrder, WriteMode,
+    STREAM_BUFFER_SIZE,
+};
+use profiles::IndentStyle;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::ffi::OsStr;
+use std::fmt;
 use std::fs::{self, File};
-use std::io::prelude::*;
-use std::path::{Path, PathBuf};
+use std::io::{self, prelude::*, BufReader, BufWriter};
+use std::path::{Component, Path, PathBuf};
+use std::process::{self, Command};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
 use structopt::StructOpt;
 use walkdir::{Error, WalkDir};
 
 #[derive(StructOpt, Debug)]
 #[structopt(name = "lineman")]
 struct LinemanArgs {
-    /// The root path from which to begin processing
-    #[structopt(short, long)]
-    path: PathBuf,
+    /// The root path from which to begin processing, which can be repeated to process several roots
+    #[structopt(
+        short,
+        long,
+        required_unless_one = &["serve", "merge-reports", "files", "stdin"],
+        number_of_values = 1
+    )]
+    path: Vec<PathBuf>,
 
-    /// A list of file extensions that dictates which files are processed
-    #[structopt(short, long)]
+    /// Files to process instead of walking the root, each of which has to exist. Globs are
+    /// matched relative to `--path` when it's given, and to the working directory otherwise
+    #[structopt(parse(from_os_str), conflicts_with = "input-list-json")]
+    files: Vec<PathBuf>,
+
+    /// A comma-separated list of file extensions that dictates which files are processed, which
+    /// can also be repeated
+    #[structopt(short, long, use_delimiter = true, require_delimiter = true)]
     extensions: Option<Vec<String>>,
 
+    /// A comma-separated list of file extensions that are skipped, even when they're listed in
+    /// `--extensions`
+    #[structopt(long, use_delimiter = true, require_delimiter = true)]
+    exclude_extensions: Vec<String>,
+
     /// Disables EOF newline normalization
     #[structopt(short, long)]
     disable_eof_newline_normalization: bool,
+
+    /// Disables EOF newline normalization for files with these comma-separated extensions only
+    #[structopt(long, use_delimiter = true, require_delimiter = true)]
+    no_final_newline_extensions: Vec<String>,
+
+    /// Prints a unified diff of the changes instead of writing them to the files
+    #[structopt(long)]
+    diff: bool,
+
+    /// The number of unchanged lines shown around each change when using `--diff`
+    #[structopt(long, default_value = "3")]
+    diff_context: usize,
+
+    /// Only processes files whose path, relative to the root, matches one of these globs
+    #[structopt(long, number_of_values = 1)]
+    include: Vec<Glob>,
+
+    /// Skips files whose path, relative to the root, matches one of these globs, even when they
+    /// match `--include`
+    #[structopt(long, number_of_values = 1)]
+    exclude: Vec<Glob>,
+
+    /// Walks into directories and files that `.gitignore`, `.ignore` and the global git excludes
+    /// ignore, and into `.git`, which are all skipped by default
+    #[structopt(long)]
+    no_ignore: bool,
+
+    /// Breaks the report down into matched files that needed cleaning, matched files that were
+    /// already clean, and files excluded by the filters
+    #[structopt(long)]
+    explain: bool,
+
+    /// A command run after each file is cleaned, where `{}` is replaced with the file's path, e.g.
+    /// `--post-command 'rustfmt {}'`
+    #[structopt(long)]
+    post_command: Option<String>,
+
+    /// Skips files whose first few KB look like compressed or encrypted data, judged by their
+    /// Shannon entropy
+    #[structopt(long)]
+    skip_high_entropy: bool,
+
+    /// The entropy, in bits per byte, above which `--skip-high-entropy` skips a file
+    #[structopt(long, default_value = "7.5")]
+    entropy_threshold: f64,
+
+    /// Rewrites files with more than one hardlink in place, keeping their inode, and lists them in
+    /// the report
+    #[structopt(long)]
+    preserve_hardlinks: bool,
+
+    /// Keeps trailing whitespace that sits inside a quoted value, as in `KEY="value "`, while still
+    /// trimming outside the quotes. Always on for `.env` files
+    #[structopt(long)]
+    preserve_quoted_trailing: bool,
+
+    /// Writes flat `key=value` run metrics to this file, for CI metric collectors
+    #[structopt(long)]
+    metrics_file: Option<PathBuf>,
+
+    /// A file of SHA-256 content hashes, one per line as printed by `sha256sum`. Files whose
+    /// content matches one of them are never modified
+    #[structopt(long)]
+    protect_hashes: Option<PathBuf>,
+
+    /// Keeps trailing whitespace inside multi-line triple-quoted strings in Python files, where
+    /// it can matter to doctests
+    #[structopt(long)]
+    preserve_python_strings: bool,
+
+    /// The report format: `text` (or `human`), `json` for one JSON document printed at the end,
+    /// `jsonl` to stream one JSON object per file as it is processed, followed by a summary
+    /// object, or `rdjson` to print reviewdog diagnostics for every whitespace issue without
+    /// cleaning anything
+    #[structopt(long, default_value = "text")]
+    format: ReportFormat,
+
+    /// Also writes the report to this file, in `--report-file-format`, so a run can log a human
+    /// report and keep a JSON artifact at the same time
+    #[structopt(long)]
+    report_file: Option<PathBuf>,
+
+    /// The format of `--report-file`, which is independent of `--format`
+    #[structopt(long, default_value = "jsonl")]
+    report_file_format: ReportFormat,
+
+    /// Leaves trailing whitespace on the last line of content untouched, for files that use it as a
+    /// sentinel. EOF newline normalization still applies
+    #[structopt(long)]
+    keep_last_line_trailing: bool,
+
+    /// Prints only a summary of what cleaning would change, in total and by extension, without
+    /// writing any files
+    #[structopt(long)]
+    estimate: bool,
+
+    /// Follows symlinks while walking the root. Targets outside the root are still skipped unless
+    /// `--allow-outside-root` is passed
+    #[structopt(long)]
+    follow_symlinks: bool,
+
+    /// Skips symlinks whose target resolves outside the root. On by default with
+    /// `--follow-symlinks`
+    #[structopt(long)]
+    stay_within_root: bool,
+
+    /// Lets `--follow-symlinks` clean symlink targets outside the root
+    #[structopt(long)]
+    allow_outside_root: bool,
+
+    /// Checks that cleaning changed nothing but whitespace before writing each file, and leaves the
+    /// file untouched and reports it otherwise
+    #[structopt(long)]
+    verify: bool,
+
+    /// Reports the files that need cleaning without writing them, and exits with a nonzero status
+    /// if there are any. Files missing a final newline are also listed on their own
+    #[structopt(long)]
+    check: bool,
+
+    /// Only processes files inside a directory, below the root, whose name matches one of these
+    /// globs, e.g. `--dir-glob migrations`
+    #[structopt(long, number_of_values = 1)]
+    dir_glob: Vec<Glob>,
+
+    /// With `--check`, prints a single line saying how many files need cleaning and the command
+    /// that cleans them, instead of the report, for minimal pre-commit hook output
+    #[structopt(long, requires = "check")]
+    hook_message: bool,
+
+    /// Keeps trailing whitespace on comment-only lines that end in a border, such as
+    /// `# ==== Section ====` or `// | boxed |`, for ASCII-art headers
+    #[structopt(long)]
+    preserve_aligned_comments: bool,
+
+    /// Only trims trailing whitespace on lines that are entirely a comment, leaving every other line
+    /// and the end of the file as they are, for a low-risk first pass over a legacy codebase
+    #[structopt(long)]
+    comments_only: bool,
+
+    /// The comment prefixes recognized by `--preserve-aligned-comments` and `--comments-only`,
+    /// separated by commas
+    #[structopt(
+        long,
+        default_value = "//,#,--,;",
+        use_delimiter = true,
+        require_delimiter = true
+    )]
+    comment_prefixes: Vec<String>,
+
+    /// Lists the tree until no entry vanishes between being listed and being looked at, as happens
+    /// while files are still landing in a checkout, before cleaning it in a single walk
+    #[structopt(long)]
+    stable: bool,
+
+    /// The most times `--stable` lists the tree again before cleaning it anyway
+    #[structopt(long, default_value = "3")]
+    stable_retries: usize,
+
+    /// Cleans the tree again and again until a pass changes nothing, failing if that takes more
+    /// than `--until-stable-max-passes` passes, as cleaning should always be idempotent
+    #[structopt(long, conflicts_with_all = &["check", "diff", "estimate", "expect-line-ending"])]
+    until_stable: bool,
+
+    /// The most passes `--until-stable` makes, including the final one that changes nothing
+    #[structopt(long, default_value = "5")]
+    until_stable_max_passes: usize,
+
+    /// A file holding a JSON array of paths to process instead of walking the root. Paths that
+    /// don't exist are reported rather than failing the run
+    #[structopt(long)]
+    input_list_json: Option<PathBuf>,
+
+    /// Only normalizes the end of the file, adding a final newline and removing trailing blank
+    /// lines, and leaves trailing whitespace on the other lines alone
+    #[structopt(long, conflicts_with = "disable-eof-newline-normalization")]
+    eof_only: bool,
+
+    /// Lists the transforms that changed each cleaned file in the report, e.g.
+    /// `trailing-whitespace, final-newline`
+    #[structopt(long)]
+    explain_changes: bool,
+
+    /// Processes files in sorted path order, so the files done before an interrupt or failure
+    /// are the same from run to run
+    #[structopt(long)]
+    sorted: bool,
+
+    /// Doesn't add a final newline to a last line that is only changed for its trailing
+    /// whitespace, so `"x   "` becomes `"x"` rather than `"x\n"`
+    #[structopt(long)]
+    minimal_eof: bool,
+
+    /// The number of indent characters used for each level of the text report
+    #[structopt(long, default_value = "4")]
+    report_indent: usize,
+
+    /// The character the text report is indented with
+    #[structopt(long, default_value = " ")]
+    report_indent_char: char,
+
+    /// Nests the files in each section of the text report under their directories, indenting
+    /// one more level per directory
+    #[structopt(long)]
+    group_by_directory: bool,
+
+    /// Always prints the "Cleaned Files:", "Skipped Files:" and "Walkdir Errors:" headers, even
+    /// when a section has no entries, so scripts can parse the report without special cases
+    #[structopt(long)]
+    report_empty: bool,
+
+    /// Directories, relative to the root, where a file missing a final newline fails the run with
+    /// a nonzero exit status. Files are still cleaned everywhere
+    #[structopt(long, number_of_values = 1)]
+    enforce_final_newline_in: Vec<PathBuf>,
+
+    /// The most bytes of a file that may be held in memory. Larger files are cleaned line by line,
+    /// and files that can't be cleaned within the budget are skipped
+    #[structopt(long)]
+    memory_budget: Option<usize>,
+
+    /// Reports paths relative to the common ancestor of every root, so files from different roots
+    /// can still be told apart
+    #[structopt(long)]
+    relative: bool,
+
+    /// Only reports files with a line ending other than this one, `lf` or `crlf`, along with how
+    /// many of each ending they contain, without cleaning anything
+    #[structopt(long)]
+    expect_line_ending: Option<LineEnding>,
+
+    /// Ends every line with this line ending, `lf` or `crlf`, converting `\r\n`, `\n` and lone
+    /// `\r` endings to it. Overrides the line ending of `--preset`
+    #[structopt(long)]
+    line_ending: Option<LineEnding>,
+
+    /// Prints the exact trailing bytes removed from each changed line, escaped as in
+    /// `line 12: removed "\x20\x20\t"`, which also works with `--check` and `--estimate`
+    #[structopt(long)]
+    show_removed: bool,
+
+    /// Only processes files tracked by git, as listed by `git ls-files`, so untracked scratch and
+    /// generated files are left alone
+    #[structopt(long)]
+    tracked_only: bool,
+
+    /// Keeps a single trailing blank line, when there is one, in files whose path relative to the
+    /// root matches one of these globs, e.g. `--keep-one-trailing-blank-for '*.csv'`
+    #[structopt(long, number_of_values = 1)]
+    keep_one_trailing_blank_for: Vec<Glob>,
+
+    /// Serves cleaning requests from an editor instead of walking a root, reading one JSON request
+    /// `{"content": ..., "filename": ...}` per line of stdin and answering each with one
+    /// `{"cleaned": ..., "changed": ...}` line on stdout, until stdin closes
+    #[structopt(long)]
+    serve: bool,
+
+    /// Cleans the content read from stdin and writes it to stdout instead of walking a root, as
+    /// `--path -` does. Only the cleaned content is written, without a report
+    #[structopt(long)]
+    stdin: bool,
+
+    /// Attributes each line with trailing whitespace to the author who last touched it, using
+    /// `git blame`, and reports how many lines each author has, without cleaning anything
+    #[structopt(long)]
+    blame: bool,
+
+    /// Skips syncing each written file to disk, leaving it to the OS to write the data back. A
+    /// crash soon after a run can then lose cleaned files, or leave them empty
+    #[structopt(long)]
+    no_fsync: bool,
+
+    /// Skips syncing each written file, as `--no-fsync` does, and syncs every filesystem that was
+    /// written to once all files are written instead, so the run as a whole is durable
+    #[structopt(long)]
+    fsync_at_end: bool,
+
+    /// Reads each file back after writing it and reports a write verification failure if it
+    /// doesn't hold exactly the cleaned content, to catch silent write failures
+    #[structopt(long)]
+    verify_writes: bool,
+
+    /// Skips files with a line longer than this many bytes, found by scanning the file before it
+    /// is read into memory, to protect against minified or corrupt files
+    #[structopt(long)]
+    max_line_bytes: Option<usize>,
+
+    /// Merges reports written with `--format jsonl`, such as those of parallel CI shards, into one
+    /// report printed in the same format, without cleaning anything
+    #[structopt(long, number_of_values = 1)]
+    merge_reports: Vec<PathBuf>,
+
+    /// A named set of cleaning options, `strict` or `minimal`, that individual flags can still
+    /// override. See the README for the exact settings of each
+    #[structopt(long)]
+    preset: Option<Preset>,
+
+    /// Reports the share of processed files that were already clean, as a single percentage that
+    /// can be tracked over time
+    #[structopt(long)]
+    health: bool,
+
+    /// The order the cleaning transforms run in, as a comma-separated list of `trailing-whitespace`,
+    /// `blank-line-runs` and `end-of-file`. Transforms left out run afterwards in this default order
+    #[structopt(
+        long,
+        default_value = "trailing-whitespace,blank-line-runs,end-of-file"
+    )]
+    transform_order: TransformOrder,
+
+    /// Skips files that another process has locked, such as an editor holding them open, and
+    /// reports them as in use instead of racing it
+    #[structopt(long)]
+    skip_locked: bool,
+
+    /// Only adds a missing final newline to files with at least this many lines, leaving the end
+    /// of shorter files, such as one-line snippets, as it is
+    #[structopt(long, default_value = "0")]
+    final_newline_min_lines: usize,
+
+    /// Only trims the trailing whitespace of lines with more than this many trailing whitespace
+    /// characters, so a threshold can be lowered over time. The end of the file is cleaned as usual
+    #[structopt(long, default_value = "0")]
+    min_trailing: usize,
+
+    /// Applies built-in per-language defaults to each file by its extension, such as expanding
+    /// leading tabs in Python files while leaving them alone in Go files and Makefiles. Explicit
+    /// flags and `.lineman.toml` configs still win
+    #[structopt(long)]
+    language_profiles: bool,
+
+    /// Expands the tabs in each line's leading indentation to the next multiple of this many
+    /// columns, leaving tabs after the indentation alone
+    #[structopt(long, conflicts_with = "spaces-to-tabs")]
+    tabs_to_spaces: Option<usize>,
+
+    /// Rewrites each line's leading indentation as tabs of this many columns, keeping any
+    /// remainder narrower than a tab as spaces
+    #[structopt(long)]
+    spaces_to_tabs: Option<usize>,
+
+    /// Collapses every run of more than this many blank lines, anywhere in the file, down to this
+    /// many. Overrides the limit of `--preset`
+    #[structopt(long)]
+    max_consecutive_blank_lines: Option<usize>,
+
+    /// Cleans each cleaned file's output a second time and fails the file, leaving it unchanged,
+    /// if that changes anything, as a guard against a transform that isn't idempotent
+    #[structopt(long)]
+    self_check: bool,
+
+    /// Cleans files that look binary, which are skipped by default. A file looks binary when the
+    /// start of it holds a NUL byte or mostly control bytes
+    #[structopt(long)]
+    no_skip_binary: bool,
+
+    /// Prints one line tallying the skipped files by reason, such as `Skipped: 3 binary, 1 in use`,
+    /// in place of the lists of skipped files
+    #[structopt(long)]
+    skip_summary: bool,
+
+    /// Still lists every skipped file under its reason when using `--skip-summary`
+    #[structopt(long)]
+    verbose: bool,
+
+    /// Removes the UTF-8 byte order mark from the start of files that have one, which is kept by
+    /// default
+    #[structopt(long)]
+    strip_bom: bool,
 }
 
-#[derive(Debug)]
 enum LinemanApplicationError {
     InvalidRootPath(String),
+    InvalidFilePath(String),
+    MetricsNotWritten(String),
+    ProtectedHashesNotRead(String),
+    ConfigNotRead(String),
+    InputListNotRead(String),
+    TreeNotStable(String),
+    TrackedFilesNotListed(String),
+    ReportsNotMerged(String),
+    ReportFileNotWritten(String),
+    StdinNotCleaned(String),
+    FilesNotSynced(String),
 }
 
-enum LinemanFileError {
+impl fmt::Debug for LinemanApplicationError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LinemanApplicationError::InvalidRootPath(message)
+            | LinemanApplicationError::InvalidFilePath(message)
+            | LinemanApplicationError::MetricsNotWritten(message)
+            | LinemanApplicationError::ProtectedHashesNotRead(message)
+            | LinemanApplicationError::ConfigNotRead(message)
+            | LinemanApplicationError::InputListNotRead(message)
+            | LinemanApplicationError::TreeNotStable(message)
+            | LinemanApplicationError::TrackedFilesNotListed(message)
+            | LinemanApplicationError::ReportsNotMerged(message)
+            | LinemanApplicationError::ReportFileNotWritten(message)
+            | LinemanApplicationError::StdinNotCleaned(message)
+            | LinemanApplicationError::FilesNotSynced(message) => {
+                write!(formatter, "{}", message)
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct Report {
+    cleaned_files: Vec<(PathBuf, FileStats)>,
+    skipped_files: Vec<(PathBuf, SkipReason)>,
+    // How many lines with trailing whitespace each author last touched, with `--blame`
+    trailing_whitespace_authors: BTreeMap<String, usize>,
+    walk_dir_errors: Vec<Error>,
+    // Only returned as an error once the report is out, as every file has been written by then
+    sync_failure: Option<String>,
+    already_clean_file_paths: Vec<PathBuf>,
+    excluded_file_paths: Vec<PathBuf>,
+    post_command_failures: Vec<(PathBuf, String)>,
+    high_entropy_file_paths: Vec<PathBuf>,
+    hardlinked_file_paths: Vec<PathBuf>,
+    protected_file_paths: Vec<PathBuf>,
+    outside_root_file_paths: Vec<PathBuf>,
+    verification_failed_file_paths: Vec<PathBuf>,
+    write_verification_failed_file_paths: Vec<PathBuf>,
+    self_check_failed_file_paths: Vec<PathBuf>,
+    missing_final_newline_file_paths: Vec<PathBuf>,
+    missing_file_paths: Vec<PathBuf>,
+    over_memory_budget_file_paths: Vec<PathBuf>,
+    untracked_file_paths: Vec<PathBuf>,
+    line_too_long_file_paths: Vec<PathBuf>,
+    in_use_file_paths: Vec<PathBuf>,
+    binary_file_paths: Vec<PathBuf>,
+    final_newline_violation_file_paths: Vec<PathBuf>,
+    unexpected_line_ending_files: Vec<(PathBuf, LineEndingCounts)>,
+    keep_unchanged_file_paths: bool,
+    check_mode: bool,
+    // Nothing is written, so the cleaned files are only those that would be cleaned
+    dry_run: bool,
+    explain_changes: bool,
+    layout: ReportLayout,
+    totals: ReportTotals,
+    interrupted: bool,
+    walk_retries: usize,
+    passes: usize,
+    reports_health: bool,
+    // Kept for a JSON lines `--report-file`, in the order they would have been streamed
+    json_lines: Option<Vec<String>>,
+    // A text `--report-file` needs the path lists even while the console report is streamed
+    keeps_path_lists_while_streaming: bool,
+}
+
+// How the text report lays out its path lists
+struct ReportLayout {
+    indent: String,
+    group_by_directory: bool,
+    shows_empty_sections: bool,
+    lists_skipped_files: bool,
+}
+
+// Kept alongside the path lists, so totals are still available when the report is streamed and the
+// lists stay empty
+#[derive(Default)]
+struct ReportTotals {
+    files_cleaned: usize,
+    lines_changed: usize,
+    bytes_saved: i64,
+    files_already_clean: usize,
+    files_excluded: usize,
+    files_skipped: usize,
+    files_skipped_not_opened: usize,
+    files_skipped_high_entropy: usize,
+    files_skipped_protected: usize,
+    files_skipped_outside_root: usize,
+    verification_failures: usize,
+    write_verification_failures: usize,
+    self_check_failures: usize,
+    files_missing: usize,
+    files_skipped_over_memory_budget: usize,
+    files_skipped_untracked: usize,
+    files_skipped_line_too_long: usize,
+    files_skipped_in_use: usize,
+    files_skipped_binary: usize,
+    final_newline_violations: usize,
+    unexpected_line_endings: usize,
+    post_command_failures: usize,
+    walk_errors: usize,
+}
+
+enum FileOutcome {
+    Cleaned {
+        file_stats: FileStats,
+        hardlinked: bool,
+        post_command_failure: Option<String>,
+        violates_final_newline_policy: bool,
+        trailing_whitespace_authors: Vec<(String, usize)>,
+    },
+    AlreadyClean,
+    Excluded,
+    Skipped(SkipReason),
+    SkippedHighEntropy,
+    SkippedProtected,
+    SkippedOutsideRoot,
+    FailedVerification,
+    FailedWriteVerification,
+    FailedSelfCheck,
+    Missing,
+    SkippedOverMemoryBudget,
+    SkippedUntracked,
+    SkippedLineTooLong,
+    SkippedInUse,
+    SkippedBinary,
+    UnexpectedLineEnding(LineEndingCounts),
+}
+
+// Why a file that was going to be cleaned couldn't be
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum SkipReason {
     FileNotOpened,
     FileNotCleaned,
 }
 
-fn main() -> Result<(), LinemanApplicationError> {
-    let mut cleaned_file_paths: Vec<PathBuf> = Vec::new();
-    let mut skipped_file_paths: Vec<PathBuf> = Vec::new();
-    let mut walk_dir_errors: Vec<Error> = Vec::new();
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Preset {
+    Strict,
+    Minimal,
+}
 
-    let args = LinemanArgs::from_args();
-    let root_path = args.path;
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct LineEndingCounts {
+    lf: usize,
+    crlf: usize,
+}
 
-    if !root_path.is_dir() {
-        return Err(LinemanApplicationError::InvalidRootPath(
-            "The provided path is not a valid directory".to_string(),
-        ));
+// State derived from the arguments once per run and shared by every file
+struct RunContext {
+    protected_hashes: HashSet<String>,
+    path_filters: PathFilters,
+    roots: Vec<WalkRoot>,
+    relative_base_path: Option<PathBuf>,
+    input_paths: Option<Vec<PathBuf>>,
+}
+
+// The glob options, compiled into sets once so each file is matched by lookups
+struct PathFilters {
+    include: GlobSet,
+    exclude: GlobSet,
+    dir_glob: GlobSet,
+    keep_one_trailing_blank_for: GlobSet,
+}
+
+struct WalkRoot {
+    path: PathBuf,
+    canonical_path: Option<PathBuf>,
+    tracked_paths: Option<HashSet<PathBuf>>,
+}
+
+#[derive(Debug, PartialEq)]
+enum ReportFormat {
+    Text,
+    Json,
+    JsonLines,
+    Rdjson,
+}
+
+impl FromStr for ReportFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "text" | "human" => Ok(ReportFormat::Text),
+            "json" => Ok(ReportFormat::Json),
+            "jsonl" => Ok(ReportFormat::JsonLines),
+            "rdjson" => Ok(ReportFormat::Rdjson),
+            _ => Err(format!("Unknown report format: {}", format)),
+        }
+    }
+}
+
+impl FromStr for Preset {
+    type Err = String;
+
+    fn from_str(preset: &str) -> Result<Self, Self::Err> {
+        match preset {
+            "strict" => Ok(Preset::Strict),
+            "minimal" => Ok(Preset::Minimal),
+            _ => Err(format!("Unknown preset: {}", preset)),
+        }
+    }
+}
+
+impl Preset {
+    fn clean_options(self) -> CleanOptions {
+        match self {
+            Preset::Strict => CleanOptions {
+                line_ending: Some(LineEnding::Lf),
+                max_consecutive_blank_lines: Some(1),
+                ..CleanOptions::default()
+            },
+            Preset::Minimal => CleanOptions {
+                normalize_eof_newlines: false,
+                ..CleanOptions::default()
+            },
+        }
     }
+}
+
+impl LineEndingCounts {
+    fn differ_from(&self, expected_line_ending: LineEnding) -> bool {
+        match expected_line_ending {
+            LineEnding::Lf => self.crlf > 0,
+            LineEnding::Crlf => self.lf > 0,
+        }
+    }
+}
 
-    let normalize_eof_newlines = !args.disable_eof_newline_normalization;
+impl Report {
+    fn record(&mut self, path: &Path, outcome: FileOutcome) {
+        self.totals.count(&outcome);
 
-    for dir_entry_result in WalkDir::new(root_path) {
-        match dir_entry_result {
-            Ok(dir_entry) => {
-                let path = dir_entry.path();
+        let path = path.to_path_buf();
 
-                if !path.is_file() {
-                    continue;
+        match outcome {
+            FileOutcome::Cleaned {
+                file_stats,
+                hardlinked,
+                post_command_failure,
+                violates_final_newline_policy,
+                trailing_whitespace_authors,
+            } => {
+                for (author, line_count) in trailing_whitespace_authors {
+                    *self.trailing_whitespace_authors.entry(author).or_default() += line_count;
                 }
 
-                if let Some(current_file_extension) = path.extension() {
-                    let should_clean_file = args.extensions.as_ref().map_or(true, |extensions| {
-                        extensions
-                            .iter()
-                            .any(|extension| OsStr::new(extension) == current_file_extension)
-                    });
-
-                    if should_clean_file {
-                        match clean_file(path, normalize_eof_newlines) {
-                            Ok(file_was_cleaned) => {
-                                if file_was_cleaned {
-                                    cleaned_file_paths.push(path.to_path_buf())
-                                }
-                            }
-                            Err(
-                                LinemanFileError::FileNotOpened | LinemanFileError::FileNotCleaned,
-                            ) => skipped_file_paths.push(path.to_path_buf()),
-                        }
-                    }
+                if hardlinked {
+                    self.hardlinked_file_paths.push(path.clone());
+                }
+
+                if let Some(failure) = post_command_failure {
+                    self.post_command_failures.push((path.clone(), failure));
+                }
+
+                if self.check_mode && file_stats.applied_transforms.final_newline {
+                    self.missing_final_newline_file_paths.push(path.clone());
+                }
+
+                if violates_final_newline_policy {
+                    self.final_newline_violation_file_paths.push(path.clone());
                 }
+
+                self.cleaned_files.push((path, file_stats));
             }
-            // TODO: I don't really know what the hell this error is, so I'm just grabbing it and printing it at the end in the report.
-            // When I have a better idea of what it is, I can do something different, I guess
-            Err(walk_dir_error) => walk_dir_errors.push(walk_dir_error),
+            FileOutcome::AlreadyClean if self.keep_unchanged_file_paths => {
+                self.already_clean_file_paths.push(path)
+            }
+            FileOutcome::Excluded if self.keep_unchanged_file_paths => {
+                self.excluded_file_paths.push(path)
+            }
+            FileOutcome::AlreadyClean | FileOutcome::Excluded => {}
+            FileOutcome::Skipped(skip_reason) => self.skipped_files.push((path, skip_reason)),
+            FileOutcome::SkippedHighEntropy => self.high_entropy_file_paths.push(path),
+            FileOutcome::SkippedProtected => self.protected_file_paths.push(path),
+            FileOutcome::SkippedOutsideRoot => self.outside_root_file_paths.push(path),
+            FileOutcome::FailedVerification => self.verification_failed_file_paths.push(path),
+            FileOutcome::FailedWriteVerification => {
+                self.write_verification_failed_file_paths.push(path)
+            }
+            FileOutcome::FailedSelfCheck => self.self_check_failed_file_paths.push(path),
+            FileOutcome::Missing => self.missing_file_paths.push(path),
+            FileOutcome::SkippedOverMemoryBudget => self.over_memory_budget_file_paths.push(path),
+            FileOutcome::SkippedUntracked => self.untracked_file_paths.push(path),
+            FileOutcome::SkippedLineTooLong => self.line_too_long_file_paths.push(path),
+            FileOutcome::SkippedInUse => self.in_use_file_paths.push(path),
+            FileOutcome::SkippedBinary => self.binary_file_paths.push(path),
+            FileOutcome::UnexpectedLineEnding(line_ending_counts) => self
+                .unexpected_line_ending_files
+                .push((path, line_ending_counts)),
         }
     }
+}
 
-    print_report(&cleaned_file_paths, &skipped_file_paths, &walk_dir_errors);
-
-    Ok(())
+impl Default for ReportLayout {
+    fn default() -> Self {
+        ReportLayout {
+            indent: " ".repeat(4),
+            group_by_directory: false,
+            shows_empty_sections: false,
+            lists_skipped_files: true,
+        }
+    }
 }
 
-fn clean_file(path: &Path, normalize_eof_newlines: bool) -> Result<bool, LinemanFileError> {
-    let file_string = fs::read_to_string(path).map_err(|_| LinemanFileError::FileNotOpened)?;
-    let lines: Vec<&str> = file_string.split_inclusive('\n').collect();
-    let (clean_lines, file_was_cleaned) = clean_lines(&lines, normalize_eof_newlines);
+impl ReportTotals {
+    fn count(&mut self, outcome: &FileOutcome) {
+        match outcome {
+            FileOutcome::Cleaned {
+                file_stats,
+                post_command_failure,
+                violates_final_newline_policy,
+                ..
+            } => {
+                self.files_cleaned += 1;
+                self.lines_changed += file_stats.lines_changed;
+                self.bytes_saved += file_stats.bytes_saved;
 
-    if file_was_cleaned {
-        let mut file = File::create(path).map_err(|_| LinemanFileError::FileNotCleaned)?;
+                if post_command_failure.is_some() {
+                    self.post_command_failures += 1;
+                }
+
+                if *violates_final_newline_policy {
+                    self.final_newline_violations += 1;
+                }
+            }
+            FileOutcome::AlreadyClean => self.files_already_clean += 1,
+            FileOutcome::Excluded => self.files_excluded += 1,
+            FileOutcome::Skipped(skip_reason) => {
+                self.files_skipped += 1;
 
-        for clean_line in clean_lines {
-            // TODO: This needs more thought, as a failure here means the file is probably only partially written to
-            // Better hope your files are version controlled
-            file.write_all(clean_line.as_bytes())
-                .map_err(|_| LinemanFileError::FileNotCleaned)?;
+                if *skip_reason == SkipReason::FileNotOpened {
+                    self.files_skipped_not_opened += 1;
+                }
+            }
+            FileOutcome::SkippedHighEntropy => self.files_skipped_high_entropy += 1,
+            FileOutcome::SkippedProtected => self.files_skipped_protected += 1,
+            FileOutcome::SkippedOutsideRoot => self.files_skipped_outside_root += 1,
+            FileOutcome::FailedVerification => self.verification_failures += 1,
+            FileOutcome::FailedWriteVerification => self.write_verification_failures += 1,
+            FileOutcome::FailedSelfCheck => self.self_check_failures += 1,
+            FileOutcome::Missing => self.files_missing += 1,
+            FileOutcome::SkippedOverMemoryBudget => self.files_skipped_over_memory_budget += 1,
+            FileOutcome::SkippedUntracked => self.files_skipped_untracked += 1,
+            FileOutcome::SkippedLineTooLong => self.files_skipped_line_too_long += 1,
+            FileOutcome::SkippedInUse => self.files_skipped_in_use += 1,
+            FileOutcome::SkippedBinary => self.files_skipped_binary += 1,
+            FileOutcome::UnexpectedLineEnding(_) => self.unexpected_line_endings += 1,
         }
     }
-
-    Ok(file_was_cleaned)
 }
 
-fn clean_lines(lines: &[&str], normalize_eof_newlines: bool) -> (Vec<String>, bool) {
-    let mut cleaned_lines: Vec<String> = lines
-        .iter()
-        .map(|line| {
-            let line_has_newline = line.ends_with('\n');
-            let trimmed_line = line.trim_end();
-            let cleaned_line = if normalize_eof_newlines || line_has_newline {
-                format!("{}\n", trimmed_line)
-            } else {
-                trimmed_line.to_string()
-            };
+const ENTROPY_SAMPLE_SIZE: usize = 4096;
 
-            cleaned_line
-        })
-        .rev()
-        .skip_while(|line| normalize_eof_newlines && line.trim_end().is_empty())
-        .collect::<Vec<_>>();
+// A sample where more than one byte in this many is a control byte looks binary
+const BINARY_CONTROL_BYTE_RATIO_DENOMINATOR: usize = 3;
 
-    cleaned_lines.reverse();
+// Set by the SIGINT handler and checked between files, so an interrupt never lands mid-write
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
 
-    // This is probably slow, maybe a better method can be implemented later
-    let lines_were_cleaned = lines != cleaned_lines;
+fn main() -> Result<(), LinemanApplicationError> {
+    let args = LinemanArgs::from_args();
 
-    (cleaned_lines, lines_were_cleaned)
-}
+    if args.serve {
+        // The editor closing the pipe is the only way this ends, so write errors just stop serving
+        let _ = serve(&args, io::stdin().lock(), &mut io::stdout());
+        return Ok(());
+    }
 
-fn print_report(
-    cleaned_file_paths: &[PathBuf],
-    skipped_file_paths: &[PathBuf],
-    walk_dir_errors: &[Error],
-) {
-    let indent = " ".repeat(4);
+    if reads_stdin(&args) {
+        return clean_stdin(&args, io::stdin().lock(), &mut io::stdout());
+    }
 
-    if !cleaned_file_paths.is_empty() {
-        println!("Cleaned Files:");
+    if !args.merge_reports.is_empty() {
+        let mut report_strings = Vec::new();
 
-        for cleaned_file_path in cleaned_file_paths {
-            println!("{}{}", indent, cleaned_file_path.display());
+        for report_path in &args.merge_reports {
+            report_strings.push(fs::read_to_string(report_path).map_err(|error| {
+                LinemanApplicationError::ReportsNotMerged(format!(
+                    "{} could not be read: {}",
+                    report_path.display(),
+                    error
+                ))
+            })?);
         }
+
+        print!("{}", merge_reports(&report_strings)?);
+        return Ok(());
     }
 
-    if !skipped_file_paths.is_empty() {
-        println!("Skipped Files:");
+    install_interrupt_handler();
+
+    let report = run(&args, &INTERRUPTED, &mut io::stdout())?;
 
-        for skipped_file_path in skipped_file_paths {
-            println!("{}{}", indent, skipped_file_path.display());
+    if args.estimate {
+        print!("{}", format_estimate(&report));
+    } else if args.hook_message {
+        if let Some(hook_message) =
+            format_hook_message(&report, &std::env::args().skip(1).collect::<Vec<_>>())
+        {
+            println!("{}", hook_message);
         }
+    } else if matches!(args.format, ReportFormat::Text | ReportFormat::Json) {
+        print_report(&args, &report);
+    } else if args.format == ReportFormat::Rdjson {
+        println!("{}", format_rdjson(&report));
     }
 
-    if !walk_dir_errors.is_empty() {
-        println!("Walkdir Errors:");
+    if let Some(report_file) = &args.report_file {
+        write_report_file(report_file, &args, &report)?;
+    }
 
-        for walk_dir_error in walk_dir_errors {
-            println!("{}{}", indent, walk_dir_error);
-        }
+    if let Some(metrics_file) = &args.metrics_file {
+        write_metrics(metrics_file, &report)?;
+    }
+
+    if let Some(sync_failure) = &report.sync_failure {
+        return Err(LinemanApplicationError::FilesNotSynced(
+            sync_failure.clone(),
+        ));
+    }
+
+    if let Some(exit_code) = failure_exit_code(&args, &report) {
+        process::exit(exit_code);
     }
+
+    Ok(())
 }
 
-#[test]
-fn clean_lines_with_trailing_spaces() {
-    let input_lines = [
-        "def main():   \n",
-        "    print(\"Hello World\")    \n",
-        "    \n",
-        "if __name__ == \"__main__\":    \n",
-        "    main()    \n",
-    ];
+fn reads_stdin(args: &LinemanArgs) -> bool {
+    args.stdin || args.path == [Path::new("-")]
+}
 
-    let expected_output_lines = [
-        "def main():\n",
-        "    print(\"Hello World\")\n",
-        "\n",
-        "if __name__ == \"__main__\":\n",
-        "    main()\n",
-    ];
+// Without a filename there is nothing for the extension and glob filters to match, so the buffer is
+// always cleaned, with the options given on the command line
+fn clean_stdin(
+    args: &LinemanArgs,
+    mut input: impl Read,
+    output: &mut dyn Write,
+) -> Result<(), LinemanApplicationError> {
+    let mut content = String::new();
+
+    input.read_to_string(&mut content).map_err(|error| {
+        LinemanApplicationError::StdinNotCleaned(format!("Stdin could not be read: {}", error))
+    })?;
 
-    let (output_lines, lines_have_changes) = clean_lines(&input_lines, true);
+    let (cleaned, _) = clean_str(&content, &root_clean_options(args));
 
-    assert_eq!(expected_output_lines.to_vec(), output_lines);
-    assert_eq!(lines_have_changes, true);
+    output
+        .write_all(cleaned.as_bytes())
+        .and_then(|_| output.flush())
+        .map_err(|error| {
+            LinemanApplicationError::StdinNotCleaned(format!(
+                "Stdout could not be written: {}",
+                error
+            ))
+        })
 }
 
-#[test]
-fn clean_lines_with_trailing_tabs() {
-    let input_lines = [
-        "def main():\t\n",
-        "    print(\"Hello World\")\t\n",
-        "\t\n",
-        "if __name__ == \"__main__\":\t\n",
-        "    main()\t\n",
-    ];
+// The filename is matched against the same filters as a walked file, relative to the editor's
+// working directory, and content it excludes is returned unchanged
+fn serve(args: &LinemanArgs, input: impl BufRead, output: &mut dyn Write) -> io::Result<()> {
+    let root_options = root_clean_options(args);
+    let path_filters = PathFilters::new(args);
 
-    let expected_output_lines = [
-        "def main():\n",
-        "    print(\"Hello World\")\n",
-        "\n",
-        "if __name__ == \"__main__\":\n",
-        "    main()\n",
-    ];
+    for request_line in input.lines() {
+        let request_line = request_line?;
+
+        if request_line.trim().is_empty() {
+            continue;
+        }
+
+        let response = match json::parse(&request_line) {
+            Ok(json::Value::Object(members)) => {
+                let member = |key: &str| {
+                    members
+                        .iter()
+                        .find(|(member_key, _)| member_key == key)
+                        .map(|(_, value)| value)
+                };
+
+                match (member("content"), member("filename")) {
+                    (Some(json::Value::String(content)), Some(json::Value::String(filename))) => {
+                        let path = Path::new(filename);
+                        let (cleaned, changed) =
+                            if should_clean_file(path, Path::new(""), args, &path_filters) {
+                                let clean_options = file_clean_options(
+                                    path,
+                                    Path::new(""),
+                                    args,
+                                    &path_filters,
+                                    &root_options,
+                                );
+                                clean_str(content, &clean_options)
+                            } else {
+                                (content.clone(), false)
+                            };
+
+                        format!(
+                            "{{\"cleaned\":{},\"changed\":{}}}",
+                            json::quote(&cleaned),
+                            changed
+                        )
+                    }
+                    _ => "{\"error\":\"Expected string `content` and `filename` members\"}"
+                        .to_string(),
+                }
+            }
+            Ok(_) => "{\"error\":\"Expected a JSON object\"}".to_string(),
+            Err(error) => format!("{{\"error\":{}}}", json::quote(&error.to_string())),
+        };
+
+        writeln!(output, "{}", response)?;
+        output.flush()?;
+    }
 
-    let (output_lines, lines_have_changes) = clean_lines(&input_lines, true);
+    Ok(())
+}
 
-    assert_eq!(expected_output_lines.to_vec(), output_lines);
-    assert_eq!(lines_have_changes, true);
+fn failure_exit_code(args: &LinemanArgs, report: &Report) -> Option<i32> {
+    if report.interrupted {
+        Some(130)
+    } else if (args.check && check_failed(report))
+        || report.totals.final_newline_violations > 0
+        || report.totals.write_verification_failures > 0
+        || report.totals.self_check_failures > 0
+    {
+        Some(1)
+    } else {
+        None
+    }
 }
 
-#[test]
-fn add_newline_to_end_of_file() {
-    let input_lines = [
-        "def main():\n",
-        "    print(\"Hello World\")\n",
-        "\n",
-        "if __name__ == \"__main__\":\n",
-        "    main()",
-    ];
+// Missing final newlines are always among the files that need cleaning, so they fail the check too
+fn check_failed(report: &Report) -> bool {
+    report.totals.files_cleaned > 0
+}
 
-    let expected_output_lines = [
-        "def main():\n",
-        "    print(\"Hello World\")\n",
-        "\n",
-        "if __name__ == \"__main__\":\n",
-        "    main()\n",
-    ];
+// The command that fixes the files is the current one with the check-only flags dropped
+fn format_hook_message(report: &Report, command_line_args: &[String]) -> Option<String> {
+    if !check_failed(report) {
+        return None;
+    }
 
-    let (output_lines, lines_have_changes) = clean_lines(&input_lines, true);
+    let fix_command = std::iter::once("lineman".to_string())
+        .chain(
+            command_line_args
+                .iter()
+                .filter(|arg| !matches!(arg.as_str(), "--check" | "--hook-message"))
+                .map(|arg| quote_argument_for_shell(arg)),
+        )
+        .collect::<Vec<_>>()
+        .join(" ");
 
-    assert_eq!(expected_output_lines.to_vec(), output_lines);
-    assert_eq!(lines_have_changes, true);
+    let files_cleaned = report.totals.files_cleaned;
+    let (files_noun, needs_verb) = if files_cleaned == 1 {
+        ("file", "needs")
+    } else {
+        ("files", "need")
+    };
+
+    Some(format!(
+        "lineman: {} {} {} cleaning; run `{}` to fix",
+        files_cleaned, files_noun, needs_verb, fix_command
+    ))
 }
 
-#[test]
-fn do_not_add_newline_to_end_of_file() {
-    let input_lines = [
-        "def main():\n",
-        "    print(\"Hello World\")\n",
-        "\n",
-        "if __name__ == \"__main__\":\n",
-        "    main()",
-    ];
+fn quote_argument_for_shell(arg: &str) -> String {
+    let needs_quotes = arg.is_empty()
+        || !arg
+            .chars()
+            .all(|character| character.is_ascii_alphanumeric() || "-_./=:,".contains(character));
 
-    let expected_output_lines = [
-        "def main():\n",
-        "    print(\"Hello World\")\n",
-        "\n",
-        "if __name__ == \"__main__\":\n",
-        "    main()",
-    ];
+    if needs_quotes {
+        quote_for_shell(Path::new(arg))
+    } else {
+        arg.to_string()
+    }
+}
 
-    let (output_lines, lines_have_changes) = clean_lines(&input_lines, false);
+#[cfg(unix)]
+fn install_interrupt_handler() {
+    extern "C" fn handle_interrupt(_signal: libc::c_int) {
+        INTERRUPTED.store(true, Ordering::SeqCst);
+    }
 
-    assert_eq!(expected_output_lines.to_vec(), output_lines);
-    assert_eq!(lines_have_changes, false);
+    // SAFETY: the handler only stores to an atomic, which is async-signal-safe
+    unsafe {
+        libc::signal(
+            libc::SIGINT,
+            handle_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t,
+        );
+    }
 }
 
-#[test]
-fn remove_excessive_newlines_from_end_of_file() {
-    let input_lines = [
-        "def main():\n",
-        "    print(\"Hello World\")\n",
-        "\n",
-        "if __name__ == \"__main__\":\n",
-        "    main()\n",
-        "\n",
-        "\n",
-        "\n",
-    ];
+#[cfg(not(unix))]
+fn install_interrupt_handler() {}
 
-    let expected_output_lines = [
-        "def main():\n",
-        "    print(\"Hello World\")\n",
-        "\n",
-        "if __name__ == \"__main__\":\n",
-        "    main()\n",
-    ];
+fn run(
+    args: &LinemanArgs,
+    interrupted: &AtomicBool,
+    output: &mut dyn Write,
+) -> Result<Report, LinemanApplicationError> {
+    // Files given without a root are taken relative to the working directory, as `--serve` does
+    let root_paths = if args.path.is_empty() {
+        vec![PathBuf::new()]
+    } else {
+        distinct_root_paths(&args.path)
+    };
+
+    if !args.path.is_empty() && !root_paths.iter().all(|root_path| root_path.is_dir()) {
+        return Err(LinemanApplicationError::InvalidRootPath(
+            "The provided path is not a valid directory".to_string(),
+        ));
+    }
+
+    if let Some(file_path) = args.files.iter().find(|file_path| !file_path.is_file()) {
+        return Err(LinemanApplicationError::InvalidFilePath(format!(
+            "{} is not a file",
+            file_path.display()
+        )));
+    }
+
+    let stays_within_root =
+        args.stay_within_root || (args.follow_symlinks && !args.allow_outside_root);
+
+    let mut roots = Vec::new();
+
+    for root_path in &root_paths {
+        roots.push(WalkRoot {
+            path: root_path.clone(),
+            canonical_path: if stays_within_root {
+                Some(
+                    fs::canonicalize(resolvable_path(root_path)).map_err(|error| {
+                        LinemanApplicationError::InvalidRootPath(format!(
+                            "The provided path could not be resolved: {}",
+                            error
+                        ))
+                    })?,
+                )
+            } else {
+                None
+            },
+            tracked_paths: if args.tracked_only {
+                Some(read_tracked_paths(root_path)?)
+            } else {
+                None
+            },
+        });
+    }
 
-    let (output_lines, lines_have_changes) = clean_lines(&input_lines, true);
+    let context = RunContext {
+        protected_hashes: match &args.protect_hashes {
+            Some(protect_hashes_path) => read_protected_hashes(protect_hashes_path)?,
+            None => HashSet::new(),
+        },
+        path_filters: PathFilters::new(args),
+        roots,
+        relative_base_path: if args.relative {
+            Some(common_ancestor(&root_paths))
+        } else {
+            None
+        },
+        input_paths: match &args.input_list_json {
+            None if !args.files.is_empty() => {
+                let mut input_paths = args.files.clone();
 
-    assert_eq!(expected_output_lines.to_vec(), output_lines);
-    assert_eq!(lines_have_changes, true);
+                if args.sorted {
+                    input_paths.sort();
+                }
+
+                Some(input_paths)
+            }
+            Some(input_list_path) => {
+                let mut input_paths = read_input_list_json(input_list_path)?;
+
+                if args.sorted {
+                    input_paths.sort();
+                }
+
+                Some(input_paths)
+            }
+            None => None,
+        },
+    };
+
+    let mut config_cache = ConfigCache::new(&root_paths, root_clean_options(args));
+    load_directory_configs(args, &context, &mut config_cache)?;
+
+    // The estimate is built from the full list of cleaned files, so it can't be streamed
+    let streams_report = args.format == ReportFormat::JsonLines && !args.estimate;
+
+    let mut walk = |output: &mut dyn Write| {
+        // The tree is only listed until it's stable, so there's a single walk that writes anything
+        let walk_retries = if args.stable {
+            walk_until_stable(args.stable_retries, || {
+                tree_changed_while_listed(args, &root_paths, interrupted)
+            })
+        } else {
+            0
+        };
+
+        let mut report = walk_tree(
+            args,
+            &context,
+            &mut config_cache,
+            interrupted,
+            streams_report,
+            output,
+        )?;
+
+        report.walk_retries = walk_retries;
+        Ok(report)
+    };
+
+    let mut report = walk(output)?;
+    report.passes = 1;
+
+    // The first pass does the cleaning and is the one reported, later passes only confirm it
+    if args.until_stable {
+        let mut pass_report_changed_files = report.totals.files_cleaned > 0;
+
+        while pass_report_changed_files && !report.interrupted {
+            if report.passes == args.until_stable_max_passes {
+                return Err(LinemanApplicationError::TreeNotStable(format!(
+                    "The tree was still changing after {} passes, so cleaning isn't idempotent",
+                    report.passes
+                )));
+            }
+
+            let pass_report = walk(&mut io::sink())?;
+            report.passes += 1;
+            report.interrupted = pass_report.interrupted;
+            pass_report_changed_files = pass_report.totals.files_cleaned > 0;
+        }
+    }
+
+    if args.fsync_at_end {
+        let mut written_directories = root_paths
+            .iter()
+            .map(PathBuf::as_path)
+            .chain(args.files.iter().filter_map(|file_path| file_path.parent()));
+
+        report.sync_failure = written_directories.find_map(|directory| {
+            sync_filesystem(directory).err().map(|error| {
+                format!(
+                    "The files under {} could not be synced: {}",
+                    directory.display(),
+                    error
+                )
+            })
+        });
+    }
+
+    if streams_report {
+        let _ = writeln!(output, "{}", format_json_summary(&report));
+    }
+
+    Ok(report)
 }
 
-#[test]
-fn do_not_remove_excessive_newlines_from_end_of_file() {
-    let input_lines = [
-        "def main():\n",
-        "    print(\"Hello World\")\n",
-        "\n",
-        "if __name__ == \"__main__\":\n",
-        "    main()\n",
-        "\n",
-        "\n",
-        "\n",
-    ];
+// Syncs everything written to the filesystem that holds the directory, renames included, in one
+// call rather than one per file
+#[cfg(target_os = "linux")]
+fn sync_filesystem(directory: &Path) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
 
-    let expected_output_lines = [
-        "def main():\n",
-        "    print(\"Hello World\")\n",
-        "\n",
-        "if __name__ == \"__main__\":\n",
-        "    main()\n",
-        "\n",
-        "\n",
-        "\n",
-    ];
+    let directory = File::open(resolvable_path(directory))?;
+
+    // SAFETY: the descriptor stays open for the duration of the call, as `directory` is in scope
+    match unsafe { libc::syncfs(directory.as_raw_fd()) } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+// Without `syncfs`, the whole system is synced, which covers the directory's filesystem too
+#[cfg(all(unix, not(target_os = "linux")))]
+fn sync_filesystem(_directory: &Path) -> io::Result<()> {
+    // SAFETY: `sync` takes no arguments and can't fail
+    unsafe { libc::sync() };
+    Ok(())
+}
+
+// Windows can't flush a directory opened for reading, and flushing each file again would be the
+// per-file sync this skips, so the OS is left to write the data back
+#[cfg(not(unix))]
+fn sync_filesystem(_directory: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+// An empty root, used for files given without one, stands for the working directory
+fn resolvable_path(path: &Path) -> &Path {
+    if path.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        path
+    }
+}
+
+// The options every file starts from, before directory configs and per-file rules are applied
+fn root_clean_options(args: &LinemanArgs) -> CleanOptions {
+    let preset_options = args
+        .preset
+        .map_or_else(CleanOptions::default, Preset::clean_options);
+
+    CleanOptions {
+        normalize_eof_newlines: preset_options.normalize_eof_newlines
+            && !args.disable_eof_newline_normalization,
+        preserve_quoted_trailing: args.preserve_quoted_trailing,
+        preserve_python_strings: args.preserve_python_strings,
+        keep_last_line_trailing: args.keep_last_line_trailing,
+        trim_trailing_whitespace: !args.eof_only,
+        minimal_eof: args.minimal_eof,
+        aligned_comment_prefixes: if args.preserve_aligned_comments {
+            args.comment_prefixes.clone()
+        } else {
+            Vec::new()
+        },
+        comments_only_prefixes: if args.comments_only {
+            Some(args.comment_prefixes.clone())
+        } else {
+            None
+        },
+        keep_one_trailing_blank_line: false,
+        line_ending: args.line_ending.or(preset_options.line_ending),
+        max_consecutive_blank_lines: args
+            .max_consecutive_blank_lines
+            .or(preset_options.max_consecutive_blank_lines),
+        transform_order: args.transform_order.clone(),
+        final_newline_min_lines: args.final_newline_min_lines,
+        min_trailing: args.min_trailing,
+        reindentation: args
+            .tabs_to_spaces
+            .map(Reindentation::TabsToSpaces)
+            .or(args.spaces_to_tabs.map(Reindentation::SpacesToTabs)),
+        strip_bom: args.strip_bom,
+    }
+}
+
+// Every config is read before the first file is, so a malformed one deep in the tree fails the run
+// before anything has been written, rather than halfway through it
+fn load_directory_configs(
+    args: &LinemanArgs,
+    context: &RunContext,
+    config_cache: &mut ConfigCache,
+) -> Result<(), LinemanApplicationError> {
+    let config_error =
+        |error: config::ConfigError| LinemanApplicationError::ConfigNotRead(error.to_string());
+
+    if let Some(input_paths) = &context.input_paths {
+        for path in input_paths {
+            if let Some(directory) = path.parent() {
+                config_cache.options_for(directory).map_err(config_error)?;
+            }
+        }
+
+        return Ok(());
+    }
+
+    for root in &context.roots {
+        let mut ignore_cache = (!args.no_ignore).then(|| IgnoreCache::new(&root.path));
+        let directories = WalkDir::new(&root.path)
+            .follow_links(args.follow_symlinks)
+            .into_iter()
+            .filter_entry(|dir_entry| {
+                dir_entry.file_type().is_dir()
+                    && ignore_cache
+                        .as_mut()
+                        .is_none_or(|ignore_cache| !ignore_cache.is_ignored(dir_entry.path(), true))
+            });
+
+        // Walk errors are reported by the walk that cleans the files
+        for dir_entry in directories.flatten() {
+            config_cache
+                .options_for(dir_entry.path())
+                .map_err(config_error)?;
+        }
+    }
+
+    Ok(())
+}
+
+// Returns how many times the tree had to be listed again before a listing saw no changes
+fn walk_until_stable(max_retries: usize, mut tree_changed: impl FnMut() -> bool) -> usize {
+    let mut walk_retries = 0;
+
+    while walk_retries < max_retries && tree_changed() {
+        walk_retries += 1;
+    }
+
+    walk_retries
+}
+
+// Only lists and stats the entries the walk would visit, so it can be repeated without side effects
+fn tree_changed_while_listed(
+    args: &LinemanArgs,
+    root_paths: &[PathBuf],
+    interrupted: &AtomicBool,
+) -> bool {
+    let vanished = |error: Option<&io::Error>| {
+        error.is_some_and(|io_error| io_error.kind() == io::ErrorKind::NotFound)
+    };
+
+    // Listed input paths are reported as missing rather than retried
+    if !args.files.is_empty() || args.input_list_json.is_some() {
+        return false;
+    }
+
+    for root_path in root_paths {
+        let mut ignore_cache = (!args.no_ignore).then(|| IgnoreCache::new(root_path));
+        let walk_dir = WalkDir::new(root_path)
+            .follow_links(args.follow_symlinks)
+            .into_iter()
+            .filter_entry(|dir_entry| {
+                ignore_cache.as_mut().is_none_or(|ignore_cache| {
+                    !ignore_cache.is_ignored(dir_entry.path(), dir_entry.file_type().is_dir())
+                })
+            });
+
+        for dir_entry_result in walk_dir {
+            if interrupted.load(Ordering::SeqCst) {
+                return false;
+            }
+
+            let entry_vanished = match dir_entry_result {
+                Ok(dir_entry) => {
+                    dir_entry.file_type().is_file()
+                        && vanished(fs::metadata(dir_entry.path()).err().as_ref())
+                }
+                Err(walk_dir_error) => vanished(walk_dir_error.io_error()),
+            };
+
+            if entry_vanished {
+                return true;
+            }
+        }
+    }
+
+    false
+}
+
+fn format_walk_retries(walk_retries: usize) -> String {
+    match walk_retries {
+        0 => "The tree was stable, no walk retries were needed".to_string(),
+        1 => "The tree changed during the walk, so it was retried once".to_string(),
+        _ => format!(
+            "The tree changed during the walk, so it was retried {} times",
+            walk_retries
+        ),
+    }
+}
+
+fn format_passes(passes: usize) -> String {
+    match passes {
+        1 => "The tree was already stable after 1 pass".to_string(),
+        _ => format!("The tree stabilized after {} passes", passes),
+    }
+}
+
+// Skipped and excluded files were never judged, so only clean and cleaned files count
+fn health_percentage(totals: &ReportTotals) -> Option<usize> {
+    let processed_files = totals.files_already_clean + totals.files_cleaned;
+
+    // Rounded down, so 100% always means every file was clean
+    (processed_files > 0).then(|| totals.files_already_clean * 100 / processed_files)
+}
+
+fn format_health(totals: &ReportTotals) -> String {
+    match health_percentage(totals) {
+        Some(percentage) => format!("{}% of processed files were already clean", percentage),
+        None => "No files were processed, so there is no health percentage".to_string(),
+    }
+}
+
+// Reasons without any skipped files are left out, so a typical run reads as a short list
+fn format_skip_summary(totals: &ReportTotals) -> String {
+    let skip_counts = [
+        (totals.files_skipped_not_opened, "not opened"),
+        (
+            totals.files_skipped - totals.files_skipped_not_opened,
+            "not written",
+        ),
+        (totals.files_skipped_high_entropy, "high entropy"),
+        (totals.files_skipped_protected, "protected"),
+        (totals.files_skipped_outside_root, "outside root"),
+        (
+            totals.files_skipped_over_memory_budget,
+            "over memory budget",
+        ),
+        (totals.files_skipped_untracked, "not tracked"),
+        (totals.files_skipped_line_too_long, "line too long"),
+        (totals.files_skipped_in_use, "in use"),
+        (totals.files_skipped_binary, "binary"),
+    ];
+
+    let skip_counts: Vec<String> = skip_counts
+        .iter()
+        .filter(|(file_count, _)| *file_count > 0)
+        .map(|(file_count, reason)| format!("{} {}", file_count, reason))
+        .collect();
+
+    if skip_counts.is_empty() {
+        "Skipped: none".to_string()
+    } else {
+        format!("Skipped: {}", skip_counts.join(", "))
+    }
+}
+
+fn walk_tree(
+    args: &LinemanArgs,
+    context: &RunContext,
+    config_cache: &mut ConfigCache,
+    interrupted: &AtomicBool,
+    streams_report: bool,
+    output: &mut dyn Write,
+) -> Result<Report, LinemanApplicationError> {
+    let mut report = Report {
+        keep_unchanged_file_paths: args.explain,
+        check_mode: args.check,
+        dry_run: args.diff || is_dry_run(args),
+        explain_changes: args.explain_changes,
+        layout: ReportLayout {
+            indent: args
+                .report_indent_char
+                .to_string()
+                .repeat(args.report_indent),
+            group_by_directory: args.group_by_directory,
+            shows_empty_sections: args.report_empty,
+            lists_skipped_files: !args.skip_summary || args.verbose,
+        },
+        reports_health: args.health,
+        json_lines: (args.report_file.is_some()
+            && args.report_file_format == ReportFormat::JsonLines)
+            .then(Vec::new),
+        keeps_path_lists_while_streaming: args.report_file.is_some()
+            && args.report_file_format == ReportFormat::Text,
+        ..Report::default()
+    };
+
+    if let Some(input_paths) = &context.input_paths {
+        for path in input_paths {
+            if interrupted.load(Ordering::SeqCst) {
+                report.interrupted = true;
+                break;
+            }
+
+            let display_path = relative_display_path(path, context);
+
+            if !path.exists() {
+                record_outcome(
+                    &mut report,
+                    display_path,
+                    FileOutcome::Missing,
+                    streams_report,
+                    output,
+                );
+                continue;
+            }
+
+            // Listed paths are filtered against the root that contains them, or the first root
+            let root = context
+                .roots
+                .iter()
+                .find(|root| path.starts_with(&root.path))
+                .unwrap_or(&context.roots[0]);
+
+            let directory_options = config_cache
+                .options_for(path.parent().unwrap_or(&root.path))
+                .map_err(|error| LinemanApplicationError::ConfigNotRead(error.to_string()))?;
+
+            if let Some(outcome) = process_file(path, root, args, context, &directory_options) {
+                record_outcome(&mut report, display_path, outcome, streams_report, output);
+            }
+        }
+
+        return Ok(report);
+    }
+
+    'roots: for root in &context.roots {
+        let mut walk_dir = WalkDir::new(&root.path).follow_links(args.follow_symlinks);
+
+        // Sorting each directory's entries as it is read gives the same order as sorting every path
+        if args.sorted {
+            walk_dir = walk_dir.sort_by_file_name();
+        }
+
+        // Ignored directories are pruned, so nothing below them is ever read
+        let mut ignore_cache = (!args.no_ignore).then(|| IgnoreCache::new(&root.path));
+        let walk_dir = walk_dir.into_iter().filter_entry(|dir_entry| {
+            ignore_cache.as_mut().is_none_or(|ignore_cache| {
+                !ignore_cache.is_ignored(dir_entry.path(), dir_entry.file_type().is_dir())
+            })
+        });
+
+        for dir_entry_result in walk_dir {
+            if interrupted.load(Ordering::SeqCst) {
+                report.interrupted = true;
+                break 'roots;
+            }
+
+            match dir_entry_result {
+                Ok(dir_entry) => {
+                    let path = dir_entry.path();
+                    let directory_options = config_cache
+                        .options_for(path.parent().unwrap_or(&root.path))
+                        .map_err(|error| {
+                            LinemanApplicationError::ConfigNotRead(error.to_string())
+                        })?;
+
+                    if let Some(outcome) =
+                        process_file(path, root, args, context, &directory_options)
+                    {
+                        record_outcome(
+                            &mut report,
+                            relative_display_path(path, context),
+                            outcome,
+                            streams_report,
+                            output,
+                        );
+                    }
+                }
+                // TODO: I don't really know what the hell this error is, so I'm just grabbing it and printing it at the end in the report.
+                // When I have a better idea of what it is, I can do something different, I guess
+                Err(walk_dir_error) => {
+                    report.totals.walk_errors += 1;
+
+                    let walk_error_line = format!(
+                        "{{\"walk_error\":{}}}",
+                        json::quote(&walk_dir_error.to_string())
+                    );
+
+                    if streams_report {
+                        let _ = writeln!(output, "{}", walk_error_line);
+                    }
+
+                    if let Some(json_lines) = &mut report.json_lines {
+                        json_lines.push(walk_error_line);
+                    }
+
+                    if !streams_report || report.keeps_path_lists_while_streaming {
+                        report.walk_dir_errors.push(walk_dir_error);
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+fn record_outcome(
+    report: &mut Report,
+    path: &Path,
+    outcome: FileOutcome,
+    streams_report: bool,
+    output: &mut dyn Write,
+) {
+    if streams_report || report.json_lines.is_some() {
+        let json_line = format_json_line(path, &outcome);
+
+        if streams_report {
+            let _ = writeln!(output, "{}", json_line);
+        }
+
+        if let Some(json_lines) = &mut report.json_lines {
+            json_lines.push(json_line);
+        }
+    }
+
+    if streams_report && !report.keeps_path_lists_while_streaming {
+        report.totals.count(&outcome);
+    } else {
+        report.record(path, outcome);
+    }
+}
+
+// These modes only look at the files, so the cleaned versions are never written
+fn is_dry_run(args: &LinemanArgs) -> bool {
+    args.estimate || args.check || args.blame || args.format == ReportFormat::Rdjson
+}
+
+fn process_file(
+    path: &Path,
+    root: &WalkRoot,
+    args: &LinemanArgs,
+    context: &RunContext,
+    directory_options: &CleanOptions,
+) -> Option<FileOutcome> {
+    if !path.is_file() {
+        return None;
+    }
+
+    if !should_clean_file(path, &root.path, args, &context.path_filters) {
+        return Some(FileOutcome::Excluded);
+    }
+
+    if let Some(tracked_paths) = &root.tracked_paths {
+        if !canonical_file_path(path).is_some_and(|path| tracked_paths.contains(&path)) {
+            return Some(FileOutcome::SkippedUntracked);
+        }
+    }
+
+    if args.skip_high_entropy && sample_entropy(path) > args.entropy_threshold {
+        return Some(FileOutcome::SkippedHighEntropy);
+    }
+
+    if !args.no_skip_binary && read_sample(path).is_some_and(|sample| looks_binary(&sample)) {
+        return Some(FileOutcome::SkippedBinary);
+    }
+
+    if let Some(canonical_root_path) = &root.canonical_path {
+        let is_within_root = fs::canonicalize(path)
+            .is_ok_and(|canonical_path| canonical_path.starts_with(canonical_root_path));
+
+        if !is_within_root {
+            return Some(FileOutcome::SkippedOutsideRoot);
+        }
+    }
+
+    if args.skip_locked && is_locked(path) {
+        return Some(FileOutcome::SkippedInUse);
+    }
+
+    // Unreadable files pass here and are reported as skipped when they are cleaned
+    if let Some(max_line_bytes) = args.max_line_bytes {
+        if has_line_longer_than(path, max_line_bytes).unwrap_or(false) {
+            return Some(FileOutcome::SkippedLineTooLong);
+        }
+    }
+
+    let exceeds_memory_budget = args.memory_budget.is_some_and(|memory_budget| {
+        fs::metadata(path).is_ok_and(|metadata| metadata.len() > memory_budget as u64)
+    });
+
+    // These all need the whole file at once, which the budget doesn't allow
+    if exceeds_memory_budget
+        && (args.diff
+            || args.verify
+            || args.show_removed
+            || collects_issues(args)
+            || !context.protected_hashes.is_empty())
+    {
+        return Some(FileOutcome::SkippedOverMemoryBudget);
+    }
+
+    if !context.protected_hashes.is_empty() {
+        let content_is_protected = fs::read(path).is_ok_and(|content| {
+            context
+                .protected_hashes
+                .contains(&sha256::hex_digest(&content))
+        });
+
+        if content_is_protected {
+            return Some(FileOutcome::SkippedProtected);
+        }
+    }
+
+    // Detection only reads the file, so it stops here whatever the other options are
+    if let Some(expected_line_ending) = args.expect_line_ending {
+        let outcome = match count_line_endings(path) {
+            Ok(line_ending_counts) if line_ending_counts.differ_from(expected_line_ending) => {
+                FileOutcome::UnexpectedLineEnding(line_ending_counts)
+            }
+            Ok(_) => FileOutcome::AlreadyClean,
+            Err(_) => FileOutcome::Skipped(SkipReason::FileNotOpened),
+        };
+
+        return Some(outcome);
+    }
+
+    let clean_options = file_clean_options(
+        path,
+        &root.path,
+        args,
+        &context.path_filters,
+        directory_options,
+    );
+    let write_mode = if args.diff {
+        WriteMode::Diff(args.diff_context)
+    } else if is_dry_run(args) {
+        WriteMode::DryRun
+    } else {
+        WriteMode::Write
+    };
+
+    // Writes normally replace the file with a new one, which would leave its other hardlinks behind,
+    // so these are rewritten in place instead, keeping their inode
+    let is_hardlinked = args.preserve_hardlinks && hard_link_count(path) > 1;
+
+    let violates_final_newline_policy = |file_stats: &FileStats| {
+        file_stats.applied_transforms.final_newline
+            && enforces_final_newline(path, &root.path, &args.enforce_final_newline_in)
+    };
+
+    let flags = CleanFileFlags {
+        verify: args.verify,
+        verify_writes: args.verify_writes,
+        show_removed: args.show_removed,
+        self_check: args.self_check,
+        in_place: is_hardlinked,
+        collects_issues: collects_issues(args),
+        syncs: !args.no_fsync && !args.fsync_at_end,
+    };
+
+    let clean_result = match args.memory_budget {
+        Some(memory_budget) if exceeds_memory_budget => {
+            clean_file_streaming(path, &clean_options, write_mode, memory_budget, flags)
+        }
+        _ => clean_file_with_flags(path, &clean_options, write_mode, flags),
+    };
+
+    let outcome = match clean_result {
+        Ok(Some(file_stats)) if write_mode != WriteMode::Write => FileOutcome::Cleaned {
+            violates_final_newline_policy: violates_final_newline_policy(&file_stats),
+            trailing_whitespace_authors: if args.blame
+                && file_stats.applied_transforms.trailing_whitespace
+            {
+                blame_trailing_whitespace(path)
+            } else {
+                Vec::new()
+            },
+            file_stats,
+            hardlinked: false,
+            post_command_failure: None,
+        },
+        Ok(Some(file_stats)) => FileOutcome::Cleaned {
+            violates_final_newline_policy: violates_final_newline_policy(&file_stats),
+            file_stats,
+            hardlinked: is_hardlinked,
+            post_command_failure: args
+                .post_command
+                .as_ref()
+                .and_then(|post_command| run_post_command(post_command, path).err()),
+            trailing_whitespace_authors: Vec::new(),
+        },
+        Ok(None) => FileOutcome::AlreadyClean,
+        Err(LinemanFileError::FileNotOpened) => FileOutcome::Skipped(SkipReason::FileNotOpened),
+        Err(LinemanFileError::FileNotCleaned) => FileOutcome::Skipped(SkipReason::FileNotCleaned),
+        Err(LinemanFileError::CleaningNotVerified) => FileOutcome::FailedVerification,
+        Err(LinemanFileError::WriteNotVerified) => FileOutcome::FailedWriteVerification,
+        Err(LinemanFileError::SelfCheckFailed) => FileOutcome::FailedSelfCheck,
+        Err(LinemanFileError::FileOverMemoryBudget) => FileOutcome::SkippedOverMemoryBudget,
+    };
+
+    Some(outcome)
+}
+
+fn collects_issues(args: &LinemanArgs) -> bool {
+    args.format == ReportFormat::Rdjson
+        || (args.report_file.is_some() && args.report_file_format == ReportFormat::Rdjson)
+}
+
+// A root inside another root would have its files walked twice, so only the outermost is kept
+fn distinct_root_paths(root_paths: &[PathBuf]) -> Vec<PathBuf> {
+    let mut distinct_root_paths: Vec<PathBuf> = Vec::new();
+
+    for (index, root_path) in root_paths.iter().enumerate() {
+        let is_nested = root_paths
+            .iter()
+            .enumerate()
+            .any(|(other_index, other_root_path)| {
+                other_index != index
+                    && root_path.starts_with(other_root_path)
+                    && (root_path != other_root_path || other_index < index)
+            });
+
+        if !is_nested {
+            distinct_root_paths.push(root_path.clone());
+        }
+    }
+
+    distinct_root_paths
+}
+
+// A single root is its own ancestor, so its files are reported just as they are found under it
+fn common_ancestor(root_paths: &[PathBuf]) -> PathBuf {
+    let mut ancestor = root_paths.first().cloned().unwrap_or_default();
+
+    for root_path in root_paths.iter().skip(1) {
+        ancestor = ancestor
+            .components()
+            .zip(root_path.components())
+            .take_while(|(component, other_component)| component == other_component)
+            .map(|(component, _)| component)
+            .collect();
+    }
+
+    ancestor
+}
+
+fn relative_display_path<'a>(path: &'a Path, context: &RunContext) -> &'a Path {
+    match &context.relative_base_path {
+        Some(relative_base_path) => path.strip_prefix(relative_base_path).unwrap_or(path),
+        None => path,
+    }
+}
+
+// The rules that depend on the file itself, such as its type, on top of its directory's options
+fn file_clean_options(
+    path: &Path,
+    root_path: &Path,
+    args: &LinemanArgs,
+    path_filters: &PathFilters,
+    directory_options: &CleanOptions,
+) -> CleanOptions {
+    let language_profile = file_extension(path)
+        .and_then(OsStr::to_str)
+        .and_then(profiles::profile_for)
+        .filter(|_| args.language_profiles);
+
+    CleanOptions {
+        // A profile can only turn normalization off, so disabling it explicitly always holds
+        normalize_eof_newlines: directory_options.normalize_eof_newlines
+            && language_profile.is_none_or(|language_profile| language_profile.final_newline)
+            && file_extension(path).is_none_or(|extension| {
+                !is_listed_extension(extension, &args.no_final_newline_extensions)
+            }),
+        reindentation: directory_options
+            .reindentation
+            .or(language_profile.and_then(|language_profile| {
+                match language_profile.indent_style {
+                    IndentStyle::Tabs => None,
+                    IndentStyle::Spaces(width) => Some(Reindentation::TabsToSpaces(width)),
+                }
+            })),
+        preserve_quoted_trailing: directory_options.preserve_quoted_trailing
+            || is_dotenv_file(path),
+        preserve_python_strings: directory_options.preserve_python_strings && is_python_file(path),
+        keep_one_trailing_blank_line: path_filters
+            .keep_one_trailing_blank_for
+            .is_match(path.strip_prefix(root_path).unwrap_or(path)),
+        ..directory_options.clone()
+    }
+}
+
+impl PathFilters {
+    fn new(args: &LinemanArgs) -> PathFilters {
+        PathFilters {
+            include: GlobSet::new(&args.include),
+            exclude: GlobSet::new(&args.exclude),
+            dir_glob: GlobSet::new(&args.dir_glob),
+            keep_one_trailing_blank_for: GlobSet::new(&args.keep_one_trailing_blank_for),
+        }
+    }
+}
+
+fn enforces_final_newline(path: &Path, root_path: &Path, enforced_directories: &[PathBuf]) -> bool {
+    let relative_path = path.strip_prefix(root_path).unwrap_or(path);
+
+    enforced_directories.iter().any(|enforced_directory| {
+        relative_path.starts_with(
+            enforced_directory
+                .strip_prefix(".")
+                .unwrap_or(enforced_directory),
+        )
+    })
+}
+
+// Files outside a repository, or that git can't blame, have no authors to report
+fn blame_trailing_whitespace(path: &Path) -> Vec<(String, usize)> {
+    let (Some(directory), Some(file_name)) = (path.parent(), path.file_name()) else {
+        return Vec::new();
+    };
+
+    match Command::new("git")
+        .arg("-C")
+        .arg(directory)
+        .args(["blame", "--porcelain", "--"])
+        .arg(file_name)
+        .output()
+    {
+        Ok(output) if output.status.success() => {
+            count_trailing_whitespace_by_author(&String::from_utf8_lossy(&output.stdout))
+        }
+        _ => Vec::new(),
+    }
+}
+
+// The porcelain format names a commit's author only the first time the commit appears, and gives
+// each line's content after a tab, following the header of the commit that last touched it
+fn count_trailing_whitespace_by_author(porcelain: &str) -> Vec<(String, usize)> {
+    let mut commit_authors: HashMap<&str, String> = HashMap::new();
+    let mut author_line_counts: BTreeMap<String, usize> = BTreeMap::new();
+    let mut commit = "";
+    let mut author_name = "";
+
+    // Split on newlines alone, so a `\r` left at the end of a line's content still counts
+    for porcelain_line in porcelain.split('\n') {
+        if let Some(content) = porcelain_line.strip_prefix('\t') {
+            if content != content.trim_end() {
+                let author = commit_authors.get(commit).map_or(commit, String::as_str);
+                *author_line_counts.entry(author.to_string()).or_default() += 1;
+            }
+        } else if let Some(name) = porcelain_line.strip_prefix("author ") {
+            author_name = name;
+        } else if let Some(mail) = porcelain_line.strip_prefix("author-mail ") {
+            commit_authors.insert(commit, format!("{} {}", author_name, mail));
+        } else if let Some(hash) = porcelain_line
+            .split(' ')
+            .next()
+            .filter(|hash| hash.len() >= 40 && hash.bytes().all(|byte| byte.is_ascii_hexdigit()))
+        {
+            commit = hash;
+        }
+    }
+
+    author_line_counts.into_iter().collect()
+}
+
+// Run from inside the root, `git ls-files` lists the tracked files under it relative to it
+// They are kept joined to the canonical root, so a file is found however its path was written
+fn read_tracked_paths(root_path: &Path) -> Result<HashSet<PathBuf>, LinemanApplicationError> {
+    let canonical_root_path = fs::canonicalize(resolvable_path(root_path)).map_err(|error| {
+        LinemanApplicationError::TrackedFilesNotListed(format!(
+            "The root could not be resolved to list tracked files: {}",
+            error
+        ))
+    })?;
+
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(root_path)
+        .args(["ls-files", "-z"])
+        .output()
+        .map_err(|error| {
+            LinemanApplicationError::TrackedFilesNotListed(format!(
+                "git could not be run to list tracked files: {}",
+                error
+            ))
+        })?;
+
+    if !output.status.success() {
+        return Err(LinemanApplicationError::TrackedFilesNotListed(format!(
+            "The tracked files could not be listed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    Ok(output
+        .stdout
+        .split(|byte| *byte == 0)
+        .filter(|relative_path| !relative_path.is_empty())
+        .map(|relative_path| canonical_root_path.join(&*String::from_utf8_lossy(relative_path)))
+        .collect())
+}
+
+// Only the directory is resolved, as `git ls-files` lists a tracked symlink rather than its target
+fn canonical_file_path(path: &Path) -> Option<PathBuf> {
+    let directory = fs::canonicalize(resolvable_path(path.parent()?)).ok()?;
+
+    Some(directory.join(path.file_name()?))
+}
+
+// Only the first field of each line is read, so `sha256sum` output can be used as is
+fn read_protected_hashes(path: &Path) -> Result<HashSet<String>, LinemanApplicationError> {
+    let hashes_string = fs::read_to_string(path).map_err(|error| {
+        LinemanApplicationError::ProtectedHashesNotRead(format!(
+            "The protected hashes file could not be read: {}",
+            error
+        ))
+    })?;
+
+    Ok(hashes_string
+        .lines()
+        .filter_map(|line| line.split_whitespace().next())
+        .filter(|hash| !hash.starts_with('#'))
+        .map(|hash| hash.to_lowercase())
+        .collect())
+}
+
+fn read_input_list_json(path: &Path) -> Result<Vec<PathBuf>, LinemanApplicationError> {
+    let input_list_error = |message: String| LinemanApplicationError::InputListNotRead(message);
+
+    let list_string = fs::read_to_string(path).map_err(|error| {
+        input_list_error(format!("The input list could not be read: {}", error))
+    })?;
+
+    let json::Value::Array(values) = json::parse(&list_string).map_err(|error| {
+        input_list_error(format!("The input list is not valid JSON: {}", error))
+    })?
+    else {
+        return Err(input_list_error(
+            "The input list must be a JSON array of paths".to_string(),
+        ));
+    };
+
+    values
+        .into_iter()
+        .map(|value| match value {
+            json::Value::String(path_string) if !path_string.is_empty() => {
+                Ok(PathBuf::from(path_string))
+            }
+            value => Err(input_list_error(format!(
+                "The input list contains an entry that is not a path: {:?}",
+                value
+            ))),
+        })
+        .collect()
+}
+
+// Scanned a buffer at a time, so a single huge line is never held in memory
+fn has_line_longer_than(path: &Path, max_line_bytes: usize) -> io::Result<bool> {
+    let mut reader = BufReader::with_capacity(STREAM_BUFFER_SIZE, File::open(path)?);
+    let mut line_bytes = 0;
+    // A `\r` only counts toward the line once it's known not to start a `\r\n` terminator, which
+    // can straddle two buffers
+    let mut pending_carriage_return = false;
+
+    loop {
+        let buffer = reader.fill_buf()?;
+
+        if buffer.is_empty() {
+            return Ok(line_bytes + usize::from(pending_carriage_return) > max_line_bytes);
+        }
+
+        for byte in buffer {
+            if *byte == b'\n' {
+                line_bytes = 0;
+                pending_carriage_return = false;
+                continue;
+            }
+
+            line_bytes += usize::from(pending_carriage_return);
+            pending_carriage_return = *byte == b'\r';
+
+            if !pending_carriage_return {
+                line_bytes += 1;
+            }
+
+            if line_bytes > max_line_bytes {
+                return Ok(true);
+            }
+        }
+
+        let buffer_length = buffer.len();
+        reader.consume(buffer_length);
+    }
+}
+
+// Read a line at a time, so even a file over the memory budget can be counted
+fn count_line_endings(path: &Path) -> io::Result<LineEndingCounts> {
+    let mut reader = BufReader::with_capacity(STREAM_BUFFER_SIZE, File::open(path)?);
+    let mut line = Vec::new();
+    let mut line_ending_counts = LineEndingCounts::default();
+
+    while reader.read_until(b'\n', &mut line)? > 0 {
+        if line.ends_with(b"\r\n") {
+            line_ending_counts.crlf += 1;
+        } else if line.ends_with(b"\n") {
+            line_ending_counts.lf += 1;
+        }
+
+        line.clear();
+    }
+
+    Ok(line_ending_counts)
+}
+
+// Editors on Unix rarely lock files, so only advisory `flock` locks held by other processes are seen
+// The probe lock is released as soon as the file is closed again
+#[cfg(unix)]
+fn is_locked(path: &Path) -> bool {
+    use std::os::unix::io::AsRawFd;
+
+    let Ok(file) = File::open(path) else {
+        return false;
+    };
+
+    // SAFETY: the descriptor stays open for the duration of the call, as `file` is still in scope
+    let lock_result = unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) };
+
+    lock_result != 0 && io::Error::last_os_error().raw_os_error() == Some(libc::EWOULDBLOCK)
+}
+
+// Opening without sharing fails with a sharing violation while another process has the file open
+#[cfg(windows)]
+fn is_locked(path: &Path) -> bool {
+    use std::os::windows::fs::OpenOptionsExt;
+
+    const ERROR_SHARING_VIOLATION: i32 = 32;
+
+    fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .share_mode(0)
+        .open(path)
+        .is_err_and(|error| error.raw_os_error() == Some(ERROR_SHARING_VIOLATION))
+}
+
+#[cfg(not(any(unix, windows)))]
+fn is_locked(_path: &Path) -> bool {
+    false
+}
+
+#[cfg(unix)]
+fn hard_link_count(path: &Path) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+
+    fs::metadata(path).map_or(1, |metadata| metadata.nlink())
+}
+
+#[cfg(not(unix))]
+fn hard_link_count(_path: &Path) -> u64 {
+    1
+}
+
+fn is_listed_extension(file_extension: &OsStr, extensions: &[String]) -> bool {
+    extensions
+        .iter()
+        .any(|extension| OsStr::new(extension) == file_extension)
+}
+
+fn should_clean_file(
+    path: &Path,
+    root_path: &Path,
+    args: &LinemanArgs,
+    path_filters: &PathFilters,
+) -> bool {
+    let Some(current_file_extension) = file_extension(path) else {
+        return false;
+    };
+
+    let extension_is_included = args
+        .extensions
+        .as_ref()
+        .is_none_or(|extensions| is_listed_extension(current_file_extension, extensions))
+        && !is_listed_extension(current_file_extension, &args.exclude_extensions);
+
+    let relative_path = path.strip_prefix(root_path).unwrap_or(path);
+    let path_is_included = (path_filters.include.is_empty()
+        || path_filters.include.is_match(relative_path))
+        && !path_filters.exclude.is_match(relative_path);
+
+    let directory_is_included = path_filters.dir_glob.is_empty()
+        || relative_path.parent().is_some_and(|parent_path| {
+            parent_path.components().any(|component| {
+                path_filters
+                    .dir_glob
+                    .is_match(Path::new(component.as_os_str()))
+            })
+        });
+
+    extension_is_included && path_is_included && directory_is_included
+}
+
+// Unreadable files score zero here, so they fall through to `clean_file` and are reported there
+fn sample_entropy(path: &Path) -> f64 {
+    read_sample(path).map_or(0.0, |sample| shannon_entropy(&sample))
+}
+
+fn read_sample(path: &Path) -> Option<Vec<u8>> {
+    let mut sample = Vec::with_capacity(ENTROPY_SAMPLE_SIZE);
+
+    File::open(path)
+        .and_then(|file| {
+            file.take(ENTROPY_SAMPLE_SIZE as u64)
+                .read_to_end(&mut sample)
+        })
+        .ok()
+        .map(|_| sample)
+}
+
+// Bytes from 0x80 up are treated as text, so UTF-8 in any script never makes a file look binary
+fn looks_binary(sample: &[u8]) -> bool {
+    if sample.contains(&0) {
+        return true;
+    }
+
+    let control_byte_count = sample
+        .iter()
+        .filter(|byte| {
+            byte.is_ascii_control() && !matches!(byte, b'\t' | b'\n' | b'\r' | 0x0c | 0x1b)
+        })
+        .count();
+
+    control_byte_count * BINARY_CONTROL_BYTE_RATIO_DENOMINATOR > sample.len()
+}
+
+fn shannon_entropy(bytes: &[u8]) -> f64 {
+    let mut byte_counts = [0usize; 256];
+
+    for byte in bytes {
+        byte_counts[*byte as usize] += 1;
+    }
+
+    byte_counts
+        .iter()
+        .filter(|count| **count > 0)
+        .map(|count| {
+            let probability = *count as f64 / bytes.len() as f64;
+            -probability * probability.log2()
+        })
+        .sum()
+}
+
+// `Path::extension` treats `.env` as having no extension, but a user filtering on `env` means to
+// include it. Other dotfiles, like `.gitignore`, have no extension and are skipped like any other
+// file without one
+fn file_extension(path: &Path) -> Option<&OsStr> {
+    path.extension().or_else(|| {
+        path.file_name()
+            .filter(|file_name| *file_name == ".env")
+            .map(|_| OsStr::new("env"))
+    })
+}
+
+fn is_dotenv_file(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|file_name| file_name.to_str())
+        .is_some_and(|file_name| {
+            file_name == ".env" || file_name.starts_with(".env.") || file_name.ends_with(".env")
+        })
+}
+
+fn is_python_file(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension == "py" || extension == "pyi")
+}
+
+fn run_post_command(command_template: &str, path: &Path) -> Result<(), String> {
+    let command = command_template.replace("{}", &quote_for_shell(path));
+
+    #[cfg(windows)]
+    let output = Command::new("cmd").args(["/C", &command]).output();
+    #[cfg(not(windows))]
+    let output = Command::new("sh").args(["-c", &command]).output();
+
+    match output {
+        Ok(output) if output.status.success() => Ok(()),
+        Ok(output) => Err(format!(
+            "{} ({})",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim_end()
+        )),
+        Err(error) => Err(error.to_string()),
+    }
+}
+
+fn quote_for_shell(path: &Path) -> String {
+    let path_string = path.display().to_string();
+
+    if cfg!(windows) {
+        format!("\"{}\"", path_string)
+    } else {
+        format!("'{}'", path_string.replace('\'', "'\\''"))
+    }
+}
+
+fn write_metrics(metrics_file: &Path, report: &Report) -> Result<(), LinemanApplicationError> {
+    fs::write(metrics_file, format_metrics(report)).map_err(|error| {
+        LinemanApplicationError::MetricsNotWritten(format!(
+            "The metrics file could not be written: {}",
+            error
+        ))
+    })
+}
+
+fn format_metrics(report: &Report) -> String {
+    let totals = &report.totals;
+
+    [
+        ("files_cleaned", totals.files_cleaned as i64),
+        ("lines_changed", totals.lines_changed as i64),
+        ("bytes_saved", totals.bytes_saved),
+        ("files_skipped", totals.files_skipped as i64),
+        (
+            "files_skipped_high_entropy",
+            totals.files_skipped_high_entropy as i64,
+        ),
+        ("post_command_failures", totals.post_command_failures as i64),
+        (
+            "files_skipped_protected",
+            totals.files_skipped_protected as i64,
+        ),
+        (
+            "files_skipped_outside_root",
+            totals.files_skipped_outside_root as i64,
+        ),
+        ("verification_failures", totals.verification_failures as i64),
+        ("walk_errors", totals.walk_errors as i64),
+        ("files_already_clean", totals.files_already_clean as i64),
+        ("files_excluded", totals.files_excluded as i64),
+        (
+            "write_verification_failures",
+            totals.write_verification_failures as i64,
+        ),
+        ("self_check_failures", totals.self_check_failures as i64),
+        ("files_missing", totals.files_missing as i64),
+        (
+            "files_skipped_over_memory_budget",
+            totals.files_skipped_over_memory_budget as i64,
+        ),
+        (
+            "files_skipped_untracked",
+            totals.files_skipped_untracked as i64,
+        ),
+        (
+            "files_skipped_line_too_long",
+            totals.files_skipped_line_too_long as i64,
+        ),
+        ("files_skipped_in_use", totals.files_skipped_in_use as i64),
+        ("files_skipped_binary", totals.files_skipped_binary as i64),
+        (
+            "unexpected_line_endings",
+            totals.unexpected_line_endings as i64,
+        ),
+    ]
+    .iter()
+    .map(|(key, value)| format!("{}={}\n", key, value))
+    .collect()
+}
+
+fn format_json_line(path: &Path, outcome: &FileOutcome) -> String {
+    let path_string = json::quote(&path.display().to_string());
+
+    let (outcome_name, file_stats) = match outcome {
+        FileOutcome::Cleaned { file_stats, .. } => ("cleaned", Some(file_stats)),
+        FileOutcome::AlreadyClean => ("already_clean", None),
+        FileOutcome::Excluded => ("excluded", None),
+        FileOutcome::Skipped(_) => ("skipped", None),
+        FileOutcome::SkippedHighEntropy => ("skipped_high_entropy", None),
+        FileOutcome::SkippedProtected => ("skipped_protected", None),
+        FileOutcome::SkippedOutsideRoot => ("skipped_outside_root", None),
+        FileOutcome::FailedVerification => ("failed_verification", None),
+        FileOutcome::FailedWriteVerification => ("failed_write_verification", None),
+        FileOutcome::FailedSelfCheck => ("failed_self_check", None),
+        FileOutcome::Missing => ("missing", None),
+        FileOutcome::SkippedOverMemoryBudget => ("skipped_over_memory_budget", None),
+        FileOutcome::SkippedUntracked => ("skipped_untracked", None),
+        FileOutcome::SkippedLineTooLong => ("skipped_line_too_long", None),
+        FileOutcome::SkippedInUse => ("skipped_in_use", None),
+        FileOutcome::SkippedBinary => ("skipped_binary", None),
+        FileOutcome::UnexpectedLineEnding(_) => ("unexpected_line_ending", None),
+    };
+
+    let mut line = format!(
+        "{{\"path\":{},\"outcome\":\"{}\",\"lines_changed\":{},\"bytes_saved\":{}",
+        path_string,
+        outcome_name,
+        file_stats.map_or(0, |file_stats| file_stats.lines_changed),
+        file_stats.map_or(0, |file_stats| file_stats.bytes_saved),
+    );
+
+    if let FileOutcome::Cleaned {
+        hardlinked,
+        post_command_failure,
+        ..
+    } = outcome
+    {
+        if *hardlinked {
+            line.push_str(",\"hardlinked\":true");
+        }
+
+        if file_stats.is_some_and(|file_stats| file_stats.streamed) {
+            line.push_str(",\"streamed\":true");
+        }
+
+        if let Some(failure) = post_command_failure {
+            line.push_str(&format!(
+                ",\"post_command_failure\":{}",
+                json::quote(failure)
+            ));
+        }
+
+        if let Some(file_stats) =
+            file_stats.filter(|file_stats| !file_stats.removed_trailing.is_empty())
+        {
+            line.push_str(&format!(
+                ",\"removed_trailing\":{}",
+                json::quote(&file_stats.removed_trailing)
+            ));
+        }
+    }
+
+    if let FileOutcome::UnexpectedLineEnding(line_ending_counts) = outcome {
+        line.push_str(&format!(
+            ",\"lf_endings\":{},\"crlf_endings\":{}",
+            line_ending_counts.lf, line_ending_counts.crlf
+        ));
+    }
+
+    line.push('}');
+    line
+}
+
+// The summary count that each per-file outcome adds to
+const OUTCOME_SUMMARY_KEYS: [(&str, &str); 17] = [
+    ("cleaned", "files_cleaned"),
+    ("already_clean", "files_already_clean"),
+    ("excluded", "files_excluded"),
+    ("skipped", "files_skipped"),
+    ("skipped_high_entropy", "files_skipped_high_entropy"),
+    ("skipped_protected", "files_skipped_protected"),
+    ("skipped_outside_root", "files_skipped_outside_root"),
+    ("failed_verification", "verification_failures"),
+    ("failed_write_verification", "write_verification_failures"),
+    ("failed_self_check", "self_check_failures"),
+    ("missing", "files_missing"),
+    (
+        "skipped_over_memory_budget",
+        "files_skipped_over_memory_budget",
+    ),
+    ("skipped_untracked", "files_skipped_untracked"),
+    ("skipped_line_too_long", "files_skipped_line_too_long"),
+    ("skipped_in_use", "files_skipped_in_use"),
+    ("skipped_binary", "files_skipped_binary"),
+    ("unexpected_line_ending", "unexpected_line_endings"),
+];
+
+// A path found in more than one report keeps its last line, in the position it first appeared
+// The per-file counts are recounted from the kept lines, so duplicates aren't counted twice, and
+// every other summary value is summed, or combined with `or` for booleans
+fn merge_reports(report_strings: &[String]) -> Result<String, LinemanApplicationError> {
+    // Indexed by path, so deduplicating stays linear however many shard reports are merged
+    let mut file_lines: Vec<json::Value> = Vec::new();
+    let mut file_line_indices: HashMap<String, usize> = HashMap::new();
+    let mut walk_error_lines: Vec<String> = Vec::new();
+    let mut seen_walk_error_lines: HashSet<String> = HashSet::new();
+    let mut summary: Vec<(String, json::Value)> = Vec::new();
+
+    for (report_index, report_string) in report_strings.iter().enumerate() {
+        for (line_index, line) in report_string.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let merge_error = |message: String| {
+                LinemanApplicationError::ReportsNotMerged(format!(
+                    "Report {}, line {}: {}",
+                    report_index + 1,
+                    line_index + 1,
+                    message
+                ))
+            };
+
+            let value = json::parse(line).map_err(|error| merge_error(error.to_string()))?;
+
+            if let Some(json::Value::Object(members)) = value.get("summary") {
+                for (key, member_value) in members {
+                    match summary
+                        .iter_mut()
+                        .find(|(summary_key, _)| summary_key == key)
+                    {
+                        Some((_, summary_value)) => {
+                            *summary_value = match (&*summary_value, member_value) {
+                                (json::Value::Number(total), json::Value::Number(number)) => {
+                                    json::Value::Number(total + number)
+                                }
+                                (json::Value::Bool(any), json::Value::Bool(flag)) => {
+                                    json::Value::Bool(*any || *flag)
+                                }
+                                _ => {
+                                    return Err(merge_error(format!(
+                                        "`{}` can't be combined with earlier reports",
+                                        key
+                                    )))
+                                }
+                            }
+                        }
+                        None => summary.push((key.clone(), member_value.clone())),
+                    }
+                }
+            } else if value.get("walk_error").is_some() {
+                if seen_walk_error_lines.insert(line.to_string()) {
+                    walk_error_lines.push(line.to_string());
+                }
+            } else if let Some(json::Value::String(path)) = value.get("path") {
+                match file_line_indices.get(path) {
+                    Some(&file_line_index) => file_lines[file_line_index] = value.clone(),
+                    None => {
+                        file_line_indices.insert(path.clone(), file_lines.len());
+                        file_lines.push(value.clone());
+                    }
+                }
+            } else {
+                return Err(merge_error(
+                    "Expected a file, walk error or summary line".to_string(),
+                ));
+            }
+        }
+    }
+
+    let mut recounted_totals: Vec<(&str, f64)> = vec![
+        ("lines_changed", 0.0),
+        ("bytes_saved", 0.0),
+        ("post_command_failures", 0.0),
+    ];
+    recounted_totals.extend(
+        OUTCOME_SUMMARY_KEYS
+            .iter()
+            .map(|(_, summary_key)| (*summary_key, 0.0)),
+    );
+
+    let mut add_to_total = |key: &str, amount: f64| {
+        if let Some((_, total)) = recounted_totals
+            .iter_mut()
+            .find(|(total_key, _)| *total_key == key)
+        {
+            *total += amount;
+        }
+    };
+
+    for file_line in &file_lines {
+        for key in ["lines_changed", "bytes_saved"] {
+            if let Some(json::Value::Number(number)) = file_line.get(key) {
+                add_to_total(key, *number);
+            }
+        }
+
+        if file_line.get("post_command_failure").is_some() {
+            add_to_total("post_command_failures", 1.0);
+        }
+
+        if let Some(json::Value::String(outcome_name)) = file_line.get("outcome") {
+            if let Some((_, summary_key)) = OUTCOME_SUMMARY_KEYS
+                .iter()
+                .find(|(name, _)| name == outcome_name)
+            {
+                add_to_total(summary_key, 1.0);
+            }
+        }
+    }
+
+    for (key, total) in recounted_totals {
+        match summary
+            .iter_mut()
+            .find(|(summary_key, _)| summary_key == key)
+        {
+            Some((_, summary_value)) => *summary_value = json::Value::Number(total),
+            None => summary.push((key.to_string(), json::Value::Number(total))),
+        }
+    }
+
+    // A percentage can't be summed, so it is worked out again from the merged counts
+    if let Some(health_index) = summary
+        .iter()
+        .position(|(summary_key, _)| summary_key == "health_percent")
+    {
+        let summary_count =
+            |key: &str| match summary.iter().find(|(summary_key, _)| summary_key == key) {
+                Some((_, json::Value::Number(count))) => *count as usize,
+                _ => 0,
+            };
+
+        let totals = ReportTotals {
+            files_already_clean: summary_count("files_already_clean"),
+            files_cleaned: summary_count("files_cleaned"),
+            ..ReportTotals::default()
+        };
+
+        match health_percentage(&totals) {
+            Some(percentage) => summary[health_index].1 = json::Value::Number(percentage as f64),
+            None => {
+                summary.remove(health_index);
+            }
+        }
+    }
+
+    let mut merged_report = String::new();
+
+    for file_line in &file_lines {
+        merged_report.push_str(&format!("{}\n", file_line));
+    }
+
+    for walk_error_line in &walk_error_lines {
+        merged_report.push_str(&format!("{}\n", walk_error_line));
+    }
+
+    merged_report.push_str(&format!(
+        "{}\n",
+        json::Value::Object(vec![("summary".to_string(), json::Value::Object(summary))])
+    ));
+
+    Ok(merged_report)
+}
+
+impl SkipReason {
+    fn name(self) -> &'static str {
+        match self {
+            SkipReason::FileNotOpened => "file_not_opened",
+            SkipReason::FileNotCleaned => "file_not_cleaned",
+        }
+    }
+}
+
+// The whole report as one document, ending with the same summary object as `jsonl`
+fn format_json_report(report: &Report) -> String {
+    let path_value = |path: &Path| json::Value::String(path.display().to_string());
+
+    let cleaned = report
+        .cleaned_files
+        .iter()
+        .map(|(path, file_stats)| {
+            let mut members = vec![
+                ("path".to_string(), path_value(path)),
+                (
+                    "lines_changed".to_string(),
+                    json::Value::Number(file_stats.lines_changed as f64),
+                ),
+                (
+                    "bytes_saved".to_string(),
+                    json::Value::Number(file_stats.bytes_saved as f64),
+                ),
+            ];
+
+            if !file_stats.removed_trailing.is_empty() {
+                members.push((
+                    "removed_trailing".to_string(),
+                    json::Value::String(file_stats.removed_trailing.clone()),
+                ));
+            }
+
+            json::Value::Object(members)
+        })
+        .collect();
+
+    let skipped = report
+        .skipped_files
+        .iter()
+        .map(|(path, skip_reason)| {
+            json::Value::Object(vec![
+                ("path".to_string(), path_value(path)),
+                (
+                    "reason".to_string(),
+                    json::Value::String(skip_reason.name().to_string()),
+                ),
+            ])
+        })
+        .collect();
+
+    let walk_errors = report
+        .walk_dir_errors
+        .iter()
+        .map(|walk_dir_error| json::Value::String(walk_dir_error.to_string()))
+        .collect();
+
+    // The summary object's members are spliced in after its opening brace
+    format!(
+        "{{\"cleaned\":{},\"skipped\":{},\"walk_errors\":{},{}",
+        json::Value::Array(cleaned),
+        json::Value::Array(skipped),
+        json::Value::Array(walk_errors),
+        &format_json_summary(report)[1..]
+    )
+}
+
+// A reviewdog diagnostic result, https://github.com/reviewdog/reviewdog/tree/master/proto/rdf
+fn format_rdjson(report: &Report) -> String {
+    let string = |string: &str| json::Value::String(string.to_string());
+    let object = |members: Vec<(&str, json::Value)>| {
+        json::Value::Object(
+            members
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect(),
+        )
+    };
+    let position = |(line, column): (usize, usize)| {
+        object(vec![
+            ("line", json::Value::Number(line as f64)),
+            ("column", json::Value::Number(column as f64)),
+        ])
+    };
+
+    let diagnostics = report
+        .cleaned_files
+        .iter()
+        .flat_map(|(path, file_stats)| {
+            file_stats.issues.iter().map(move |issue| {
+                let range = object(vec![
+                    ("start", position(issue.start)),
+                    ("end", position(issue.end)),
+                ]);
+
+                object(vec![
+                    ("message", string(issue.message)),
+                    (
+                        "location",
+                        object(vec![
+                            ("path", string(&path.display().to_string())),
+                            ("range", range.clone()),
+                        ]),
+                    ),
+                    ("severity", string("WARNING")),
+                    (
+                        "suggestions",
+                        json::Value::Array(vec![object(vec![
+                            ("range", range),
+                            ("text", string(&issue.replacement)),
+                        ])]),
+                    ),
+                ])
+            })
+        })
+        .collect();
+
+    object(vec![
+        ("source", object(vec![("name", string("lineman"))])),
+        ("severity", string("WARNING")),
+        ("diagnostics", json::Value::Array(diagnostics)),
+    ])
+    .to_string()
+}
+
+fn format_json_summary(report: &Report) -> String {
+    let totals = &report.totals;
+
+    let health_member = match health_percentage(totals) {
+        Some(percentage) if report.reports_health => format!(",\"health_percent\":{}", percentage),
+        _ => String::new(),
+    };
+
+    format!(
+        concat!(
+            "{{\"summary\":{{\"files_cleaned\":{},\"lines_changed\":{},\"bytes_saved\":{},",
+            "\"files_already_clean\":{},\"files_excluded\":{},\"files_skipped\":{},",
+            "\"files_skipped_high_entropy\":{},\"files_skipped_protected\":{},",
+            "\"files_skipped_outside_root\":{},\"verification_failures\":{},",
+            "\"write_verification_failures\":{},\"self_check_failures\":{},",
+            "\"files_missing\":{},\"files_skipped_over_memory_budget\":{},",
+            "\"files_skipped_untracked\":{},\"files_skipped_line_too_long\":{},",
+            "\"files_skipped_in_use\":{},\"files_skipped_binary\":{},",
+            "\"unexpected_line_endings\":{},",
+            "\"post_command_failures\":{},\"walk_errors\":{},\"walk_retries\":{},",
+            "\"passes\":{},\"interrupted\":{}{}}}}}"
+        ),
+        totals.files_cleaned,
+        totals.lines_changed,
+        totals.bytes_saved,
+        totals.files_already_clean,
+        totals.files_excluded,
+        totals.files_skipped,
+        totals.files_skipped_high_entropy,
+        totals.files_skipped_protected,
+        totals.files_skipped_outside_root,
+        totals.verification_failures,
+        totals.write_verification_failures,
+        totals.self_check_failures,
+        totals.files_missing,
+        totals.files_skipped_over_memory_budget,
+        totals.files_skipped_untracked,
+        totals.files_skipped_line_too_long,
+        totals.files_skipped_in_use,
+        totals.files_skipped_binary,
+        totals.unexpected_line_endings,
+        totals.post_command_failures,
+        totals.walk_errors,
+        report.walk_retries,
+        report.passes,
+        report.interrupted,
+        health_member,
+    )
+}
+
+// Files, lines and bytes, in total and for each extension, so the size of a cleanup can be judged
+// before anything is written
+fn format_estimate(report: &Report) -> String {
+    let mut totals_by_extension: BTreeMap<String, (usize, usize, i64)> = BTreeMap::new();
+
+    for (file_path, file_stats) in &report.cleaned_files {
+        let extension = file_extension(file_path).map_or("(none)".to_string(), |extension| {
+            extension.to_string_lossy().to_string()
+        });
+
+        let extension_totals = totals_by_extension.entry(extension).or_default();
+        extension_totals.0 += 1;
+        extension_totals.1 += file_stats.lines_changed;
+        extension_totals.2 += file_stats.bytes_saved;
+    }
+
+    let indent = " ".repeat(4);
+    let totals = &report.totals;
+
+    let mut estimate = format!(
+        "Estimate:\n{indent}Files touched: {}\n{indent}Lines changed: {}\n{indent}Bytes saved: {}\n",
+        totals.files_cleaned,
+        totals.lines_changed,
+        totals.bytes_saved,
+        indent = indent,
+    );
+
+    if !totals_by_extension.is_empty() {
+        estimate.push_str(&format!("{}By extension:\n", indent));
+
+        for (extension, (files, lines_changed, bytes_saved)) in totals_by_extension {
+            estimate.push_str(&format!(
+                "{indent}{indent}{}: {} files, {} lines changed, {} bytes saved\n",
+                extension,
+                files,
+                lines_changed,
+                bytes_saved,
+                indent = indent,
+            ));
+        }
+    }
+
+    estimate.push_str(&format_removed_trailing_section(report));
+    estimate
+}
+
+// Each cleaned file's removed bytes under its path, or nothing when `--show-removed` found none
+fn format_removed_trailing_section(report: &Report) -> String {
+    let indent = &report.layout.indent;
+    let mut section = String::new();
+
+    for (file_path, file_stats) in &report.cleaned_files {
+        if file_stats.removed_trailing.is_empty() {
+            continue;
+        }
+
+        if section.is_empty() {
+            section.push_str("Removed Trailing Whitespace:\n");
+        }
+
+        section.push_str(&format!("{}{}:\n", indent, file_path.display()));
+
+        for removed_line in file_stats.removed_trailing.lines() {
+            section.push_str(&format!("{}{}\n", indent, removed_line));
+        }
+    }
+
+    section
+}
+
+fn print_report(args: &LinemanArgs, report: &Report) {
+    // Stdout is line buffered, so large reports are buffered here and written in one go
+    let mut writer = BufWriter::new(io::stdout().lock());
+
+    let write_result = match args.format {
+        ReportFormat::Json => writeln!(writer, "{}", format_json_report(report)),
+        _ => write_text_report(&mut writer, args, report),
+    };
+
+    // Nothing sensible can be done if stdout has gone away, so write errors are ignored
+    let _ = write_result.and_then(|_| writer.flush());
+}
+
+// The run's notes come first, then the per-file sections
+fn write_text_report<W: Write>(
+    writer: &mut W,
+    args: &LinemanArgs,
+    report: &Report,
+) -> io::Result<()> {
+    if report.interrupted {
+        writeln!(
+            writer,
+            "Interrupted, only files processed before Ctrl-C are reported"
+        )?;
+    }
+
+    if args.stable {
+        writeln!(writer, "{}", format_walk_retries(report.walk_retries))?;
+    }
+
+    if args.until_stable {
+        writeln!(writer, "{}", format_passes(report.passes))?;
+    }
+
+    if args.health {
+        writeln!(writer, "{}", format_health(&report.totals))?;
+    }
+
+    if args.skip_summary {
+        writeln!(writer, "{}", format_skip_summary(&report.totals))?;
+    }
+
+    write_report(writer, report, args.explain)
+}
+
+fn write_report_file(
+    report_file: &Path,
+    args: &LinemanArgs,
+    report: &Report,
+) -> Result<(), LinemanApplicationError> {
+    let mut report_bytes = Vec::new();
+
+    match args.report_file_format {
+        // Writing into memory can't fail
+        ReportFormat::Text => {
+            let _ = write_text_report(&mut report_bytes, args, report);
+        }
+        ReportFormat::Json => {
+            report_bytes.extend_from_slice(format!("{}\n", format_json_report(report)).as_bytes());
+        }
+        ReportFormat::Rdjson => {
+            report_bytes.extend_from_slice(format!("{}\n", format_rdjson(report)).as_bytes());
+        }
+        ReportFormat::JsonLines => {
+            for json_line in report.json_lines.iter().flatten() {
+                report_bytes.extend_from_slice(format!("{}\n", json_line).as_bytes());
+            }
+
+            report_bytes.extend_from_slice(format!("{}\n", format_json_summary(report)).as_bytes());
+        }
+    }
+
+    fs::write(report_file, report_bytes).map_err(|error| {
+        LinemanApplicationError::ReportFileNotWritten(format!(
+            "The report file could not be written: {}",
+            error
+        ))
+    })
+}
+
+// Every path in the report is padded to the widest one, so the per-file columns line up across
+// sections
+fn write_report<W: Write>(writer: &mut W, report: &Report, explain: bool) -> io::Result<()> {
+    let layout = &report.layout;
+
+    let path_width = report
+        .cleaned_files
+        .iter()
+        .map(|(file_path, _)| file_path)
+        .chain(
+            report
+                .post_command_failures
+                .iter()
+                .map(|(file_path, _)| file_path),
+        )
+        .chain(
+            report
+                .unexpected_line_ending_files
+                .iter()
+                .map(|(file_path, _)| file_path),
+        )
+        .map(|file_path| file_path.display().to_string().chars().count())
+        .max()
+        .unwrap_or(0);
+
+    let cleaned_files_header = if explain {
+        "Matched & Dirty Files:"
+    } else if report.dry_run {
+        "Files That Would Be Cleaned:"
+    } else {
+        "Cleaned Files:"
+    };
+
+    if !report.cleaned_files.is_empty() || layout.shows_empty_sections {
+        writeln!(writer, "{}", cleaned_files_header)?;
+
+        let lines_width = column_width(
+            report
+                .cleaned_files
+                .iter()
+                .map(|(_, stats)| stats.lines_changed),
+        );
+        let bytes_width = column_width(
+            report
+                .cleaned_files
+                .iter()
+                .map(|(_, stats)| stats.bytes_saved),
+        );
+
+        let rows: Vec<(&Path, String)> = report
+            .cleaned_files
+            .iter()
+            .map(|(file_path, file_stats)| {
+                let mut details = format!(
+                    "  {:>lines_width$} lines changed  {:>bytes_width$} bytes saved",
+                    file_stats.lines_changed, file_stats.bytes_saved,
+                );
+
+                if report.explain_changes {
+                    details.push_str(&format!(
+                        "  ({})",
+                        file_stats.applied_transforms.names().join(", ")
+                    ));
+                }
+
+                if report.check_mode {
+                    if let Some(end_of_file_delta) = format_end_of_file_delta(file_stats) {
+                        details.push_str(&format!("  ({})", end_of_file_delta));
+                    }
+                }
+
+                (file_path.as_path(), details)
+            })
+            .collect();
+
+        write_path_rows(writer, layout, &rows, path_width)?;
+    }
+
+    if explain {
+        write_report_section(
+            writer,
+            layout,
+            "Matched & Clean Files:",
+            &report.already_clean_file_paths,
+        )?;
+        write_report_section(
+            writer,
+            layout,
+            "Excluded Files:",
+            &report.excluded_file_paths,
+        )?;
+    }
+
+    write_report_section(
+        writer,
+        layout,
+        "Missing final newline:",
+        &report.missing_final_newline_file_paths,
+    )?;
+    write_report_section(
+        writer,
+        layout,
+        "Missing Final Newline (Enforced):",
+        &report.final_newline_violation_file_paths,
+    )?;
+
+    if !report.unexpected_line_ending_files.is_empty() {
+        writeln!(writer, "Unexpected Line Endings:")?;
+
+        let rows: Vec<(&Path, String)> = report
+            .unexpected_line_ending_files
+            .iter()
+            .map(|(file_path, line_ending_counts)| {
+                (
+                    file_path.as_path(),
+                    format!(
+                        "  {} LF  {} CRLF",
+                        line_ending_counts.lf, line_ending_counts.crlf
+                    ),
+                )
+            })
+            .collect();
+
+        write_path_rows(writer, layout, &rows, path_width)?;
+    }
+
+    if layout.lists_skipped_files {
+        write_skipped_sections(writer, report)?;
+    }
+
+    write_report_section(
+        writer,
+        layout,
+        "Verification Failures (Left Unchanged):",
+        &report.verification_failed_file_paths,
+    )?;
+    write_report_section(
+        writer,
+        layout,
+        "Write Verification Failures:",
+        &report.write_verification_failed_file_paths,
+    )?;
+    write_report_section(
+        writer,
+        layout,
+        "Self-Check Failures (Left Unchanged):",
+        &report.self_check_failed_file_paths,
+    )?;
+    write_report_section(writer, layout, "Missing Files:", &report.missing_file_paths)?;
+    write_report_section(
+        writer,
+        layout,
+        "Hardlinked Files (Rewritten In Place):",
+        &report.hardlinked_file_paths,
+    )?;
+
+    if !report.post_command_failures.is_empty() {
+        writeln!(writer, "Post-Command Failures:")?;
+
+        let rows: Vec<(&Path, String)> = report
+            .post_command_failures
+            .iter()
+            .map(|(file_path, failure)| (file_path.as_path(), format!("  {}", failure)))
+            .collect();
+
+        write_path_rows(writer, layout, &rows, path_width)?;
+    }
+
+    write!(writer, "{}", format_removed_trailing_section(report))?;
+
+    if !report.trailing_whitespace_authors.is_empty() {
+        writeln!(writer, "Trailing Whitespace By Author:")?;
+
+        let author_width = report
+            .trailing_whitespace_authors
+            .keys()
+            .map(|author| author.chars().count())
+            .max()
+            .unwrap_or_default();
+
+        for (author, line_count) in &report.trailing_whitespace_authors {
+            writeln!(
+                writer,
+                "{}{:<author_width$}  {} lines",
+                layout.indent, author, line_count
+            )?;
+        }
+    }
+
+    if !report.walk_dir_errors.is_empty() || layout.shows_empty_sections {
+        writeln!(writer, "Walkdir Errors:")?;
+
+        for walk_dir_error in &report.walk_dir_errors {
+            writeln!(writer, "{}{}", layout.indent, walk_dir_error)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn write_skipped_sections<W: Write>(writer: &mut W, report: &Report) -> io::Result<()> {
+    let layout = &report.layout;
+
+    let skipped_file_paths: Vec<PathBuf> = report
+        .skipped_files
+        .iter()
+        .map(|(file_path, _)| file_path.clone())
+        .collect();
+
+    if skipped_file_paths.is_empty() && layout.shows_empty_sections {
+        writeln!(writer, "Skipped Files:")?;
+    }
+
+    write_report_section(writer, layout, "Skipped Files:", &skipped_file_paths)?;
+    write_report_section(
+        writer,
+        layout,
+        "Skipped Files (High Entropy):",
+        &report.high_entropy_file_paths,
+    )?;
+    write_report_section(
+        writer,
+        layout,
+        "Skipped Files (Protected):",
+        &report.protected_file_paths,
+    )?;
+    write_report_section(
+        writer,
+        layout,
+        "Skipped Files (Outside Root):",
+        &report.outside_root_file_paths,
+    )?;
+    write_report_section(
+        writer,
+        layout,
+        "Skipped Files (Over Memory Budget):",
+        &report.over_memory_budget_file_paths,
+    )?;
+    write_report_section(
+        writer,
+        layout,
+        "Skipped Files (Not Tracked):",
+        &report.untracked_file_paths,
+    )?;
+    write_report_section(
+        writer,
+        layout,
+        "Skipped Files (Line Too Long):",
+        &report.line_too_long_file_paths,
+    )?;
+    write_report_section(
+        writer,
+        layout,
+        "Skipped Files (In Use):",
+        &report.in_use_file_paths,
+    )?;
+    write_report_section(
+        writer,
+        layout,
+        "Skipped Files (Binary):",
+        &report.binary_file_paths,
+    )?;
+
+    Ok(())
+}
+
+// What is wrong with the end of a file, so a failed check explains itself without a diff
+fn format_end_of_file_delta(file_stats: &FileStats) -> Option<String> {
+    let mut problems = Vec::new();
+
+    if file_stats.trailing_blank_lines != file_stats.expected_trailing_blank_lines {
+        problems.push(format!(
+            "has {} trailing blank line{}, expected {}",
+            file_stats.trailing_blank_lines,
+            if file_stats.trailing_blank_lines == 1 {
+                ""
+            } else {
+                "s"
+            },
+            file_stats.expected_trailing_blank_lines
+        ));
+    }
+
+    if file_stats.applied_transforms.final_newline {
+        problems.push("missing final newline".to_string());
+    }
+
+    (!problems.is_empty()).then(|| problems.join(", "))
+}
+
+fn write_report_section<W: Write>(
+    writer: &mut W,
+    layout: &ReportLayout,
+    header: &str,
+    file_paths: &[PathBuf],
+) -> io::Result<()> {
+    if !file_paths.is_empty() {
+        writeln!(writer, "{}", header)?;
+
+        let rows: Vec<(&Path, String)> = file_paths
+            .iter()
+            .map(|file_path| (file_path.as_path(), String::new()))
+            .collect();
+
+        write_path_rows(writer, layout, &rows, 0)?;
+    }
+
+    Ok(())
+}
+
+// Each row is a path followed by its details, which are aligned into a column when there are any
+fn write_path_rows<W: Write>(
+    writer: &mut W,
+    layout: &ReportLayout,
+    rows: &[(&Path, String)],
+    path_width: usize,
+) -> io::Result<()> {
+    if layout.group_by_directory {
+        return write_grouped_path_rows(writer, &layout.indent, rows);
+    }
+
+    for (file_path, details) in rows {
+        writeln!(
+            writer,
+            "{}{:<path_width$}{}",
+            layout.indent,
+            file_path.display().to_string(),
+            details
+        )?;
+    }
+
+    Ok(())
+}
+
+// The directory shared by every row is printed once, and each directory below it opens a new level
+fn write_grouped_path_rows<W: Write>(
+    writer: &mut W,
+    indent: &str,
+    rows: &[(&Path, String)],
+) -> io::Result<()> {
+    let mut rows = rows.to_vec();
+    rows.sort_by_key(|(file_path, _)| *file_path);
+
+    let base_directory = common_directory(rows.iter().map(|(file_path, _)| *file_path));
+    let base_depth = if base_directory.as_os_str().is_empty() {
+        1
+    } else {
+        writeln!(writer, "{}{}/", indent, base_directory.display())?;
+        2
+    };
+
+    let grouped_rows: Vec<(Vec<String>, String, &String)> = rows
+        .iter()
+        .map(|(file_path, details)| {
+            let relative_path = file_path.strip_prefix(&base_directory).unwrap_or(file_path);
+            let directory_names = relative_path
+                .parent()
+                .map(|parent_path| {
+                    parent_path
+                        .iter()
+                        .map(|name| name.to_string_lossy().to_string())
+                        .collect()
+                })
+                .unwrap_or_default();
+            let file_name = relative_path.file_name().unwrap_or_default();
+
+            (
+                directory_names,
+                file_name.to_string_lossy().to_string(),
+                details,
+            )
+        })
+        .collect();
+
+    let indent_width = indent.chars().count();
+    let name_width = grouped_rows
+        .iter()
+        .map(|(directory_names, file_name, _)| {
+            indent_width * (base_depth + directory_names.len()) + file_name.chars().count()
+        })
+        .max()
+        .unwrap_or(0);
+
+    let mut open_directory_names: Vec<String> = Vec::new();
+
+    for (directory_names, file_name, details) in grouped_rows {
+        let shared_depth = open_directory_names
+            .iter()
+            .zip(&directory_names)
+            .take_while(|(open_name, name)| open_name == name)
+            .count();
+
+        open_directory_names.truncate(shared_depth);
+
+        for directory_name in &directory_names[shared_depth..] {
+            writeln!(
+                writer,
+                "{}{}/",
+                indent.repeat(base_depth + open_directory_names.len()),
+                directory_name
+            )?;
+            open_directory_names.push(directory_name.clone());
+        }
+
+        let name_column = format!(
+            "{}{}",
+            indent.repeat(base_depth + directory_names.len()),
+            file_name
+        );
+
+        if details.is_empty() {
+            writeln!(writer, "{}", name_column)?;
+        } else {
+            writeln!(writer, "{:<name_width$}{}", name_column, details)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn common_directory<'a>(file_paths: impl Iterator<Item = &'a Path>) -> PathBuf {
+    let mut common_components: Option<Vec<Component>> = None;
+
+    for file_path in file_paths {
+        let parent_components: Vec<Component> = file_path
+            .parent()
+            .map(|parent_path| parent_path.components().collect())
+            .unwrap_or_default();
+
+        common_components = Some(match common_components {
+            None => parent_components,
+            Some(common_components) => common_components
+                .into_iter()
+                .zip(parent_components)
+                .take_while(|(common_component, component)| common_component == component)
+                .map(|(common_component, _)| common_component)
+                .collect(),
+        });
+    }
+
+    common_components.unwrap_or_default().into_iter().collect()
+}
+
+fn column_width<T: ToString>(values: impl Iterator<Item = T>) -> usize {
+    values
+        .map(|value| value.to_string().len())
+        .max()
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+use lineman::{clean_lines, AppliedTransforms};
+
+#[cfg(test)]
+fn create_test_directory(test_name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let directory =
+        std::env::temp_dir().join(format!("lineman-{}-{}", test_name, std::process::id()));
+
+    let _ = fs::remove_dir_all(&directory);
+
+    for (relative_path, content) in files {
+        let path = directory.join(relative_path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    directory
+}
+
+#[test]
+fn tabs_to_spaces_and_spaces_to_tabs_are_mutually_exclusive() {
+    let directory = create_test_directory(
+        "tabs-to-spaces",
+        &[
+            ("mixed.py", "def main():\n\t  pass\n"),
+            ("spaces.py", "def main():\n    pass\n"),
+        ],
+    );
+
+    let report = run_in_test_directory(&directory, &["--tabs-to-spaces", "4"]);
+
+    assert_eq!(
+        cleaned_file_paths(&report),
+        vec![directory.join("mixed.py")]
+    );
+    assert_eq!(
+        fs::read_to_string(directory.join("mixed.py")).unwrap(),
+        "def main():\n      pass\n"
+    );
+    assert!(LinemanArgs::from_iter_safe([
+        "lineman",
+        "--path",
+        ".",
+        "--tabs-to-spaces",
+        "4",
+        "--spaces-to-tabs",
+        "4",
+    ])
+    .is_err());
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn line_ending_leaves_files_that_already_match_untouched() {
+    let directory = create_test_directory(
+        "line-ending",
+        &[("lf.txt", "one\ntwo\n"), ("mixed.txt", "one\r\ntwo\n")],
+    );
+
+    let report = run_in_test_directory(&directory, &["--line-ending", "lf"]);
+
+    assert_eq!(
+        cleaned_file_paths(&report),
+        vec![directory.join("mixed.txt")]
+    );
+    assert_eq!(
+        fs::read_to_string(directory.join("mixed.txt")).unwrap(),
+        "one\ntwo\n"
+    );
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn language_profiles_expand_tabs_in_python_but_not_go() {
+    let directory = create_test_directory(
+        "language-profiles",
+        &[
+            ("main.go", "func main() {\n\treturn\n}\n"),
+            ("script.py", "def main():\n\treturn\n"),
+            (
+                "strings.py",
+                "def main():\n\ttext = '''\n\tkept\n'''\n\treturn text\n",
+            ),
+        ],
+    );
+
+    let report = run_in_test_directory(&directory, &[]);
+
+    assert!(report.cleaned_files.is_empty());
+
+    let report = run_in_test_directory(&directory, &["--language-profiles", "--sorted"]);
+
+    assert_eq!(
+        cleaned_file_paths(&report),
+        vec![directory.join("script.py"), directory.join("strings.py")]
+    );
+    assert_eq!(
+        fs::read_to_string(directory.join("script.py")).unwrap(),
+        "def main():\n    return\n"
+    );
+    // Expanding the tabs inside the string would change its value
+    assert_eq!(
+        fs::read_to_string(directory.join("strings.py")).unwrap(),
+        "def main():\n    text = '''\n\tkept\n'''\n    return text\n"
+    );
+    assert_eq!(
+        fs::read_to_string(directory.join("main.go")).unwrap(),
+        "func main() {\n\treturn\n}\n"
+    );
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn show_removed_is_part_of_the_report_so_json_output_stays_valid() {
+    let directory = create_test_directory("show-removed", &[("notes.txt", "note \t\nclean\n")]);
+
+    let directory_string = directory.display().to_string();
+    let args = LinemanArgs::from_iter([
+        "lineman",
+        "--path",
+        &directory_string,
+        "--show-removed",
+        "--check",
+        "--format",
+        "jsonl",
+    ]);
+    let mut output = Vec::new();
+    run(&args, &AtomicBool::new(false), &mut output).unwrap();
+
+    let output_lines: Vec<json::Value> = String::from_utf8(output)
+        .unwrap()
+        .lines()
+        .map(|line| json::parse(line).unwrap())
+        .collect();
+
+    assert_eq!(
+        output_lines[0].get("removed_trailing"),
+        Some(&json::Value::String(
+            "    line 1: removed \"\\x20\\t\"\n".to_string()
+        ))
+    );
+
+    let report = run_in_test_directory(&directory, &["--show-removed", "--check"]);
+    let mut output = Vec::new();
+    write_report(&mut output, &report, false).unwrap();
+
+    assert!(String::from_utf8(output).unwrap().contains(&format!(
+        "Removed Trailing Whitespace:\n    {}:\n        line 1: removed \"\\x20\\t\"\n",
+        directory.join("notes.txt").display()
+    )));
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn keep_one_trailing_blank_for_matching_files() {
+    let directory = create_test_directory(
+        "keep_one_trailing_blank",
+        &[
+            ("export.csv", "a,b\n1,2\n\n  \n\n"),
+            ("single.csv", "a,b\n\n"),
+            ("main.rs", "fn main() {}\n\n\n"),
+        ],
+    );
+
+    let report = run_in_test_directory(&directory, &["--keep-one-trailing-blank-for", "*.csv"]);
+
+    let mut cleaned_paths = cleaned_file_paths(&report);
+    cleaned_paths.sort();
+
+    assert_eq!(
+        cleaned_paths,
+        [directory.join("export.csv"), directory.join("main.rs")]
+    );
+    assert_eq!(
+        fs::read_to_string(directory.join("export.csv")).unwrap(),
+        "a,b\n1,2\n\n"
+    );
+    assert_eq!(
+        fs::read_to_string(directory.join("single.csv")).unwrap(),
+        "a,b\n\n"
+    );
+    assert_eq!(
+        fs::read_to_string(directory.join("main.rs")).unwrap(),
+        "fn main() {}\n"
+    );
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn max_consecutive_blank_lines_collapses_runs_anywhere_in_the_file() {
+    let args = LinemanArgs::from_iter(["lineman", "--serve", "--max-consecutive-blank-lines", "2"]);
+    let clean_options = root_clean_options(&args);
+    let clean = |input_lines: &[&str]| {
+        let (output_lines, applied_transforms) = clean_lines(input_lines, &clean_options);
+        (output_lines.concat(), applied_transforms.names())
+    };
+
+    assert_eq!(
+        clean(&["\n", "\n", "\n", "\n", "start\n"]),
+        ("\n\nstart\n".to_string(), vec!["blank-line-runs"])
+    );
+    assert_eq!(
+        clean(&["a\n", "\n", "\n", "\n", "\n", "\n", "\n", "b\n"]),
+        ("a\n\n\nb\n".to_string(), vec!["blank-line-runs"])
+    );
+    assert_eq!(
+        clean(&["a\n", "\n", "\n", "\n", "b\n", "\n", "\n", "\n"]),
+        (
+            "a\n\n\nb\n".to_string(),
+            vec!["trailing-blank-lines", "blank-line-runs"]
+        )
+    );
+    assert_eq!(
+        clean(&["a\n", "\n", "\n", "b\n", "\n", "c\n"]),
+        ("a\n\n\nb\n\nc\n".to_string(), vec![])
+    );
+
+    let args = LinemanArgs::from_iter([
+        "lineman",
+        "--serve",
+        "--max-consecutive-blank-lines",
+        "1",
+        "-d",
+    ]);
+    let (output_lines, _) = clean_lines(&["a\n", "\n", "\n", "\n"], &root_clean_options(&args));
+
+    assert_eq!(output_lines.concat(), "a\n\n");
+}
+
+#[test]
+fn dotenv_files_preserve_quoted_trailing_whitespace_automatically() {
+    let directory = create_test_directory(
+        "dotenv",
+        &[
+            (".env", "QUOTED=\"value   \nnext\"\nUNQUOTED=value   \n"),
+            (".bashrc", "alias ll='ls -l'   \n"),
+        ],
+    );
+
+    run_in_test_directory(&directory, &[]);
+
+    assert_eq!(
+        fs::read_to_string(directory.join(".env")).unwrap(),
+        "QUOTED=\"value   \nnext\"\nUNQUOTED=value\n"
+    );
+    assert_eq!(
+        fs::read_to_string(directory.join(".bashrc")).unwrap(),
+        "alias ll='ls -l'   \n"
+    );
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[cfg(test)]
+fn cleaned_file_paths(report: &Report) -> Vec<PathBuf> {
+    report
+        .cleaned_files
+        .iter()
+        .map(|(file_path, _)| file_path.clone())
+        .collect()
+}
+
+#[cfg(test)]
+fn run_in_test_directory(directory: &Path, extra_args: &[&str]) -> Report {
+    let directory_string = directory.display().to_string();
+    let mut args = vec!["lineman", "--path", &directory_string];
+    args.extend_from_slice(extra_args);
+
+    match run(
+        &LinemanArgs::from_iter(args),
+        &AtomicBool::new(false),
+        &mut io::sink(),
+    ) {
+        Ok(report) => report,
+        Err(error) => panic!("{:?}", error),
+    }
+}
+
+#[test]
+fn explain_sorts_files_into_matched_and_excluded_buckets() {
+    let directory = create_test_directory(
+        "explain",
+        &[
+            ("src/dirty.rs", "fn main() {}   \n"),
+            ("src/clean.rs", "fn main() {}\n"),
+            ("docs/dirty.md", "# Title   \n"),
+        ],
+    );
+
+    let report = run_in_test_directory(&directory, &["--include", "src/*.rs", "--explain"]);
+
+    assert_eq!(
+        cleaned_file_paths(&report),
+        [directory.join("src/dirty.rs")]
+    );
+    assert_eq!(
+        report.already_clean_file_paths,
+        [directory.join("src/clean.rs")]
+    );
+    assert_eq!(
+        report.excluded_file_paths,
+        [directory.join("docs/dirty.md")]
+    );
+    assert_eq!(
+        fs::read_to_string(directory.join("docs/dirty.md")).unwrap(),
+        "# Title   \n"
+    );
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn dir_glob_only_cleans_files_under_matching_directories() {
+    let directory = create_test_directory(
+        "dir-glob",
+        &[
+            ("db/migrations/001.sql", "create table users;   \n"),
+            ("app/migrations/nested/002.sql", "drop table users;   \n"),
+            ("db/schema.sql", "create table schema;   \n"),
+            ("migrations.sql", "select 1;   \n"),
+        ],
+    );
+
+    let report = run_in_test_directory(&directory, &["--dir-glob", "migration*"]);
+    let mut cleaned_paths = cleaned_file_paths(&report);
+    cleaned_paths.sort();
+
+    assert_eq!(
+        cleaned_paths,
+        vec![
+            directory.join("app/migrations/nested/002.sql"),
+            directory.join("db/migrations/001.sql"),
+        ]
+    );
+    assert_eq!(
+        fs::read_to_string(directory.join("db/schema.sql")).unwrap(),
+        "create table schema;   \n"
+    );
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn ignored_and_excluded_files_are_never_cleaned() {
+    let directory = create_test_directory(
+        "ignore",
+        &[
+            (".gitignore", "target/\n*.log\n!keep.log\n"),
+            (".git/notes.txt", "notes   \n"),
+            ("target/output.txt", "output   \n"),
+            ("debug.log", "debug   \n"),
+            ("keep.log", "keep   \n"),
+            ("nested/.ignore", "/local.txt\n"),
+            ("nested/local.txt", "local   \n"),
+            ("nested/deeper/local.txt", "deeper   \n"),
+            ("src/generated.rs", "generated   \n"),
+            ("src/main.rs", "main   \n"),
+        ],
+    );
+
+    let report = run_in_test_directory(&directory, &["--sorted", "--exclude", "src/generated.rs"]);
+
+    assert_eq!(
+        cleaned_file_paths(&report),
+        vec![
+            directory.join("keep.log"),
+            directory.join("nested/deeper/local.txt"),
+            directory.join("src/main.rs"),
+        ]
+    );
+    assert_eq!(
+        fs::read_to_string(directory.join("target/output.txt")).unwrap(),
+        "output   \n"
+    );
+    // The ignore files have no extension, so they are excluded along with `src/generated.rs`
+    assert_eq!(report.totals.files_excluded, 3);
+
+    let report = run_in_test_directory(
+        &directory,
+        &["--no-ignore", "--exclude", "src/generated.rs"],
+    );
+
+    assert_eq!(report.totals.files_cleaned, 4);
+    assert_eq!(
+        fs::read_to_string(directory.join("src/generated.rs")).unwrap(),
+        "generated   \n"
+    );
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn post_command_runs_once_per_cleaned_file() {
+    let directory = create_test_directory(
+        "post-command",
+        &[
+            ("root/dirty_one.txt", "one   \n"),
+            ("root/dirty_two.txt", "two\t\n"),
+            ("root/clean.txt", "clean\n"),
+        ],
+    );
+
+    let log_path = directory.join("post_command.log");
+    let post_command = format!("echo {{}} >> '{}'", log_path.display());
+    let report = run_in_test_directory(&directory.join("root"), &["--post-command", &post_command]);
+
+    let mut logged_paths: Vec<PathBuf> = fs::read_to_string(&log_path)
+        .unwrap()
+        .lines()
+        .map(PathBuf::from)
+        .collect();
+    logged_paths.sort();
+
+    let mut cleaned_file_paths = cleaned_file_paths(&report);
+    cleaned_file_paths.sort();
+
+    assert_eq!(cleaned_file_paths.len(), 2);
+    assert_eq!(logged_paths, cleaned_file_paths);
+    assert!(report.post_command_failures.is_empty());
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn post_command_failures_are_reported_without_aborting() {
+    let directory = create_test_directory(
+        "post-command-failure",
+        &[("dirty_one.txt", "one   \n"), ("dirty_two.txt", "two\t\n")],
+    );
+
+    let report = run_in_test_directory(&directory, &["--post-command", "exit 1"]);
+
+    assert_eq!(report.cleaned_files.len(), 2);
+    assert_eq!(report.post_command_failures.len(), 2);
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn sorted_processes_files_in_path_order() {
+    let directory = create_test_directory(
+        "sorted",
+        &[
+            ("root/zeta.txt", "zeta   \n"),
+            ("root/beta/inner.txt", "inner   \n"),
+            ("root/alpha.txt", "alpha   \n"),
+            ("root/beta.txt", "beta   \n"),
+            ("root/gamma/inner.txt", "inner   \n"),
+        ],
+    );
+
+    let log_path = directory.join("post_command.log");
+    let post_command = format!("echo {{}} >> '{}'", log_path.display());
+    let report = run_in_test_directory(
+        &directory.join("root"),
+        &["--sorted", "--post-command", &post_command],
+    );
+
+    let logged_paths: Vec<PathBuf> = fs::read_to_string(&log_path)
+        .unwrap()
+        .lines()
+        .map(PathBuf::from)
+        .collect();
+
+    let mut sorted_paths = logged_paths.clone();
+    sorted_paths.sort();
+
+    assert_eq!(logged_paths.len(), 5);
+    assert_eq!(logged_paths, sorted_paths);
+    assert_eq!(cleaned_file_paths(&report), sorted_paths);
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn skip_high_entropy_skips_random_bytes_and_keeps_text() {
+    let mut state: u32 = 0x2545_f491;
+    let random_bytes: Vec<u8> = (0..ENTROPY_SAMPLE_SIZE)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect();
+
+    let directory = create_test_directory("high-entropy", &[("text.txt", "let x = 1;   \n")]);
+    fs::write(directory.join("blob.bin"), &random_bytes).unwrap();
+
+    let report = run_in_test_directory(&directory, &["--skip-high-entropy"]);
+
+    assert_eq!(report.high_entropy_file_paths, [directory.join("blob.bin")]);
+    assert_eq!(cleaned_file_paths(&report), [directory.join("text.txt")]);
+    assert_eq!(fs::read(directory.join("blob.bin")).unwrap(), random_bytes);
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn binary_files_are_skipped_unless_no_skip_binary_is_passed() {
+    let directory = create_test_directory(
+        "binary",
+        &[
+            ("empty.txt", ""),
+            ("unicode.txt", "héllo wörld   \n"),
+            ("image.png", "PNG\r\n\u{1a}\n\0\0\0   \n"),
+            ("controls.dat", "\u{1}\u{2}\u{3}\u{4}ab   \n"),
+        ],
+    );
+
+    let report = run_in_test_directory(&directory, &["--sorted"]);
+
+    assert_eq!(
+        report.binary_file_paths,
+        vec![directory.join("controls.dat"), directory.join("image.png")]
+    );
+    assert_eq!(
+        cleaned_file_paths(&report),
+        vec![directory.join("unicode.txt")]
+    );
+    assert_eq!(report.totals.files_already_clean, 1);
+    assert_eq!(
+        fs::read_to_string(directory.join("image.png")).unwrap(),
+        "PNG\r\n\u{1a}\n\0\0\0   \n"
+    );
+
+    let report = run_in_test_directory(&directory, &["--no-skip-binary"]);
+
+    assert!(report.binary_file_paths.is_empty());
+    assert_eq!(report.totals.files_cleaned, 2);
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn skip_summary_tallies_skipped_files_by_reason() {
+    let directory = create_test_directory(
+        "skip-summary",
+        &[
+            ("clean.txt", "clean\n"),
+            ("image.png", "PNG\r\n\u{1a}\n\0\0\0   \n"),
+            ("controls.dat", "\u{1}\u{2}\u{3}\u{4}ab   \n"),
+            ("long.txt", "a line longer than the limit   \n"),
+        ],
+    );
+    fs::write(directory.join("latin1.txt"), b"caf\xe9   \n").unwrap();
+
+    let directory_string = directory.display().to_string();
+    let text_report = |extra_args: &[&str]| {
+        let mut args = vec![
+            "lineman",
+            "--path",
+            &directory_string,
+            "--max-line-bytes",
+            "16",
+        ];
+        args.extend_from_slice(extra_args);
+        let args = LinemanArgs::from_iter(args);
+
+        let report = run(&args, &AtomicBool::new(false), &mut io::sink()).unwrap();
+        let mut output = Vec::new();
+        write_text_report(&mut output, &args, &report).unwrap();
+
+        String::from_utf8(output).unwrap()
+    };
+
+    let output = text_report(&["--skip-summary"]);
+
+    assert_eq!(
+        output.lines().next(),
+        Some("Skipped: 1 not opened, 1 line too long, 2 binary")
+    );
+    assert!(!output.contains("Skipped Files"));
+
+    let output = text_report(&["--skip-summary", "--verbose"]);
+
+    assert_eq!(
+        output.lines().next(),
+        Some("Skipped: 1 not opened, 1 line too long, 2 binary")
+    );
+    assert!(output.contains("Skipped Files (Binary):\n"));
+    assert!(output.contains("Skipped Files (Line Too Long):\n"));
+    assert!(output.contains(&format!(
+        "Skipped Files:\n    {}\n",
+        directory.join("latin1.txt").display()
+    )));
+
+    assert_eq!(
+        format_skip_summary(&ReportTotals::default()),
+        "Skipped: none"
+    );
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn byte_order_marks_are_kept_unless_strip_bom_is_passed() {
+    let directory = create_test_directory(
+        "byte-order-mark",
+        &[
+            ("only_mark.txt", "\u{feff}"),
+            ("content.txt", "\u{feff}\tindented   \nlast\n\n"),
+            ("clean.txt", "\u{feff}clean\n"),
+        ],
+    );
+
+    let report = run_in_test_directory(&directory, &["--sorted", "--tabs-to-spaces", "4"]);
+
+    assert_eq!(
+        cleaned_file_paths(&report),
+        vec![directory.join("content.txt")]
+    );
+    assert_eq!(
+        fs::read_to_string(directory.join("only_mark.txt")).unwrap(),
+        "\u{feff}"
+    );
+    assert_eq!(
+        fs::read_to_string(directory.join("content.txt")).unwrap(),
+        "\u{feff}    indented\nlast\n"
+    );
+
+    let report = run_in_test_directory(&directory, &["--sorted", "--strip-bom"]);
+
+    assert_eq!(
+        cleaned_file_paths(&report),
+        vec![
+            directory.join("clean.txt"),
+            directory.join("content.txt"),
+            directory.join("only_mark.txt"),
+        ]
+    );
+    assert!(report
+        .cleaned_files
+        .iter()
+        .all(|(_, file_stats)| file_stats.applied_transforms.names() == ["byte-order-mark"]));
+    assert_eq!(
+        fs::read_to_string(directory.join("only_mark.txt")).unwrap(),
+        ""
+    );
+    assert_eq!(
+        fs::read_to_string(directory.join("content.txt")).unwrap(),
+        "    indented\nlast\n"
+    );
+
+    let streamed_content = format!("\u{feff}streamed   \n{}", "more\n".repeat(8));
+    fs::write(directory.join("content.txt"), &streamed_content).unwrap();
+    let report = run_in_test_directory(&directory, &["--strip-bom", "--memory-budget", "32"]);
+
+    assert!(report.cleaned_files[0].1.streamed);
+    assert_eq!(
+        fs::read_to_string(directory.join("content.txt")).unwrap(),
+        format!("streamed\n{}", "more\n".repeat(8))
+    );
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn interrupt_stops_the_run_before_the_next_file() {
+    let directory = create_test_directory(
+        "interrupt",
+        &[("dirty_one.txt", "one   \n"), ("dirty_two.txt", "two\t\n")],
+    );
+
+    // Each file's line is streamed as soon as the file is done, so the first one interrupts the run
+    struct InterruptingWriter<'a>(&'a AtomicBool);
+
+    impl Write for InterruptingWriter<'_> {
+        fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+            self.0.store(true, Ordering::SeqCst);
+            Ok(buffer.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let directory_string = directory.display().to_string();
+    let args = LinemanArgs::from_iter([
+        "lineman",
+        "--path",
+        &directory_string,
+        "--sorted",
+        "--format",
+        "jsonl",
+    ]);
+    let interrupted = AtomicBool::new(false);
+
+    let report = match run(&args, &interrupted, &mut InterruptingWriter(&interrupted)) {
+        Ok(report) => report,
+        Err(error) => panic!("{:?}", error),
+    };
+
+    assert!(report.interrupted);
+    assert_eq!(report.totals.files_cleaned, 1);
+    assert_eq!(
+        fs::read_to_string(directory.join("dirty_one.txt")).unwrap(),
+        "one\n"
+    );
+    assert_eq!(
+        fs::read_to_string(directory.join("dirty_two.txt")).unwrap(),
+        "two\t\n"
+    );
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn report_columns_are_aligned_across_files() {
+    let report = Report {
+        cleaned_files: vec![
+            (
+                PathBuf::from("src/main.rs"),
+                FileStats {
+                    lines_changed: 12,
+                    bytes_saved: 140,
+                    applied_transforms: AppliedTransforms::default(),
+                    streamed: false,
+                    trailing_blank_lines: 0,
+                    expected_trailing_blank_lines: 0,
+                    issues: Vec::new(),
+                    removed_trailing: String::new(),
+                },
+            ),
+            (
+                PathBuf::from("src/nested/module.rs"),
+                FileStats {
+                    lines_changed: 3,
+                    bytes_saved: 9,
+                    applied_transforms: AppliedTransforms::default(),
+                    streamed: false,
+                    trailing_blank_lines: 0,
+                    expected_trailing_blank_lines: 0,
+                    issues: Vec::new(),
+                    removed_trailing: String::new(),
+                },
+            ),
+        ],
+        skipped_files: vec![(
+            PathBuf::from("src/unreadable.rs"),
+            SkipReason::FileNotOpened,
+        )],
+        post_command_failures: vec![(PathBuf::from("src/main.rs"), "exit status: 1".to_string())],
+        ..Report::default()
+    };
+
+    let expected_output = concat!(
+        "Cleaned Files:\n",
+        "    src/main.rs           12 lines changed  140 bytes saved\n",
+        "    src/nested/module.rs   3 lines changed    9 bytes saved\n",
+        "Skipped Files:\n",
+        "    src/unreadable.rs\n",
+        "Post-Command Failures:\n",
+        "    src/main.rs           exit status: 1\n",
+    );
+
+    let mut output = Vec::new();
+    write_report(&mut output, &report, false).unwrap();
+
+    assert_eq!(String::from_utf8(output).unwrap(), expected_output);
+}
+
+#[test]
+fn grouped_report_nests_files_under_their_directories() {
+    let cleaned_file = |path: &str, lines_changed, bytes_saved| {
+        (
+            PathBuf::from(path),
+            FileStats {
+                lines_changed,
+                bytes_saved,
+                applied_transforms: AppliedTransforms::default(),
+                streamed: false,
+                trailing_blank_lines: 0,
+                expected_trailing_blank_lines: 0,
+                issues: Vec::new(),
+                removed_trailing: String::new(),
+            },
+        )
+    };
+
+    let report = Report {
+        cleaned_files: vec![
+            cleaned_file("project/src/nested/module.rs", 3, 9),
+            cleaned_file("project/src/main.rs", 12, 140),
+            cleaned_file("project/build.rs", 1, 2),
+        ],
+        skipped_files: vec![
+            (
+                PathBuf::from("project/src/unreadable.rs"),
+                SkipReason::FileNotOpened,
+            ),
+            (
+                PathBuf::from("project/src/locked.rs"),
+                SkipReason::FileNotCleaned,
+            ),
+        ],
+        layout: ReportLayout {
+            indent: " ".repeat(2),
+            group_by_directory: true,
+            ..ReportLayout::default()
+        },
+        ..Report::default()
+    };
+
+    let expected_output = concat!(
+        "Cleaned Files:\n",
+        "  project/\n",
+        "    build.rs        1 lines changed    2 bytes saved\n",
+        "    src/\n",
+        "      main.rs      12 lines changed  140 bytes saved\n",
+        "      nested/\n",
+        "        module.rs   3 lines changed    9 bytes saved\n",
+        "Skipped Files:\n",
+        "  project/src/\n",
+        "    locked.rs\n",
+        "    unreadable.rs\n",
+    );
+
+    let mut output = Vec::new();
+    write_report(&mut output, &report, false).unwrap();
+
+    assert_eq!(String::from_utf8(output).unwrap(), expected_output);
+}
+
+#[test]
+fn explain_changes_lists_the_transforms_applied_to_each_file() {
+    let directory = create_test_directory(
+        "explain-changes",
+        &[("both.txt", "trailing   \nno newline")],
+    );
+
+    let report = run_in_test_directory(&directory, &["--explain-changes"]);
+
+    assert_eq!(
+        report.cleaned_files[0].1.applied_transforms,
+        AppliedTransforms {
+            trailing_whitespace: true,
+            final_newline: true,
+            trailing_blank_lines: false,
+            line_endings: false,
+            blank_line_runs: false,
+            indentation: false,
+            byte_order_mark: false,
+        }
+    );
+
+    let mut output = Vec::new();
+    write_report(&mut output, &report, false).unwrap();
+
+    assert!(String::from_utf8(output)
+        .unwrap()
+        .contains("bytes saved  (trailing-whitespace, final-newline)\n"));
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn preserve_hardlinks_keeps_both_paths_on_the_same_inode() {
+    use std::os::unix::fs::MetadataExt;
+
+    let directory = create_test_directory("hardlinks", &[("original.txt", "linked   \n")]);
+    fs::hard_link(directory.join("original.txt"), directory.join("link.txt")).unwrap();
+
+    let report = run_in_test_directory(&directory, &["--preserve-hardlinks"]);
+
+    let original_metadata = fs::metadata(directory.join("original.txt")).unwrap();
+    let link_metadata = fs::metadata(directory.join("link.txt")).unwrap();
+
+    assert_eq!(original_metadata.ino(), link_metadata.ino());
+    assert_eq!(original_metadata.nlink(), 2);
+    assert_eq!(
+        fs::read_to_string(directory.join("link.txt")).unwrap(),
+        "linked\n"
+    );
+    assert_eq!(report.cleaned_files.len(), 1);
+    assert_eq!(report.hardlinked_file_paths, cleaned_file_paths(&report));
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn files_are_cleaned_the_same_without_per_file_syncs() {
+    let file_contents = [
+        ("small.rs", "fn small() {}   \n\n\n"),
+        ("large.rs", &"let x = 1;  \n".repeat(10)),
+    ];
+
+    for sync_args in [&["--no-fsync"][..], &["--fsync-at-end"]] {
+        let directory = create_test_directory("no-fsync", &file_contents);
+        let mut args = vec!["--memory-budget", "64"];
+        args.extend(sync_args);
+
+        let report = run_in_test_directory(&directory, &args);
+
+        assert_eq!(report.totals.files_cleaned, 2);
+        assert_eq!(
+            fs::read_to_string(directory.join("small.rs")).unwrap(),
+            "fn small() {}\n"
+        );
+        assert_eq!(
+            fs::read_to_string(directory.join("large.rs")).unwrap(),
+            "let x = 1;\n".repeat(10)
+        );
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
+
+#[test]
+fn metrics_file_lists_run_totals() {
+    let directory = create_test_directory(
+        "metrics",
+        &[
+            ("root/dirty_one.txt", "one   \n"),
+            ("root/dirty_two.txt", "two\n\n\n"),
+            ("root/clean.txt", "clean\n"),
+        ],
+    );
+
+    let metrics_path = directory.join("metrics.txt");
+    let report = run_in_test_directory(&directory.join("root"), &[]);
+
+    if let Err(error) = write_metrics(&metrics_path, &report) {
+        panic!("{:?}", error);
+    }
+
+    assert_eq!(
+        fs::read_to_string(&metrics_path).unwrap(),
+        concat!(
+            "files_cleaned=2\n",
+            "lines_changed=3\n",
+            "bytes_saved=5\n",
+            "files_skipped=0\n",
+            "files_skipped_high_entropy=0\n",
+            "post_command_failures=0\n",
+            "files_skipped_protected=0\n",
+            "files_skipped_outside_root=0\n",
+            "verification_failures=0\n",
+            "walk_errors=0\n",
+            "files_already_clean=1\n",
+            "files_excluded=0\n",
+            "write_verification_failures=0\n",
+            "self_check_failures=0\n",
+            "files_missing=0\n",
+            "files_skipped_over_memory_budget=0\n",
+            "files_skipped_untracked=0\n",
+            "files_skipped_line_too_long=0\n",
+            "files_skipped_in_use=0\n",
+            "files_skipped_binary=0\n",
+            "unexpected_line_endings=0\n",
+        )
+    );
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn metrics_count_every_outcome() {
+    let outcomes = [
+        FileOutcome::Cleaned {
+            file_stats: FileStats {
+                lines_changed: 1,
+                bytes_saved: 1,
+                applied_transforms: AppliedTransforms::default(),
+                streamed: false,
+                trailing_blank_lines: 0,
+                expected_trailing_blank_lines: 0,
+                issues: Vec::new(),
+                removed_trailing: String::new(),
+            },
+            hardlinked: false,
+            post_command_failure: None,
+            violates_final_newline_policy: false,
+            trailing_whitespace_authors: Vec::new(),
+        },
+        FileOutcome::AlreadyClean,
+        FileOutcome::Excluded,
+        FileOutcome::Skipped(SkipReason::FileNotCleaned),
+        FileOutcome::SkippedHighEntropy,
+        FileOutcome::SkippedProtected,
+        FileOutcome::SkippedOutsideRoot,
+        FileOutcome::FailedVerification,
+        FileOutcome::FailedWriteVerification,
+        FileOutcome::FailedSelfCheck,
+        FileOutcome::Missing,
+        FileOutcome::SkippedOverMemoryBudget,
+        FileOutcome::SkippedUntracked,
+        FileOutcome::SkippedLineTooLong,
+        FileOutcome::SkippedInUse,
+        FileOutcome::SkippedBinary,
+        FileOutcome::UnexpectedLineEnding(LineEndingCounts::default()),
+    ];
+
+    for outcome in &outcomes {
+        // Matching every variant keeps a new one from being added without being listed above
+        match outcome {
+            FileOutcome::Cleaned { .. }
+            | FileOutcome::AlreadyClean
+            | FileOutcome::Excluded
+            | FileOutcome::Skipped(_)
+            | FileOutcome::SkippedHighEntropy
+            | FileOutcome::SkippedProtected
+            | FileOutcome::SkippedOutsideRoot
+            | FileOutcome::FailedVerification
+            | FileOutcome::FailedWriteVerification
+            | FileOutcome::FailedSelfCheck
+            | FileOutcome::Missing
+            | FileOutcome::SkippedOverMemoryBudget
+            | FileOutcome::SkippedUntracked
+            | FileOutcome::SkippedLineTooLong
+            | FileOutcome::SkippedInUse
+            | FileOutcome::SkippedBinary
+            | FileOutcome::UnexpectedLineEnding(_) => {}
+        }
+
+        let mut report = Report::default();
+        report.totals.count(outcome);
+
+        assert!(
+            format_metrics(&report)
+                .lines()
+                .any(|line| line.ends_with("=1")),
+            "no metric counts {}",
+            format_json_line(Path::new("file"), outcome)
+        );
+    }
+}
+
+#[test]
+fn report_file_is_written_independently_of_the_console_format() {
+    let directory = create_test_directory("report-file", &[("dirty.txt", "dirty   \n")]);
+    let directory_string = directory.display().to_string();
+    let report_file = directory.with_extension("report");
+    let report_file_string = report_file.display().to_string();
+
+    let args = LinemanArgs::from_iter([
+        "lineman",
+        "--path",
+        &directory_string,
+        "--format",
+        "human",
+        "--report-file",
+        &report_file_string,
+    ]);
+    let report = run(&args, &AtomicBool::new(false), &mut io::sink()).unwrap();
+
+    let mut console_output = Vec::new();
+    write_text_report(&mut console_output, &args, &report).unwrap();
+    write_report_file(&report_file, &args, &report).unwrap();
+
+    let dirty_path = json::quote(&directory.join("dirty.txt").display().to_string());
+
+    assert!(String::from_utf8(console_output)
+        .unwrap()
+        .starts_with("Cleaned Files:\n"));
+    assert_eq!(
+        fs::read_to_string(&report_file)
+            .unwrap()
+            .lines()
+            .map(|line| json::parse(line).unwrap().get("path").cloned())
+            .collect::<Vec<_>>(),
+        vec![Some(json::parse(&dirty_path).unwrap()), None]
+    );
+
+    fs::write(directory.join("dirty.txt"), "dirty   \n").unwrap();
+
+    let args = LinemanArgs::from_iter([
+        "lineman",
+        "--path",
+        &directory_string,
+        "--format",
+        "jsonl",
+        "--report-file",
+        &report_file_string,
+        "--report-file-format",
+        "text",
+    ]);
+    let mut console_output = Vec::new();
+    let report = run(&args, &AtomicBool::new(false), &mut console_output).unwrap();
+    write_report_file(&report_file, &args, &report).unwrap();
+
+    assert!(String::from_utf8(console_output)
+        .unwrap()
+        .lines()
+        .all(|line| json::parse(line).is_ok()));
+    assert!(fs::read_to_string(&report_file)
+        .unwrap()
+        .starts_with("Cleaned Files:\n"));
+
+    fs::remove_file(report_file).unwrap();
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn files_matching_a_protected_hash_are_left_unchanged() {
+    let vendored_content = "vendored   \n";
+    let directory = create_test_directory(
+        "protect-hashes",
+        &[
+            ("root/vendored.txt", vendored_content),
+            ("root/own.txt", "own   \n"),
+            (
+                "hashes.txt",
+                &format!(
+                    "{}  vendored.txt\n",
+                    sha256::hex_digest(vendored_content.as_bytes())
+                ),
+            ),
+        ],
+    );
+
+    let hashes_path = directory.join("hashes.txt").display().to_string();
+    let report =
+        run_in_test_directory(&directory.join("root"), &["--protect-hashes", &hashes_path]);
+
+    assert_eq!(
+        report.protected_file_paths,
+        [directory.join("root/vendored.txt")]
+    );
+    assert_eq!(
+        cleaned_file_paths(&report),
+        [directory.join("root/own.txt")]
+    );
+    assert_eq!(
+        fs::read_to_string(directory.join("root/vendored.txt")).unwrap(),
+        vendored_content
+    );
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn jsonl_report_lines_parse_independently() {
+    let directory = create_test_directory(
+        "jsonl",
+        &[
+            ("dirty \"quoted\".txt", "dirty   \n"),
+            ("clean.txt", "clean\n"),
+        ],
+    );
+
+    let directory_string = directory.display().to_string();
+    let args =
+        LinemanArgs::from_iter(["lineman", "--path", &directory_string, "--format", "jsonl"]);
+    let mut output = Vec::new();
+
+    let report = match run(&args, &AtomicBool::new(false), &mut output) {
+        Ok(report) => report,
+        Err(error) => panic!("{:?}", error),
+    };
+
+    let output = String::from_utf8(output).unwrap();
+    let values: Vec<json::Value> = output
+        .lines()
+        .map(|line| json::parse(line).unwrap())
+        .collect();
+
+    let mut outcomes: Vec<(Option<&json::Value>, Option<&json::Value>)> = values
+        .iter()
+        .take(2)
+        .map(|value| (value.get("outcome"), value.get("bytes_saved")))
+        .collect();
+    outcomes.sort_by_key(|(outcome, _)| format!("{:?}", outcome));
+
+    assert_eq!(values.len(), 3);
+    assert_eq!(
+        outcomes,
+        [
+            (
+                Some(&json::Value::String("already_clean".to_string())),
+                Some(&json::Value::Number(0.0))
+            ),
+            (
+                Some(&json::Value::String("cleaned".to_string())),
+                Some(&json::Value::Number(3.0))
+            ),
+        ]
+    );
+    assert_eq!(
+        values[2]
+            .get("summary")
+            .and_then(|summary| summary.get("files_cleaned")),
+        Some(&json::Value::Number(1.0))
+    );
+    assert!(report.cleaned_files.is_empty());
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn estimate_totals_changes_without_writing() {
+    let directory = create_test_directory(
+        "estimate",
+        &[
+            ("src/main.rs", "fn main() {   \n    run();   \n}\n\n\n"),
+            ("src/lib.rs", "pub fn run() {}\t\n"),
+            ("scripts/build.py", "print('build')  \n"),
+            ("scripts/clean.py", "print('clean')\n"),
+        ],
+    );
+
+    let report = run_in_test_directory(&directory, &["--estimate"]);
+
+    assert_eq!(
+        format_estimate(&report),
+        concat!(
+            "Estimate:\n",
+            "    Files touched: 3\n",
+            "    Lines changed: 6\n",
+            "    Bytes saved: 11\n",
+            "    By extension:\n",
+            "        py: 1 files, 1 lines changed, 2 bytes saved\n",
+            "        rs: 2 files, 5 lines changed, 9 bytes saved\n",
+        )
+    );
+    assert_eq!(
+        fs::read_to_string(directory.join("src/lib.rs")).unwrap(),
+        "pub fn run() {}\t\n"
+    );
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn symlinks_to_files_outside_the_root_are_skipped() {
+    let directory = create_test_directory(
+        "outside-root",
+        &[
+            ("outside/target.txt", "outside   \n"),
+            ("root/inside.txt", "inside   \n"),
+        ],
+    );
+
+    std::os::unix::fs::symlink(
+        directory.join("outside/target.txt"),
+        directory.join("root/link.txt"),
+    )
+    .unwrap();
+    std::os::unix::fs::symlink(
+        directory.join("root/inside.txt"),
+        directory.join("root/inside_link.txt"),
+    )
+    .unwrap();
+
+    let report = run_in_test_directory(&directory.join("root"), &["--follow-symlinks"]);
+
+    assert_eq!(
+        report.outside_root_file_paths,
+        [directory.join("root/link.txt")]
+    );
+    assert_eq!(
+        fs::read_to_string(directory.join("outside/target.txt")).unwrap(),
+        "outside   \n"
+    );
+    assert_eq!(
+        fs::read_to_string(directory.join("root/inside.txt")).unwrap(),
+        "inside\n"
+    );
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn stable_retries_the_walk_when_an_entry_vanishes() {
+    let directory = create_test_directory("stable", &[("landed.txt", "landed   \n")]);
+    let vanishing_path = directory.join("vanishing.txt");
+
+    // A symlink to a missing file looks, to a walk following links, like a file deleted after listing
+    std::os::unix::fs::symlink(directory.join("missing.txt"), &vanishing_path).unwrap();
+
+    let directory_string = directory.display().to_string();
+    let args =
+        LinemanArgs::from_iter(["lineman", "--path", &directory_string, "--follow-symlinks"]);
+    let mut attempts = 0;
+
+    let walk_retries = walk_until_stable(3, || {
+        attempts += 1;
+
+        if attempts == 2 {
+            fs::remove_file(&vanishing_path).unwrap();
+        }
+
+        tree_changed_while_listed(
+            &args,
+            std::slice::from_ref(&directory),
+            &AtomicBool::new(false),
+        )
+    });
+
+    assert_eq!(attempts, 2);
+    assert_eq!(walk_retries, 1);
+    assert_eq!(
+        format_walk_retries(walk_retries),
+        "The tree changed during the walk, so it was retried once"
+    );
+    // Listing the tree never cleans it, so the cleaning walk still finds the file dirty
+    assert_eq!(
+        fs::read_to_string(directory.join("landed.txt")).unwrap(),
+        "landed   \n"
+    );
+
+    std::os::unix::fs::symlink(directory.join("missing.txt"), &vanishing_path).unwrap();
+
+    let report = run_in_test_directory(
+        &directory,
+        &["--follow-symlinks", "--stable", "--stable-retries", "2"],
+    );
+
+    assert_eq!(report.walk_retries, 2);
+    assert_eq!(report.walk_dir_errors.len(), 1);
+    assert_eq!(
+        cleaned_file_paths(&report),
+        vec![directory.join("landed.txt")]
+    );
+    assert_eq!(report.totals.files_cleaned, 1);
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn until_stable_settles_a_dirty_tree_in_two_passes() {
+    let directory = create_test_directory(
+        "until_stable",
+        &[("dirty.txt", "dirty   \n\n\n"), ("clean.txt", "clean\n")],
+    );
+
+    let report = run_in_test_directory(&directory, &["--until-stable"]);
+
+    assert_eq!(report.passes, 2);
+    assert_eq!(cleaned_file_paths(&report), [directory.join("dirty.txt")]);
+    assert_eq!(
+        fs::read_to_string(directory.join("dirty.txt")).unwrap(),
+        "dirty\n"
+    );
+    assert_eq!(
+        run_in_test_directory(&directory, &["--until-stable"]).passes,
+        1
+    );
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn verify_still_cleans_files_with_only_whitespace_changes() {
+    let directory = create_test_directory("verify", &[("file.txt", "let value = 1;   \n")]);
+    let path = directory.join("file.txt");
+
+    let report = run_in_test_directory(&directory, &["--verify"]);
+
+    assert!(report.verification_failed_file_paths.is_empty());
+    assert_eq!(fs::read_to_string(&path).unwrap(), "let value = 1;\n");
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn verify_writes_passes_an_intact_write() {
+    let directory = create_test_directory("verify_writes", &[("file.txt", "let value = 1;   \n")]);
+    let path = directory.join("file.txt");
+
+    let report = run_in_test_directory(&directory, &["--verify-writes"]);
+
+    assert!(report.write_verification_failed_file_paths.is_empty());
+    assert_eq!(cleaned_file_paths(&report), [directory.join("file.txt")]);
+    assert_eq!(fs::read_to_string(&path).unwrap(), "let value = 1;\n");
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn self_check_passes_the_built_in_cleaning() {
+    let directory = create_test_directory("self_check", &[("file.txt", "let value = 1;   \n")]);
+    let path = directory.join("file.txt");
+
+    let report = run_in_test_directory(&directory, &["--self-check"]);
+
+    assert!(report.self_check_failed_file_paths.is_empty());
+    assert_eq!(fs::read_to_string(&path).unwrap(), "let value = 1;\n");
+
+    fs::write(&path, "a  \nb  \nc  \n").unwrap();
+
+    let report = run_in_test_directory(&directory, &["--self-check", "--memory-budget", "10"]);
+
+    assert!(report.self_check_failed_file_paths.is_empty());
+    assert_eq!(fs::read_to_string(&path).unwrap(), "a\nb\nc\n");
+    assert!(report.cleaned_files[0].1.streamed);
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn nested_config_disables_eof_normalization_for_its_subtree_only() {
+    let directory = create_test_directory(
+        "nested-config",
+        &[
+            ("top.txt", "top"),
+            (
+                "generated/.lineman.toml",
+                "normalize_eof_newlines = false\n",
+            ),
+            ("generated/output.txt", "output   "),
+            ("generated/deeper/output.txt", "deeper\n\n\n"),
+            ("src/main.txt", "main"),
+        ],
+    );
+
+    run_in_test_directory(&directory, &["--extensions", "txt"]);
+
+    assert_eq!(
+        fs::read_to_string(directory.join("top.txt")).unwrap(),
+        "top\n"
+    );
+    assert_eq!(
+        fs::read_to_string(directory.join("generated/output.txt")).unwrap(),
+        "output"
+    );
+    assert_eq!(
+        fs::read_to_string(directory.join("generated/deeper/output.txt")).unwrap(),
+        "deeper\n\n\n"
+    );
+    assert_eq!(
+        fs::read_to_string(directory.join("src/main.txt")).unwrap(),
+        "main\n"
+    );
+
+    fs::write(
+        directory.join("generated/deeper/.lineman.toml"),
+        "normalize_eof_newlines = true\n",
+    )
+    .unwrap();
+
+    run_in_test_directory(&directory, &["--extensions", "txt"]);
+
+    assert_eq!(
+        fs::read_to_string(directory.join("generated/deeper/output.txt")).unwrap(),
+        "deeper\n"
+    );
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn malformed_nested_config_fails_the_run_before_any_file_is_written() {
+    let directory = create_test_directory(
+        "malformed-config",
+        &[
+            ("a.txt", "first   \n"),
+            ("z/deeper/.lineman.toml", "normalize_eof_newlines = maybe\n"),
+            ("z/deeper/last.txt", "last   \n"),
+        ],
+    );
+
+    let directory_string = directory.display().to_string();
+    let args = LinemanArgs::from_iter(["lineman", "--path", &directory_string, "--sorted"]);
+
+    assert!(matches!(
+        run(&args, &AtomicBool::new(false), &mut io::sink()),
+        Err(LinemanApplicationError::ConfigNotRead(_))
+    ));
+    assert_eq!(
+        fs::read_to_string(directory.join("a.txt")).unwrap(),
+        "first   \n"
+    );
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn exclude_extensions_removes_files_from_the_candidate_set() {
+    let directory = create_test_directory(
+        "exclude-extensions",
+        &[
+            ("main.rs", "fn main() {}   "),
+            ("notes.txt", "notes   "),
+            ("logo.svg", "<svg/>   "),
+        ],
+    );
+
+    let report = run_in_test_directory(&directory, &["--exclude-extensions", "svg"]);
+    let mut cleaned_paths = cleaned_file_paths(&report);
+    cleaned_paths.sort();
+
+    assert_eq!(
+        cleaned_paths,
+        vec![directory.join("main.rs"), directory.join("notes.txt")]
+    );
+    assert_eq!(
+        fs::read_to_string(directory.join("logo.svg")).unwrap(),
+        "<svg/>   "
+    );
+
+    fs::write(directory.join("main.rs"), "fn main() {}   ").unwrap();
+    fs::write(directory.join("notes.txt"), "notes   ").unwrap();
+
+    let report = run_in_test_directory(
+        &directory,
+        &["--extensions", "rs,txt", "--exclude-extensions", "txt"],
+    );
+
+    assert_eq!(cleaned_file_paths(&report), vec![directory.join("main.rs")]);
+    assert_eq!(
+        fs::read_to_string(directory.join("notes.txt")).unwrap(),
+        "notes   "
+    );
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn no_final_newline_extensions_only_skips_normalization_for_those_files() {
+    let directory = create_test_directory(
+        "no-final-newline-extensions",
+        &[
+            ("main.rs", "fn main() {}\n\n\n"),
+            ("fixture.txt", "expected output   \n\n"),
+            ("exact.txt", "no newline"),
+        ],
+    );
+
+    run_in_test_directory(
+        &directory,
+        &[
+            "--extensions",
+            "rs,txt",
+            "--no-final-newline-extensions",
+            "txt",
+        ],
+    );
+
+    assert_eq!(
+        fs::read_to_string(directory.join("main.rs")).unwrap(),
+        "fn main() {}\n"
+    );
+    assert_eq!(
+        fs::read_to_string(directory.join("fixture.txt")).unwrap(),
+        "expected output\n\n"
+    );
+    assert_eq!(
+        fs::read_to_string(directory.join("exact.txt")).unwrap(),
+        "no newline"
+    );
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn check_lists_files_missing_a_final_newline_and_fails() {
+    let directory = create_test_directory(
+        "check",
+        &[
+            ("no_newline.txt", "last line"),
+            ("trailing.txt", "trailing   \n"),
+        ],
+    );
+
+    let report = run_in_test_directory(&directory, &["--check"]);
+    let missing_path = directory.join("no_newline.txt");
+
+    assert!(check_failed(&report));
+    assert_eq!(
+        report.missing_final_newline_file_paths,
+        vec![missing_path.clone()]
+    );
+    assert_eq!(fs::read_to_string(&missing_path).unwrap(), "last line");
+
+    let mut output = Vec::new();
+    write_report(&mut output, &report, false).unwrap();
+
+    assert!(String::from_utf8(output).unwrap().contains(&format!(
+        "Missing final newline:\n    {}\n",
+        missing_path.display()
+    )));
+
+    run_in_test_directory(&directory, &[]);
+
+    assert!(!check_failed(&run_in_test_directory(
+        &directory,
+        &["--check"]
+    )));
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn check_lists_only_the_files_that_would_change() {
+    let directory = create_test_directory(
+        "check-would-clean",
+        &[("clean.txt", "clean\n"), ("dirty.txt", "dirty   \n")],
+    );
+
+    let report = run_in_test_directory(&directory, &["--check"]);
+    let dirty_path = directory.join("dirty.txt");
+
+    assert!(check_failed(&report));
+    assert_eq!(
+        report
+            .cleaned_files
+            .iter()
+            .map(|(file_path, _)| file_path.clone())
+            .collect::<Vec<_>>(),
+        vec![dirty_path.clone()]
+    );
+    assert_eq!(fs::read_to_string(&dirty_path).unwrap(), "dirty   \n");
+
+    let mut output = Vec::new();
+    write_report(&mut output, &report, false).unwrap();
+    let output = String::from_utf8(output).unwrap();
+
+    assert!(output.starts_with("Files That Would Be Cleaned:\n"));
+    assert!(!output.contains("clean.txt"));
+
+    fs::remove_file(&dirty_path).unwrap();
+
+    assert!(failure_exit_code(
+        &LinemanArgs::from_iter(["lineman", "--check", "--path", "unused"]),
+        &run_in_test_directory(&directory, &["--check"])
+    )
+    .is_none());
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn check_reports_how_the_end_of_each_file_is_wrong() {
+    let directory = create_test_directory(
+        "check-end-of-file",
+        &[
+            ("blank_lines.txt", "content\n\n\n\n"),
+            ("no_newline.txt", "content"),
+            ("one_blank_line.txt", "content\n  \n"),
+            ("trailing.txt", "content   \n"),
+        ],
+    );
+
+    let report = run_in_test_directory(&directory, &["--check", "--sorted"]);
+    let details: Vec<Option<String>> = report
+        .cleaned_files
+        .iter()
+        .map(|(_, file_stats)| format_end_of_file_delta(file_stats))
+        .collect();
+
+    assert_eq!(
+        details,
+        vec![
+            Some("has 3 trailing blank lines, expected 0".to_string()),
+            Some("missing final newline".to_string()),
+            Some("has 1 trailing blank line, expected 0".to_string()),
+            None,
+        ]
+    );
+
+    let mut output = Vec::new();
+    write_report(&mut output, &report, false).unwrap();
+
+    assert!(String::from_utf8(output)
+        .unwrap()
+        .contains("bytes saved  (missing final newline)\n"));
+
+    let report = run_in_test_directory(
+        &directory,
+        &[
+            "--check",
+            "--keep-one-trailing-blank-for",
+            "blank_lines.txt",
+        ],
+    );
+    let (_, file_stats) = report
+        .cleaned_files
+        .iter()
+        .find(|(file_path, _)| file_path.ends_with("blank_lines.txt"))
+        .unwrap();
+
+    assert_eq!(
+        format_end_of_file_delta(file_stats),
+        Some("has 3 trailing blank lines, expected 1".to_string())
+    );
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn report_empty_prints_every_main_header_on_a_no_op_run() {
+    let directory = create_test_directory("report-empty", &[("clean.txt", "clean\n")]);
+
+    let mut output = Vec::new();
+    write_report(
+        &mut output,
+        &run_in_test_directory(&directory, &["--report-empty"]),
+        false,
+    )
+    .unwrap();
+
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "Cleaned Files:\nSkipped Files:\nWalkdir Errors:\n"
+    );
+
+    let mut output = Vec::new();
+    write_report(&mut output, &run_in_test_directory(&directory, &[]), false).unwrap();
+
+    assert!(output.is_empty());
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn enforce_final_newline_in_only_flags_files_under_the_given_directories() {
+    let directory = create_test_directory(
+        "enforce-final-newline",
+        &[
+            ("src/lib.rs", "pub fn run() {}"),
+            ("src/nested/mod.rs", "mod nested;\n"),
+            ("vendor/lib.rs", "pub fn vendored() {}"),
+        ],
+    );
+
+    let directory_string = directory.display().to_string();
+    let args = LinemanArgs::from_iter([
+        "lineman",
+        "--path",
+        &directory_string,
+        "--enforce-final-newline-in",
+        "./src",
+    ]);
+    let report = run(&args, &AtomicBool::new(false), &mut io::sink()).unwrap();
+
+    assert_eq!(failure_exit_code(&args, &report), Some(1));
+    assert_eq!(
+        report.final_newline_violation_file_paths,
+        vec![directory.join("src/lib.rs")]
+    );
+    assert_eq!(
+        fs::read_to_string(directory.join("vendor/lib.rs")).unwrap(),
+        "pub fn vendored() {}\n"
+    );
+    assert_eq!(
+        fs::read_to_string(directory.join("src/lib.rs")).unwrap(),
+        "pub fn run() {}\n"
+    );
+
+    let report = run(&args, &AtomicBool::new(false), &mut io::sink()).unwrap();
+
+    assert_eq!(failure_exit_code(&args, &report), None);
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn hook_message_is_one_line_with_the_fix_command() {
+    let directory = create_test_directory(
+        "hook-message",
+        &[("a.rs", "a   \n"), ("b.rs", "b\t\n"), ("c.rs", "c\n")],
+    );
+
+    let report = run_in_test_directory(&directory, &["--check", "--hook-message"]);
+    let command_line_args: Vec<String> = [
+        "--path",
+        ".",
+        "--check",
+        "--include",
+        "src/**/*.rs",
+        "--hook-message",
+    ]
+    .iter()
+    .map(|arg| arg.to_string())
+    .collect();
+
+    assert!(check_failed(&report));
+    assert_eq!(
+        format_hook_message(&report, &command_line_args),
+        Some(
+            "lineman: 2 files need cleaning; run `lineman --path . --include 'src/**/*.rs'` to fix"
+                .to_string()
+        )
+    );
+
+    run_in_test_directory(&directory, &[]);
+    let report = run_in_test_directory(&directory, &["--check", "--hook-message"]);
+
+    assert!(!check_failed(&report));
+    assert_eq!(format_hook_message(&report, &command_line_args), None);
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn input_list_json_cleans_listed_paths_and_reports_missing_ones() {
+    let directory = create_test_directory(
+        "input-list-json",
+        &[
+            ("listed.txt", "listed   \n"),
+            ("nested/listed.txt", "nested   \n"),
+            ("unlisted.txt", "unlisted   \n"),
+        ],
+    );
+    let input_list_path = directory.join("input.json");
+    let listed_paths = [
+        directory.join("listed.txt"),
+        directory.join("nested/listed.txt"),
+    ];
+    let missing_path = directory.join("missing.txt");
+
+    fs::write(
+        &input_list_path,
+        format!(
+            "[{}, {}, {}]",
+            json::quote(&listed_paths[0].display().to_string()),
+            json::quote(&listed_paths[1].display().to_string()),
+            json::quote(&missing_path.display().to_string()),
+        ),
+    )
+    .unwrap();
+
+    let input_list_string = input_list_path.display().to_string();
+    let report = run_in_test_directory(&directory, &["--input-list-json", &input_list_string]);
+
+    assert_eq!(cleaned_file_paths(&report), listed_paths.to_vec());
+    assert_eq!(report.missing_file_paths, vec![missing_path]);
+    assert_eq!(
+        fs::read_to_string(directory.join("unlisted.txt")).unwrap(),
+        "unlisted   \n"
+    );
+
+    fs::write(&input_list_path, "{\"paths\": []}").unwrap();
+
+    assert!(read_input_list_json(&input_list_path).is_err());
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn positional_files_are_processed_without_walking_the_root() {
+    let directory = create_test_directory(
+        "positional-files",
+        &[
+            ("first.rs", "fn first() {}   \n"),
+            ("second.py", "def second(): pass   \n"),
+            ("third.rs", "fn third() {}   \n"),
+        ],
+    );
+    let first_path = directory.join("first.rs").display().to_string();
+    let second_path = directory.join("second.py").display().to_string();
+
+    let run_with = |args: &[&str]| {
+        run(
+            &LinemanArgs::from_iter(std::iter::once("lineman").chain(args.iter().copied())),
+            &AtomicBool::new(false),
+            &mut io::sink(),
+        )
+    };
+
+    let report = run_with(&["--extensions", "rs", &first_path, &second_path]).unwrap();
+
+    assert_eq!(
+        cleaned_file_paths(&report),
+        vec![directory.join("first.rs")]
+    );
+    assert_eq!(report.totals.files_excluded, 1);
+
+    let report = run_with(&["--sorted", &first_path, &second_path]).unwrap();
+
+    assert_eq!(
+        cleaned_file_paths(&report),
+        vec![directory.join("second.py")]
+    );
+    assert_eq!(
+        fs::read_to_string(directory.join("third.rs")).unwrap(),
+        "fn third() {}   \n"
+    );
+    assert!(matches!(
+        run_with(&[&directory.display().to_string()]),
+        Err(LinemanApplicationError::InvalidFilePath(_))
+    ));
+
+    let args = LinemanArgs::from_iter([
+        "lineman",
+        "--exclude-extensions",
+        "py",
+        "--no-final-newline-extensions",
+        "txt",
+        &first_path,
+    ]);
+
+    assert_eq!(args.files, [PathBuf::from(&first_path)]);
+
+    let args = LinemanArgs::from_iter([
+        "lineman",
+        "--include",
+        "*.rs",
+        "--exclude",
+        "*.md",
+        "--dir-glob",
+        "src",
+        "--keep-one-trailing-blank-for",
+        "*.csv",
+        "--enforce-final-newline-in",
+        "src",
+        "--comment-prefixes",
+        "//",
+        &first_path,
+        &second_path,
+    ]);
+
+    assert_eq!(
+        args.files,
+        [PathBuf::from(&first_path), PathBuf::from(&second_path)]
+    );
+    assert_eq!(args.include.len(), 1);
+    assert_eq!(args.enforce_final_newline_in, [PathBuf::from("src")]);
+
+    let args = LinemanArgs::from_iter(["lineman", "--merge-reports", "a.jsonl", &first_path]);
+
+    assert_eq!(args.merge_reports, [PathBuf::from("a.jsonl")]);
+    assert_eq!(args.files, [PathBuf::from(&first_path)]);
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn files_over_the_memory_budget_are_streamed() {
+    let large_content: String = (0..20).map(|index| format!("line {index}   \n")).collect();
+    let directory = create_test_directory(
+        "memory-budget",
+        &[
+            ("large.txt", &format!("{}\n\n", large_content)),
+            ("long_line.txt", &format!("{}\n", "x".repeat(100))),
+            ("small.txt", "small   \n"),
+        ],
+    );
+
+    let report = run_in_test_directory(&directory, &["--memory-budget", "64"]);
+    let streamed = |file_name: &str| {
+        report
+            .cleaned_files
+            .iter()
+            .find(|(file_path, _)| file_path.ends_with(file_name))
+            .map(|(_, file_stats)| file_stats.streamed)
+    };
+
+    assert_eq!(streamed("large.txt"), Some(true));
+    assert_eq!(streamed("small.txt"), Some(false));
+    assert_eq!(
+        report.over_memory_budget_file_paths,
+        vec![directory.join("long_line.txt")]
+    );
+    assert_eq!(
+        fs::read_to_string(directory.join("large.txt")).unwrap(),
+        large_content.replace("   \n", "\n")
+    );
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn relative_paths_from_disjoint_roots_stay_distinct() {
+    let directory = create_test_directory(
+        "relative_roots",
+        &[
+            ("a/src/main.rs", "fn main() {}   \n"),
+            ("b/src/main.rs", "fn main() {}   \n"),
+        ],
+    );
+    let second_root_string = directory.join("b/src").display().to_string();
+
+    let mut cleaned_paths = cleaned_file_paths(&run_in_test_directory(
+        &directory.join("a/src"),
+        &["--path", &second_root_string, "--relative", "--check"],
+    ));
+    cleaned_paths.sort();
+
+    assert_eq!(
+        cleaned_paths,
+        [
+            PathBuf::from("a/src/main.rs"),
+            PathBuf::from("b/src/main.rs")
+        ]
+    );
+    assert_eq!(
+        distinct_root_paths(&[
+            directory.join("a"),
+            directory.join("a/src"),
+            directory.join("a"),
+        ]),
+        [directory.join("a")]
+    );
+}
+
+#[test]
+fn expect_line_ending_reports_crlf_files_without_changing_them() {
+    let crlf_content = "first line\r\nsecond line   \r\nthird line\n";
+    let directory = create_test_directory(
+        "expect_line_ending",
+        &[("crlf.txt", crlf_content), ("lf.txt", "first line   \n")],
+    );
+
+    let report = run_in_test_directory(&directory, &["--expect-line-ending", "lf"]);
+
+    assert_eq!(
+        report.unexpected_line_ending_files,
+        [(
+            directory.join("crlf.txt"),
+            LineEndingCounts { lf: 1, crlf: 2 }
+        )]
+    );
+    assert!(report.cleaned_files.is_empty());
+    assert_eq!(report.totals.files_already_clean, 1);
+    assert_eq!(
+        fs::read_to_string(directory.join("crlf.txt")).unwrap(),
+        crlf_content
+    );
+    assert_eq!(
+        fs::read_to_string(directory.join("lf.txt")).unwrap(),
+        "first line   \n"
+    );
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn tracked_only_skips_untracked_files() {
+    let directory = create_test_directory(
+        "tracked_only",
+        &[
+            ("tracked.txt", "tracked   \n"),
+            ("scratch.txt", "scratch   \n"),
+        ],
+    );
+
+    for git_args in [&["init", "--quiet"][..], &["add", "tracked.txt"]] {
+        let status = Command::new("git")
+            .arg("-C")
+            .arg(&directory)
+            .args(git_args)
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+
+    let report = run_in_test_directory(&directory, &["--tracked-only", "--extensions", "txt"]);
+
+    assert_eq!(cleaned_file_paths(&report), [directory.join("tracked.txt")]);
+    assert_eq!(report.untracked_file_paths, [directory.join("scratch.txt")]);
+    assert_eq!(
+        fs::read_to_string(directory.join("scratch.txt")).unwrap(),
+        "scratch   \n"
+    );
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn tracked_only_finds_listed_files_however_their_paths_are_written() {
+    let directory = create_test_directory(
+        "tracked_only_listed",
+        &[
+            ("tracked.txt", "tracked   \n"),
+            ("listed.txt", "listed   \n"),
+            ("scratch.txt", "scratch   \n"),
+        ],
+    );
+
+    for git_args in [
+        &["init", "--quiet"][..],
+        &["add", "tracked.txt", "listed.txt"],
+    ] {
+        let status = Command::new("git")
+            .arg("-C")
+            .arg(&directory)
+            .args(git_args)
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+
+    fs::create_dir(directory.join("nested")).unwrap();
+
+    // The file is written differently from the root, as with `-p .` and a relative file
+    let root_string = directory.join(".").display().to_string();
+    let tracked_string = directory
+        .join("nested")
+        .join("..")
+        .join("tracked.txt")
+        .display()
+        .to_string();
+    let scratch_string = directory.join("scratch.txt").display().to_string();
+    let report = run(
+        &LinemanArgs::from_iter([
+            "lineman",
+            "--path",
+            &root_string,
+            "--tracked-only",
+            "--check",
+            &tracked_string,
+            &scratch_string,
+        ]),
+        &AtomicBool::new(false),
+        &mut io::sink(),
+    )
+    .unwrap();
+
+    assert_eq!(report.totals.files_cleaned, 1);
+    assert_eq!(report.untracked_file_paths, [directory.join("scratch.txt")]);
+
+    let input_list_path = directory.join("input-list.json");
+    fs::write(
+        &input_list_path,
+        format!(
+            "[{}]",
+            json::quote(
+                &directory
+                    .join("nested")
+                    .join("..")
+                    .join("listed.txt")
+                    .display()
+                    .to_string()
+            )
+        ),
+    )
+    .unwrap();
+
+    let input_list_string = input_list_path.display().to_string();
+    let report = run_in_test_directory(
+        &directory,
+        &["--tracked-only", "--input-list-json", &input_list_string],
+    );
+
+    assert_eq!(report.totals.files_cleaned, 1);
+    assert!(report.untracked_file_paths.is_empty());
+    assert_eq!(
+        fs::read_to_string(directory.join("listed.txt")).unwrap(),
+        "listed\n"
+    );
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn blame_counts_trailing_whitespace_lines_per_author() {
+    let directory = create_test_directory("blame", &[("notes.txt", "first   \nsecond\nthird\t\n")]);
+
+    let git = |git_args: &[&str]| {
+        let status = Command::new("git")
+            .arg("-C")
+            .arg(&directory)
+            .args(["-c", "commit.gpgsign=false"])
+            .args(git_args)
+            .status()
+            .unwrap();
+        assert!(status.success());
+    };
+
+    git(&["init", "--quiet"]);
+    git(&["add", "notes.txt"]);
+    git(&[
+        "-c",
+        "user.name=Alice",
+        "-c",
+        "user.email=alice@example.com",
+        "commit",
+        "--quiet",
+        "-m",
+        "Add notes",
+    ]);
+
+    fs::write(
+        directory.join("notes.txt"),
+        "first   \nsecond  \nthird\t\nfourth \n",
+    )
+    .unwrap();
+
+    git(&["add", "notes.txt"]);
+    git(&[
+        "-c",
+        "user.name=Bob",
+        "-c",
+        "user.email=bob@example.com",
+        "commit",
+        "--quiet",
+        "-m",
+        "Edit notes",
+    ]);
+
+    let report = run_in_test_directory(&directory, &["--blame", "--extensions", "txt"]);
+    let mut output = Vec::new();
+    write_report(&mut output, &report, false).unwrap();
+
+    assert!(String::from_utf8(output)
+        .unwrap()
+        .starts_with("Files That Would Be Cleaned:\n"));
+    assert_eq!(
+        report
+            .trailing_whitespace_authors
+            .into_iter()
+            .collect::<Vec<_>>(),
+        [
+            ("Alice <alice@example.com>".to_string(), 2),
+            ("Bob <bob@example.com>".to_string(), 2),
+        ]
+    );
+    assert_eq!(
+        fs::read_to_string(directory.join("notes.txt")).unwrap(),
+        "first   \nsecond  \nthird\t\nfourth \n"
+    );
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn serve_answers_sequential_requests() {
+    let requests = concat!(
+        "{\"content\": \"fn main() {}   \\n\\n\", \"filename\": \"src/main.rs\"}\n",
+        "{\"content\": \"already clean\\n\", \"filename\": \"notes.txt\"}\n",
+        "{\"content\": \"excluded   \", \"filename\": \"notes.md\"}\n",
+    );
+    let args = LinemanArgs::from_iter(["lineman", "--serve", "--extensions", "rs,txt"]);
+    let mut output = Vec::new();
+
+    serve(&args, requests.as_bytes(), &mut output).unwrap();
+
+    let responses: Vec<json::Value> = String::from_utf8(output)
+        .unwrap()
+        .lines()
+        .map(|line| json::parse(line).unwrap())
+        .collect();
+    let response_fields = |response: &json::Value| {
+        (
+            response.get("cleaned").cloned(),
+            response.get("changed").cloned(),
+        )
+    };
+
+    assert_eq!(
+        responses.iter().map(response_fields).collect::<Vec<_>>(),
+        [
+            (
+                Some(json::Value::String("fn main() {}\n".to_string())),
+                Some(json::Value::Bool(true))
+            ),
+            (
+                Some(json::Value::String("already clean\n".to_string())),
+                Some(json::Value::Bool(false))
+            ),
+            (
+                Some(json::Value::String("excluded   ".to_string())),
+                Some(json::Value::Bool(false))
+            ),
+        ]
+    );
+}
+
+#[test]
+fn serve_answers_deeply_nested_requests_with_an_error_and_keeps_serving() {
+    let requests = format!(
+        "{}\n{{\"content\": \"x   \", \"filename\": \"main.rs\"}}\n",
+        "[".repeat(200_000)
+    );
+    let args = LinemanArgs::from_iter(["lineman", "--serve"]);
+    let mut output = Vec::new();
+
+    serve(&args, requests.as_bytes(), &mut output).unwrap();
+
+    let responses: Vec<json::Value> = String::from_utf8(output)
+        .unwrap()
+        .lines()
+        .map(|line| json::parse(line).unwrap())
+        .collect();
+
+    assert_eq!(responses.len(), 2);
+    assert!(responses[0].get("error").is_some());
+    assert_eq!(
+        responses[1].get("cleaned"),
+        Some(&json::Value::String("x\n".to_string()))
+    );
+}
+
+#[test]
+fn stdin_is_cleaned_to_stdout_whatever_the_extension_filters() {
+    let args = LinemanArgs::from_iter([
+        "lineman",
+        "--path",
+        "-",
+        "--extensions",
+        "rs",
+        "--line-ending",
+        "crlf",
+    ]);
+    let mut output = Vec::new();
+
+    assert!(reads_stdin(&args));
+
+    clean_stdin(
+        &args,
+        "first   \nsecond\r\n\n\nlast".as_bytes(),
+        &mut output,
+    )
+    .unwrap();
+
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "first\r\nsecond\r\n\r\n\r\nlast\r\n"
+    );
+
+    let args = LinemanArgs::from_iter(["lineman", "--stdin"]);
+    let mut output = Vec::new();
+
+    assert!(reads_stdin(&args));
+
+    clean_stdin(&args, "a  \n\n\n".as_bytes(), &mut output).unwrap();
+
+    assert_eq!(String::from_utf8(output).unwrap(), "a\n");
+    assert!(!reads_stdin(&LinemanArgs::from_iter([
+        "lineman", "--path", "src"
+    ])));
+}
+
+#[test]
+fn max_line_bytes_skips_files_with_an_oversized_line() {
+    let oversized_line = "x".repeat(3 * STREAM_BUFFER_SIZE) + "   ";
+    let directory = create_test_directory(
+        "max_line_bytes",
+        &[
+            ("minified.js", &oversized_line),
+            ("short.js", "let value = 1;   \nlet other = 2;\n"),
+        ],
+    );
+
+    let report = run_in_test_directory(&directory, &["--max-line-bytes", "1024"]);
+
+    assert_eq!(
+        report.line_too_long_file_paths,
+        [directory.join("minified.js")]
+    );
+    assert_eq!(cleaned_file_paths(&report), [directory.join("short.js")]);
+    assert_eq!(
+        fs::read_to_string(directory.join("minified.js")).unwrap(),
+        oversized_line
+    );
+    assert!(!has_line_longer_than(&directory.join("short.js"), 14).unwrap());
+    assert!(has_line_longer_than(&directory.join("short.js"), 13).unwrap());
+
+    fs::write(
+        directory.join("crlf.js"),
+        "let value = 1;\r\nlet other = 2;\r\n",
+    )
+    .unwrap();
+
+    assert!(!has_line_longer_than(&directory.join("crlf.js"), 14).unwrap());
+    assert!(has_line_longer_than(&directory.join("crlf.js"), 13).unwrap());
+
+    fs::write(directory.join("lone.js"), "let value = 1;\r").unwrap();
+
+    assert!(has_line_longer_than(&directory.join("lone.js"), 14).unwrap());
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn merge_reports_deduplicates_paths_and_sums_counts() {
+    let first_report = concat!(
+        "{\"path\":\"a.rs\",\"outcome\":\"cleaned\",\"lines_changed\":2,\"bytes_saved\":6}\n",
+        "{\"path\":\"b.rs\",\"outcome\":\"already_clean\",\"lines_changed\":0,\"bytes_saved\":0}\n",
+        "{\"summary\":{\"files_cleaned\":1,\"lines_changed\":2,\"bytes_saved\":6,",
+        "\"files_already_clean\":1,\"walk_errors\":0,\"interrupted\":false}}\n",
+    );
+    let second_report = concat!(
+        "{\"path\":\"b.rs\",\"outcome\":\"cleaned\",\"lines_changed\":1,\"bytes_saved\":3}\n",
+        "{\"path\":\"c.rs\",\"outcome\":\"cleaned\",\"lines_changed\":1,\"bytes_saved\":-1}\n",
+        "{\"walk_error\":\"IO error for operation on d: Permission denied\"}\n",
+        "{\"summary\":{\"files_cleaned\":2,\"lines_changed\":2,\"bytes_saved\":2,",
+        "\"files_already_clean\":0,\"walk_errors\":1,\"interrupted\":true}}\n",
+    );
+
+    let merged_report =
+        merge_reports(&[first_report.to_string(), second_report.to_string()]).unwrap();
+
+    let expected_summary = concat!(
+        "{\"summary\":{\"files_cleaned\":3,\"lines_changed\":4,\"bytes_saved\":8,",
+        "\"files_already_clean\":0,\"walk_errors\":1,\"interrupted\":true"
+    );
+
+    let merged_lines: Vec<&str> = merged_report.lines().collect();
+
+    assert_eq!(
+        merged_lines[..4],
+        [
+            "{\"path\":\"a.rs\",\"outcome\":\"cleaned\",\"lines_changed\":2,\"bytes_saved\":6}",
+            "{\"path\":\"b.rs\",\"outcome\":\"cleaned\",\"lines_changed\":1,\"bytes_saved\":3}",
+            "{\"path\":\"c.rs\",\"outcome\":\"cleaned\",\"lines_changed\":1,\"bytes_saved\":-1}",
+            "{\"walk_error\":\"IO error for operation on d: Permission denied\"}",
+        ]
+    );
+    assert!(merged_lines[4].starts_with(expected_summary));
+    assert!(merge_reports(&["not json".to_string()]).is_err());
+}
+
+#[test]
+fn presets_expand_to_their_documented_settings() {
+    let fixture = "first   \r\n\n\n\nsecond\t\nlast  \n\n\n";
+    let directory = create_test_directory(
+        "presets",
+        &[("strict.txt", fixture), ("minimal.txt", fixture)],
+    );
+
+    for (preset, expected_content) in [
+        ("strict", "first\n\nsecond\nlast\n"),
+        ("minimal", "first\n\n\n\nsecond\nlast\n\n\n"),
+    ] {
+        let file_name = format!("{}.txt", preset);
+        run_in_test_directory(&directory, &["--preset", preset, "--include", &file_name]);
+
+        assert_eq!(
+            fs::read_to_string(directory.join(&file_name)).unwrap(),
+            expected_content
+        );
+    }
+
+    let strict_options = Preset::Strict.clean_options();
+    let (output_lines, applied_transforms) = clean_lines(
+        &["kept  \"\r\n", "\r\n", "\r\n", "end\n"],
+        &CleanOptions {
+            trim_trailing_whitespace: false,
+            ..strict_options
+        },
+    );
+
+    assert_eq!(output_lines, ["kept  \"\n", "\n", "end\n"]);
+    assert!(applied_transforms.line_endings && applied_transforms.blank_line_runs);
+
+    let args = LinemanArgs::from_iter(["lineman", "--serve", "--preset", "strict", "-d"]);
+    assert!(!root_clean_options(&args).normalize_eof_newlines);
+    assert_eq!(
+        root_clean_options(&args).max_consecutive_blank_lines,
+        Some(1)
+    );
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn health_is_the_share_of_processed_files_already_clean() {
+    let directory = create_test_directory(
+        "health",
+        &[
+            ("clean_1.txt", "clean\n"),
+            ("clean_2.txt", "clean\n"),
+            ("clean_3.txt", "clean\n"),
+            ("dirty.txt", "dirty   \n"),
+            ("skipped.md", "excluded   \n"),
+        ],
+    );
+
+    let report = run_in_test_directory(&directory, &["--health", "--extensions", "txt"]);
+
+    assert_eq!(health_percentage(&report.totals), Some(75));
+    assert_eq!(
+        format_health(&report.totals),
+        "75% of processed files were already clean"
+    );
+    assert!(format_json_summary(&report).ends_with(",\"health_percent\":75}}"));
+    assert_eq!(health_percentage(&ReportTotals::default()), None);
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn skip_locked_skips_files_locked_by_another_holder() {
+    use std::os::unix::io::AsRawFd;
+
+    let directory = create_test_directory(
+        "skip_locked",
+        &[("open.txt", "open   \n"), ("closed.txt", "closed   \n")],
+    );
+
+    // `flock` locks belong to the open file, so this one conflicts with lineman's own open
+    let editor_file = File::open(directory.join("open.txt")).unwrap();
+
+    // SAFETY: `editor_file` keeps the descriptor open until it is dropped below
+    assert_eq!(
+        unsafe { libc::flock(editor_file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) },
+        0
+    );
+
+    let report = run_in_test_directory(&directory, &["--skip-locked"]);
+
+    assert_eq!(report.in_use_file_paths, [directory.join("open.txt")]);
+    assert_eq!(cleaned_file_paths(&report), [directory.join("closed.txt")]);
+    assert_eq!(
+        fs::read_to_string(directory.join("open.txt")).unwrap(),
+        "open   \n"
+    );
+
+    drop(editor_file);
+    let report = run_in_test_directory(&directory, &["--skip-locked"]);
+
+    assert!(report.in_use_file_paths.is_empty());
+    assert_eq!(cleaned_file_paths(&report), [directory.join("open.txt")]);
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn rdjson_reports_each_whitespace_issue_without_cleaning() {
+    let directory = create_test_directory(
+        "rdjson",
+        &[
+            ("dirty.rs", "fn a() {}   \nfn b() {}"),
+            ("clean.rs", "fn c() {}\n"),
+        ],
+    );
+
+    let report = run_in_test_directory(&directory, &["--format", "rdjson"]);
+    let rdjson = json::parse(&format_rdjson(&report)).unwrap();
+
+    let diagnostic = |index: usize| match rdjson.get("diagnostics") {
+        Some(json::Value::Array(diagnostics)) => diagnostics[index].to_string(),
+        _ => panic!("{}", rdjson),
+    };
+
+    let dirty_path = json::quote(&directory.join("dirty.rs").display().to_string());
+
+    assert_eq!(
+        rdjson.get("source").and_then(|source| source.get("name")),
+        Some(&json::Value::String("lineman".to_string()))
+    );
+    assert_eq!(
+        diagnostic(0),
+        format!(
+            concat!(
+                "{{\"message\":\"Trailing whitespace\",",
+                "\"location\":{{\"path\":{},\"range\":{{\"start\":{{\"line\":1,\"column\":10}},",
+                "\"end\":{{\"line\":1,\"column\":13}}}}}},\"severity\":\"WARNING\",",
+                "\"suggestions\":[{{\"range\":{{\"start\":{{\"line\":1,\"column\":10}},",
+                "\"end\":{{\"line\":1,\"column\":13}}}},\"text\":\"\"}}]}}",
+            ),
+            dirty_path
+        )
+    );
+    assert!(diagnostic(1).contains("\"message\":\"Missing final newline\""));
+    assert!(diagnostic(1).contains(concat!(
+        "\"range\":{\"start\":{\"line\":2,\"column\":10},\"end\":{\"line\":2,\"column\":10}},",
+        "\"text\":\"\\n\"",
+    )));
+    assert_eq!(
+        fs::read_to_string(directory.join("dirty.rs")).unwrap(),
+        "fn a() {}   \nfn b() {}"
+    );
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn json_report_lists_cleaned_and_skipped_files_with_summary_counts() {
+    let directory = create_test_directory(
+        "json-report",
+        &[
+            ("dirty.rs", "fn dirty() {}   \n"),
+            ("clean.rs", "fn clean() {}\n"),
+            ("latin1.rs", ""),
+        ],
+    );
+    fs::write(directory.join("latin1.rs"), b"caf\xe9  \n").unwrap();
+
+    let report = run_in_test_directory(&directory, &["--format", "json"]);
+    let json_report = json::parse(&format_json_report(&report)).unwrap();
+
+    let path_value =
+        |file_name: &str| json::Value::String(directory.join(file_name).display().to_string());
+
+    assert_eq!(
+        json_report.get("cleaned"),
+        Some(&json::Value::Array(vec![json::Value::Object(vec![
+            ("path".to_string(), path_value("dirty.rs")),
+            ("lines_changed".to_string(), json::Value::Number(1.0)),
+            ("bytes_saved".to_string(), json::Value::Number(3.0)),
+        ])]))
+    );
+    assert_eq!(
+        json_report.get("skipped"),
+        Some(&json::Value::Array(vec![json::Value::Object(vec![
+            ("path".to_string(), path_value("latin1.rs")),
+            (
+                "reason".to_string(),
+                json::Value::String("file_not_opened".to_string())
+            ),
+        ])]))
+    );
+    assert_eq!(
+        json_report.get("walk_errors"),
+        Some(&json::Value::Array(Vec::new()))
+    );
+    assert_eq!(
+        json_report
+            .get("summary")
+            .and_then(|summary| summary.get("files_already_clean")),
+        Some(&json::Value::Number(1.0))
+    );
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn diff_heads_the_report_with_the_files_that_would_be_cleaned() {
+    let directory = create_test_directory("diff_report", &[("dirty.txt", "dirty   \n")]);
+
+    let report = run_in_test_directory(&directory, &["--diff"]);
+    let mut output = Vec::new();
+    write_report(&mut output, &report, false).unwrap();
 
-    let (output_lines, lines_have_changes) = clean_lines(&input_lines, false);
+    assert_eq!(cleaned_file_paths(&report), [directory.join("dirty.txt")]);
+    assert!(String::from_utf8(output)
+        .unwrap()
+        .starts_with("Files That Would Be Cleaned:\n"));
+    assert_eq!(
+        fs::read_to_string(directory.join("dirty.txt")).unwrap(),
+        "dirty   \n"
+    );
 
-    assert_eq!(expected_output_lines.to_vec(), output_lines);
-    assert_eq!(lines_have_changes, false);
+    fs::remove_dir_all(directory).unwrap();
 }
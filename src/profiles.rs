@@ -0,0 +1,102 @@
+// Built-in per-language defaults, applied with `--language-profiles` to files by their extension
+// A profile only fills in what nothing else decided, so explicit flags and `.lineman.toml` configs
+// always win over it
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IndentStyle {
+    // Leading tabs are left alone, as the language expects or requires them
+    Tabs,
+    // Leading tabs are expanded to this many columns of spaces
+    Spaces(usize),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct LanguageProfile {
+    pub extensions: &'static [&'static str],
+    pub indent_style: IndentStyle,
+    pub final_newline: bool,
+}
+
+static LANGUAGE_PROFILES: [LanguageProfile; 10] = [
+    LanguageProfile {
+        extensions: &["go"],
+        indent_style: IndentStyle::Tabs,
+        final_newline: true,
+    },
+    LanguageProfile {
+        extensions: &["mk", "mak"],
+        indent_style: IndentStyle::Tabs,
+        final_newline: true,
+    },
+    LanguageProfile {
+        extensions: &["tsv"],
+        indent_style: IndentStyle::Tabs,
+        final_newline: true,
+    },
+    LanguageProfile {
+        extensions: &["py", "pyi"],
+        indent_style: IndentStyle::Spaces(4),
+        final_newline: true,
+    },
+    LanguageProfile {
+        extensions: &["rs"],
+        indent_style: IndentStyle::Spaces(4),
+        final_newline: true,
+    },
+    LanguageProfile {
+        extensions: &["java", "kt", "swift", "c", "h", "cpp", "hpp", "cs"],
+        indent_style: IndentStyle::Spaces(4),
+        final_newline: true,
+    },
+    LanguageProfile {
+        extensions: &["js", "jsx", "ts", "tsx", "json"],
+        indent_style: IndentStyle::Spaces(2),
+        final_newline: true,
+    },
+    LanguageProfile {
+        extensions: &["yml", "yaml"],
+        indent_style: IndentStyle::Spaces(2),
+        final_newline: true,
+    },
+    LanguageProfile {
+        extensions: &["rb", "lua"],
+        indent_style: IndentStyle::Spaces(2),
+        final_newline: true,
+    },
+    LanguageProfile {
+        extensions: &["toml", "ini", "cfg"],
+        indent_style: IndentStyle::Spaces(4),
+        final_newline: true,
+    },
+];
+
+pub fn profile_for(extension: &str) -> Option<&'static LanguageProfile> {
+    LANGUAGE_PROFILES
+        .iter()
+        .find(|profile| profile.extensions.contains(&extension))
+}
+
+#[test]
+fn profiles_are_looked_up_by_extension() {
+    assert_eq!(profile_for("go").unwrap().indent_style, IndentStyle::Tabs);
+    assert_eq!(profile_for("mk").unwrap().indent_style, IndentStyle::Tabs);
+    assert_eq!(
+        profile_for("py").unwrap().indent_style,
+        IndentStyle::Spaces(4)
+    );
+    assert!(profile_for("md").is_none());
+}
+
+#[test]
+fn each_extension_has_at_most_one_profile() {
+    let mut extensions: Vec<&str> = LANGUAGE_PROFILES
+        .iter()
+        .flat_map(|profile| profile.extensions.iter().copied())
+        .collect();
+    let extension_count = extensions.len();
+
+    extensions.sort_unstable();
+    extensions.dedup();
+
+    assert_eq!(extensions.len(), extension_count);
+}